@@ -0,0 +1,129 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::{fmt::Write, fs, path::Path, process::Command};
+use submerger::{
+    apply_sub_changes, load_sub, merge, LoadOptions, MergeOptions, SubPosition, TrackOptions,
+};
+
+/// Number of cues in each of the generated subtitle files of the command benchmarks
+const CUES: u64 = 10_000;
+
+/// Number of cues in the fixture of the library benchmarks, about that of a feature film with
+/// dense dialogue
+const FIXTURE_CUES: u64 = 5_000;
+
+/// Number of directories, each with a pair of fixtures, searched by the recursive benchmark
+const RECURSIVE_DIRS: usize = 8;
+
+fn timestamp(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Write an SRT file of `cues` two line cues, the first of some of them in italics
+fn write_srt(path: &Path, text: &str, cues: u64) {
+    let mut srt = String::new();
+    for i in 0..cues {
+        let start = i * 2000;
+        let line = match i % 4 {
+            0 => format!("<i>{} line {}</i>", text, i),
+            _ => format!("{} line {}", text, i),
+        };
+        writeln!(
+            srt,
+            "{}\n{} --> {}\n{}\nsecond line\n",
+            i + 1,
+            timestamp(start),
+            timestamp(start + 1500),
+            line
+        )
+        .unwrap();
+    }
+    fs::write(path, srt).unwrap();
+}
+
+fn merge_large_srt(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let sub1 = dir.path().join("sub1.srt");
+    let sub2 = dir.path().join("sub2.srt");
+    let out = dir.path().join("out.srt");
+    write_srt(&sub1, "first", CUES);
+    write_srt(&sub2, "second", CUES);
+
+    c.bench_function("simple 2x10k cues", |b| {
+        b.iter(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_submerger"))
+                .arg("simple")
+                .args([&sub1, &sub2, &out])
+                .args(["--color", "#aaaaaa", "--log-level", "error"])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        })
+    });
+}
+
+fn library(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let sub1 = dir.path().join("sub1.srt");
+    let sub2 = dir.path().join("sub2.srt");
+    write_srt(&sub1, "first", FIXTURE_CUES);
+    write_srt(&sub2, "second", FIXTURE_CUES);
+    let load = |path| load_sub(path, &LoadOptions::default()).unwrap();
+
+    c.bench_function("load_sub 5k cues", |b| b.iter(|| load(&sub1)));
+
+    let opts = TrackOptions {
+        color: Some("#fabd2f".to_owned()),
+        position: Some(SubPosition::TopCenter),
+        bold: true,
+        ..Default::default()
+    };
+    let srt = load(&sub2);
+    c.bench_function("apply_sub_changes 5k cues", |b| {
+        b.iter_batched_ref(
+            || srt.clone(),
+            |srt| apply_sub_changes(srt, &opts),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let (srt1, mut srt2) = (load(&sub1), load(&sub2));
+    apply_sub_changes(&mut srt2, &opts);
+    c.bench_function("merge 2x5k cues", |b| {
+        b.iter_batched(
+            || (srt1.clone(), srt2.clone()),
+            |(srt1, srt2)| merge(srt1, srt2, &MergeOptions::default()),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn recursive(c: &mut Criterion) {
+    let root = tempfile::tempdir().unwrap();
+    for i in 0..RECURSIVE_DIRS {
+        let dir = root.path().join(format!("Season {}", i + 1));
+        fs::create_dir(&dir).unwrap();
+        write_srt(&dir.join("episode.en.srt"), "first", FIXTURE_CUES);
+        write_srt(&dir.join("episode.ja.srt"), "second", FIXTURE_CUES);
+    }
+
+    c.bench_function("recursive 8x2x5k cues", |b| {
+        b.iter(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_submerger"))
+                .args(["recursive", "en", "ja"])
+                .arg(root.path())
+                .args(["--color", "#aaaaaa", "--log-level", "error"])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        })
+    });
+}
+
+criterion_group!(benches, merge_large_srt, library, recursive);
+criterion_main!(benches);
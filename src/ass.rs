@@ -0,0 +1,556 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use regex::Regex;
+use std::{collections::BTreeMap, fmt::Write, sync::LazyLock, time::Duration};
+use subtp::srt::{SrtSubtitle, SubRip};
+
+use crate::{color_rgb, split_box, split_position, SubPosition};
+
+static KARAOKE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\(k[fo]?|K)\d+").unwrap());
+
+/// A `Dialogue` event of an ASS/SSA file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssEvent {
+    /// When the event is first shown
+    pub start: Duration,
+    /// When the event is no longer shown
+    pub end: Duration,
+    /// Name of the style the event uses, e.g. `Default` or `Signs`
+    pub style: String,
+    /// The raw text, including override blocks such as `{\i1}`
+    pub text: String,
+    /// The other fields of the event, such as `Layer` and `MarginL`, by their name in the
+    /// `Format` line
+    pub other: Vec<(String, String)>,
+}
+
+/// A `Style` line of an ASS/SSA file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssStyle {
+    /// Name of the style, which events refer to it by
+    pub name: String,
+    /// All fields of the style, including the name, in the order of the `Format` line
+    pub fields: Vec<String>,
+}
+
+/// An ASS/SSA file. Sections other than the script info, styles and events, such as embedded
+/// fonts, are dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ass {
+    /// The lines of the `[Script Info]` section, such as `PlayResX: 1920`
+    pub info: Vec<String>,
+    /// Name of the styles section, `V4+ Styles` for ASS and `V4 Styles` for SSA
+    pub styles_section: String,
+    /// The field names of the styles section's `Format` line
+    pub style_format: Vec<String>,
+    /// The styles the events use
+    pub styles: Vec<AssStyle>,
+    /// The field names of the `[Events]` section's `Format` line
+    pub event_format: Vec<String>,
+    /// The `Dialogue` events, `Comment` events are dropped
+    pub events: Vec<AssEvent>,
+}
+
+/// The section of an ASS/SSA file that `Ass::parse` is reading
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Info,
+    Styles,
+    Events,
+    Other,
+}
+
+impl Ass {
+    /// Parse an ASS/SSA file. The fields of each `Style` and `Dialogue` line are read according
+    /// to the `Format` line of their section, so both ASS and SSA v4 files work.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut section = Section::Other;
+        let mut ass = Ass::default();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.to_ascii_lowercase().as_str() {
+                    "script info" => Section::Info,
+                    "v4+ styles" | "v4 styles" => {
+                        ass.styles_section = name.to_owned();
+                        Section::Styles
+                    }
+                    "events" => Section::Events,
+                    _ => Section::Other,
+                };
+                continue;
+            }
+            if section == Section::Info {
+                if !line.is_empty() {
+                    ass.info.push(line.to_owned());
+                }
+                continue;
+            }
+
+            let Some((kind, rest)) = line.split_once(':') else {
+                continue;
+            };
+            match (section, kind.trim()) {
+                (Section::Styles, "Format") => ass.style_format = parse_format(rest),
+                (Section::Styles, "Style") => {
+                    let fields: Vec<String> = rest
+                        .trim_start()
+                        .splitn(ass.style_format.len().max(1), ',')
+                        .map(|f| f.trim().to_owned())
+                        .collect();
+                    let name = ass
+                        .style_format
+                        .iter()
+                        .position(|f| f.eq_ignore_ascii_case("name"))
+                        .and_then(|i| fields.get(i))
+                        .context(format!("unable to find the name of style {:?}", rest))?
+                        .clone();
+                    ass.styles.push(AssStyle { name, fields });
+                }
+                (Section::Events, "Format") => ass.event_format = parse_format(rest),
+                (Section::Events, "Dialogue") => {
+                    if ass.event_format.is_empty() {
+                        bail!("found a Dialogue line before the Format line of [Events]");
+                    }
+                    ass.events.push(parse_dialogue(&ass.event_format, rest)?);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(ass)
+    }
+
+    /// Only keep the events using the style named `style`
+    pub fn retain_style(&mut self, style: &str) {
+        self.events.retain(|e| e.style == style);
+    }
+
+    /// Remove karaoke timing tags (`\k`, `\kf`, `\ko` and `\K`) from the text of every event,
+    /// along with any override blocks left empty by it
+    pub fn strip_karaoke(&mut self) {
+        for event in &mut self.events {
+            let stripped = KARAOKE_TAG.replace_all(&event.text, "");
+            event.text = stripped.replace("{}", "");
+        }
+    }
+
+    /// Convert to SRT. Line breaks (`\N`, `\n`) and hard spaces (`\h`) are converted, while
+    /// override blocks are kept as they are.
+    pub fn to_subrip(&self) -> SubRip {
+        let subtitles = self
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| SrtSubtitle {
+                sequence: i as u32 + 1,
+                start: event.start.into(),
+                end: event.end.into(),
+                text: event
+                    .text
+                    .replace("\\h", " ")
+                    .split("\\N")
+                    .flat_map(|l| l.split("\\n"))
+                    .map(|l| l.to_owned())
+                    .collect(),
+                line_position: None,
+            })
+            .collect();
+
+        SubRip { subtitles }
+    }
+
+    /// Render as an ASS/SSA file, writing the sections `parse` keeps
+    pub fn render(&self) -> String {
+        let styles_section = match self.styles_section.as_str() {
+            "" => "V4+ Styles",
+            name => name,
+        };
+        let mut out = "[Script Info]\n".to_owned();
+        for line in &self.info {
+            writeln!(out, "{}", line).unwrap();
+        }
+
+        write!(
+            out,
+            "\n[{}]\nFormat: {}\n",
+            styles_section,
+            self.style_format.join(", ")
+        )
+        .unwrap();
+        for style in &self.styles {
+            writeln!(out, "Style: {}", style.fields.join(",")).unwrap();
+        }
+
+        write!(
+            out,
+            "\n[Events]\nFormat: {}\n",
+            self.event_format.join(", ")
+        )
+        .unwrap();
+        for event in &self.events {
+            let fields: Vec<String> = self
+                .event_format
+                .iter()
+                .map(|f| match f.to_ascii_lowercase().as_str() {
+                    "start" => timestamp(event.start),
+                    "end" => timestamp(event.end),
+                    "style" => event.style.clone(),
+                    "text" => event.text.clone(),
+                    lower => match event
+                        .other
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(f))
+                    {
+                        Some((_, value)) => value.clone(),
+                        // Events of a file with another format, e.g. SSA's `Marked` for `Layer`
+                        None if lower == "layer" || lower.starts_with("margin") => "0".to_owned(),
+                        None => String::new(),
+                    },
+                })
+                .collect();
+            writeln!(out, "Dialogue: {}", fields.join(",")).unwrap();
+        }
+        out
+    }
+}
+
+/// Combine the events of two ASS/SSA files in chronological order, keeping the styles of both.
+/// A style of `second` named like a different style of `first` is renamed with a `_2` suffix.
+/// The script info, such as the resolution, and the event format are those of `first`.
+pub fn merge_ssa(mut first: Ass, second: Ass) -> Result<Ass> {
+    let same_format = first.style_format.len() == second.style_format.len()
+        && first
+            .style_format
+            .iter()
+            .zip(&second.style_format)
+            .all(|(a, b)| a.eq_ignore_ascii_case(b));
+    if !same_format {
+        bail!("the styles of the files have different formats, e.g. one is ASS and the other SSA, unable to merge them keeping their styles");
+    }
+
+    let resolution = |ass: &Ass| {
+        let value = |key: &str| {
+            ass.info
+                .iter()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.trim().to_owned())
+        };
+        (value("PlayResX"), value("PlayResY"))
+    };
+    if resolution(&first) != resolution(&second) {
+        warn!(
+            reason = "mismatched_resolution";
+            "The files have different script resolutions, the positions and sizes of the second file's styles and tags are off by the difference"
+        );
+    }
+
+    let name_field = first
+        .style_format
+        .iter()
+        .position(|f| f.eq_ignore_ascii_case("name"));
+    let mut renamed = BTreeMap::new();
+    for mut style in second.styles {
+        match first.styles.iter().find(|s| s.name == style.name) {
+            Some(existing) if existing.fields == style.fields => continue,
+            Some(_) => {
+                let name = format!("{}_2", style.name);
+                if let Some(field) = name_field.and_then(|i| style.fields.get_mut(i)) {
+                    field.clone_from(&name);
+                }
+                renamed.insert(std::mem::replace(&mut style.name, name.clone()), name);
+            }
+            None => (),
+        }
+        first.styles.push(style);
+    }
+
+    for mut event in second.events {
+        if let Some(name) = renamed.get(&event.style) {
+            event.style.clone_from(name);
+        }
+        first.events.push(event);
+    }
+    // The sort being stable keeps the event of the first file first when both start at once
+    first.events.sort_by_key(|e| e.start);
+    Ok(first)
+}
+
+fn parse_format(line: &str) -> Vec<String> {
+    line.split(',').map(|f| f.trim().to_owned()).collect()
+}
+
+fn parse_dialogue(format: &[String], line: &str) -> Result<AssEvent> {
+    // The text is the last field and may itself contain commas
+    let fields: Vec<&str> = line.trim_start().splitn(format.len(), ',').collect();
+    if fields.len() != format.len() {
+        bail!(
+            "expected {} fields in Dialogue line {:?}",
+            format.len(),
+            line
+        )
+    }
+    let field = |name: &str| {
+        format
+            .iter()
+            .position(|f| f.eq_ignore_ascii_case(name))
+            .map(|i| fields[i])
+            .context(format!(
+                "the [Events] Format line is missing the {} field",
+                name
+            ))
+    };
+    let other = format
+        .iter()
+        .zip(&fields)
+        .filter(|(f, _)| !EVENT_FIELDS.iter().any(|e| f.eq_ignore_ascii_case(e)))
+        .map(|(f, value)| (f.clone(), value.trim().to_owned()))
+        .collect();
+
+    Ok(AssEvent {
+        start: parse_timestamp(field("start")?)?,
+        end: parse_timestamp(field("end")?)?,
+        style: field("style")?.trim().to_owned(),
+        text: field("text")?.to_owned(),
+        other,
+    })
+}
+
+/// The fields of `AssEvent` that are not in `AssEvent::other`
+const EVENT_FIELDS: &[&str] = &["start", "end", "style", "text"];
+
+/// Parse an ASS timestamp of the form `H:MM:SS.cc`
+fn parse_timestamp(s: &str) -> Result<Duration> {
+    let invalid = || format!("invalid ASS timestamp {:?}", s);
+    let (hms, centis) = s.trim().split_once('.').with_context(invalid)?;
+    let mut parts = hms.split(':').map(|p| p.parse::<u64>());
+    let (Some(Ok(h)), Some(Ok(m)), Some(Ok(sec)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        bail!(invalid())
+    };
+    // Centiseconds per the spec, but some tools write milliseconds
+    let fraction: u64 = centis.parse().with_context(invalid)?;
+    let millis = match centis.len() {
+        1 => fraction * 100,
+        2 => fraction * 10,
+        3 => fraction,
+        _ => bail!(invalid()),
+    };
+
+    Ok(Duration::from_millis(
+        ((h * 60 + m) * 60 + sec) * 1000 + millis,
+    ))
+}
+
+/// Script pixels are those of the 384x288 frame players assume for SRT files, so `{\pos(..)}`
+/// tags of the merged cues mean the same in the ASS output
+const HEADER: &str = "[Script Info]
+ScriptType: v4.00+
+PlayResX: 384
+PlayResY: 288
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, \
+Alignment, MarginL, MarginR, MarginV, Encoding
+";
+
+const EVENTS: &str = "
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+/// The color of a `<font color>` tag wrapping a whole line, possibly inside `<i>`, `<b>` or `<u>`
+static LINE_COLOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^(<[ibu]>)*(?P<font><font color="?(?P<color>[^">]+)"?>)"#).unwrap()
+});
+
+static FONT_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<font(?P<attrs>[^>]*)>|</font>"#).unwrap());
+
+static COLOR_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"color="?(?P<color>[^"\s>]+)"?"#).unwrap());
+
+static SIZE_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"size="?(?P<size>\d+)"?"#).unwrap());
+
+static FORMATTING_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?P<close>/)?(?P<tag>[ibu])>").unwrap());
+
+/// Render `srt` as ASS. The position, color and background box of each cue, taken from its first
+/// line, become the alignment, primary color and opaque box (`BorderStyle` 3) of a style,
+/// instead of the `{\anN}`, `<font>` and box tags SRT uses. The box tag is kept as an override
+/// too. Other colors become `{\c}` overrides and `<i>`, `<b>` and `<u>` their ASS equivalents.
+pub fn render(srt: &SubRip) -> String {
+    let mut styles: BTreeMap<(u8, Option<String>, Option<String>), String> = BTreeMap::new();
+    styles.insert((2, None, None), "Default".to_owned());
+    let mut unrepresentable = 0;
+
+    let mut events = String::new();
+    for cue in &srt.subtitles {
+        let first = cue.text.first().map_or("", |txt| split_position(txt).1);
+        let (box_color, first) = split_box(first);
+        // The variants are in numpad order, like ASS alignments
+        let position = cue.text.first().and_then(|txt| split_position(txt).0);
+        let alignment = position.unwrap_or(SubPosition::BottomCenter) as u8 + 1;
+        let color = LINE_COLOR
+            .captures(first)
+            .map(|c| c["color"].to_owned())
+            .filter(|color| match color_rgb(color) {
+                Some(_) => true,
+                None => {
+                    unrepresentable += 1;
+                    false
+                }
+            });
+
+        let key = (alignment, color, box_color.map(|c| c.to_owned()));
+        let name = match styles.get(&key) {
+            Some(name) => name.clone(),
+            None => {
+                let mut name = format!("an{}", key.0);
+                if let Some(color) = &key.1 {
+                    let hex: String = color
+                        .chars()
+                        .filter(|c| c.is_ascii_alphanumeric())
+                        .collect();
+                    write!(name, "_{}", hex).unwrap();
+                }
+                if let Some(box_color) = &key.2 {
+                    write!(name, "_box{}", box_color.trim_start_matches("&H")).unwrap();
+                }
+                styles.insert(key.clone(), name.clone());
+                name
+            }
+        };
+
+        let mut lines: Vec<String> = cue
+            .text
+            .iter()
+            .map(|txt| event_text(split_box(split_position(txt).1).1, key.1.as_deref()))
+            .collect();
+        // Overrides last until the end of the event, so the box tag is only needed once
+        if let (Some(first), Some(txt)) = (lines.first_mut(), cue.text.first()) {
+            let rest = split_position(txt).1;
+            let tag = &rest[..rest.len() - split_box(rest).1.len()];
+            first.insert_str(0, tag);
+        }
+        writeln!(
+            events,
+            "Dialogue: 0,{},{},{},,0,0,0,,{}",
+            timestamp(cue.start.into()),
+            timestamp(cue.end.into()),
+            name,
+            lines.join("\\N")
+        )
+        .unwrap();
+    }
+
+    if unrepresentable > 0 {
+        warn!(
+            reason = "unrepresentable_style";
+            "{} cues have colors that are neither hex colors nor CSS color names, they are shown in white", unrepresentable
+        );
+    }
+
+    let mut out = HEADER.to_owned();
+    for ((alignment, color, box_color), name) in &styles {
+        let primary = color.as_deref().and_then(color_rgb).unwrap_or(0xFFFFFF);
+        // An opaque box is drawn in the outline color by some renderers and in the back color
+        // by others
+        let (border_style, outline, back) = match box_color {
+            Some(box_color) => (3, box_color.as_str(), box_color.as_str()),
+            None => (1, "&H00000000", "&H80000000"),
+        };
+        writeln!(
+            out,
+            "Style: {},Arial,16,{},&H000000FF,{},{},0,0,0,0,100,100,0,0,{},1,0,{},10,10,10,1",
+            name,
+            ass_color(primary),
+            outline,
+            back,
+            border_style,
+            alignment
+        )
+        .unwrap();
+    }
+    out.push_str(EVENTS);
+    out.push_str(&events);
+    out
+}
+
+/// Convert the tags of an SRT line to ASS overrides. The `<font>` tag wrapping a line shown in
+/// its style's color, `style_color`, is dropped, and uncolored lines are made white.
+fn event_text(txt: &str, style_color: Option<&str>) -> String {
+    let mut txt = txt.to_owned();
+    match LINE_COLOR.captures(&txt) {
+        Some(c) if Some(&c["color"]) == style_color => {
+            let font = c.name("font").unwrap().range();
+            if let Some(close) = txt.rfind("</font>") {
+                txt.replace_range(close..close + "</font>".len(), "");
+            }
+            txt.replace_range(font, "");
+        }
+        None if style_color.is_some() => txt = format!("<font color=\"#ffffff\">{txt}</font>"),
+        _ => (),
+    }
+
+    // Overrides last until the end of the event, so every color and size is reset at its
+    // `</font>`, which is paired with its `<font>` as they may be nested
+    let mut open = Vec::new();
+    let txt = FONT_TAG.replace_all(&txt, |c: &regex::Captures| {
+        let Some(attrs) = c.name("attrs") else {
+            let (color, size) = open.pop().unwrap_or((true, false));
+            let mut reset = String::new();
+            if color {
+                reset.push_str("{\\c}");
+            }
+            if size {
+                reset.push_str("{\\fs}");
+            }
+            return reset;
+        };
+
+        let rgb = COLOR_ATTR
+            .captures(attrs.as_str())
+            .and_then(|c| color_rgb(&c["color"]));
+        let size = SIZE_ATTR.captures(attrs.as_str());
+        // Only what was overridden is reset, an unknown color is left as it is
+        open.push((rgb.is_some(), size.is_some()));
+        let mut overrides = String::new();
+        if let Some(rgb) = rgb {
+            write!(overrides, "{{\\c{}&}}", ass_color(rgb)).unwrap();
+        }
+        if let Some(size) = size {
+            write!(overrides, "{{\\fs{}}}", &size["size"]).unwrap();
+        }
+        overrides
+    });
+    let txt = FORMATTING_TAG.replace_all(&txt, |c: &regex::Captures| {
+        let on = if c.name("close").is_some() { 0 } else { 1 };
+        format!("{{\\{}{}}}", &c["tag"], on)
+    });
+    txt.into_owned()
+}
+
+/// An ASS color, which is in `&HBBGGRR` order
+pub(crate) fn ass_color(rgb: u32) -> String {
+    let (r, g, b) = (rgb >> 16, (rgb >> 8) & 0xFF, rgb & 0xFF);
+    format!("&H{:02X}{:02X}{:02X}", b, g, r)
+}
+
+/// An ASS timestamp of the form `H:MM:SS.cc`
+fn timestamp(t: Duration) -> String {
+    let centis = t.as_millis() / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
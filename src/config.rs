@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{collections::BTreeMap, fs, num::NonZeroU32, path::Path};
+
+use crate::{
+    builtin_preset, parse_color, parse_offset, OutputFormat, SubPosition, TrackOptions,
+    BUILTIN_PRESETS,
+};
+
+/// Defaults for the options of the merging subcommands, read from a TOML file like
+///
+/// ```toml
+/// out-format = "ass"
+///
+/// [sub1]
+/// color = "white"
+/// position = "bottom-center"
+///
+/// [sub2]
+/// color = "#fabd2f"
+/// position = "top-center"
+/// offset = -1.5
+/// size = 18
+///
+/// [lang.ja]
+/// color = "#83a598"
+///
+/// [preset.subdued]
+/// color = "#928374"
+/// position = "top-left"
+/// ```
+///
+/// Options given on the command line take precedence over those of the file, which take
+/// precedence over the built-in defaults. Within the file, the options of a track's language
+/// take precedence over those of the track. Presets are only used if chosen by `--sub2-preset`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Defaults for the first track
+    #[serde(default)]
+    pub sub1: TrackConfig,
+    /// Defaults for the second track
+    #[serde(default)]
+    pub sub2: TrackConfig,
+    /// The format to write merged subtitles in
+    #[serde(default, deserialize_with = "value_enum")]
+    pub out_format: Option<OutputFormat>,
+    /// Defaults for the tracks in a language, by language code, e.g. `ja`
+    #[serde(default)]
+    pub lang: BTreeMap<String, TrackConfig>,
+    /// Styling presets by name, in addition to the built-in ones, which they take precedence over
+    #[serde(default)]
+    pub preset: BTreeMap<String, TrackConfig>,
+}
+
+impl Config {
+    /// The defaults of the first track, in the language `lang` if it is known
+    pub fn sub1_options(&self, lang: Option<&str>) -> TrackOptions {
+        self.track_options(&self.sub1, lang)
+    }
+
+    /// The defaults of the second track, in the language `lang` if it is known
+    pub fn sub2_options(&self, lang: Option<&str>) -> TrackOptions {
+        self.track_options(&self.sub2, lang)
+    }
+
+    /// The preset named `name`, defined in the file or built in
+    pub fn preset(&self, name: &str) -> Option<TrackOptions> {
+        match self.preset.get(name) {
+            Some(preset) => Some(preset.options()),
+            None => builtin_preset(name),
+        }
+    }
+
+    /// The names of all presets, those defined in the file after the built-in ones
+    pub fn preset_names(&self) -> Vec<&str> {
+        let defined = self.preset.keys().map(|name| name.as_str());
+        let builtin = BUILTIN_PRESETS.iter().copied();
+        let mut names: Vec<_> = builtin
+            .filter(|name| !self.preset.contains_key(*name))
+            .collect();
+        names.extend(defined);
+        names
+    }
+
+    /// The options of `track` filled in by those of its language. A code with a region, such as
+    /// `en-US`, falls back to the options of the language without it.
+    fn track_options(&self, track: &TrackConfig, lang: Option<&str>) -> TrackOptions {
+        let lang = lang.and_then(|code| {
+            let base = code.split(['-', '_']).next()?;
+            self.lang.get(code).or_else(|| self.lang.get(base))
+        });
+        match lang {
+            Some(lang) => lang.options().or(track.options()),
+            None => track.options(),
+        }
+    }
+}
+
+/// Defaults for the styling of a track, see `Config`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackConfig {
+    /// See `TrackOptions::color`, as `#RGB`, `#RRGGBB` or a CSS color name
+    #[serde(default, deserialize_with = "color")]
+    pub color: Option<String>,
+    /// See `TrackOptions::position`, named like `--position`, e.g. `top-center`
+    #[serde(default, deserialize_with = "value_enum")]
+    pub position: Option<SubPosition>,
+    /// See `TrackOptions::offset`, as a number of seconds or a time like `--sub1-offset`
+    #[serde(default, deserialize_with = "offset")]
+    pub offset: Option<f64>,
+    /// See `TrackOptions::size`
+    pub size: Option<NonZeroU32>,
+    /// See `TrackOptions::box_color`, written like `color`
+    #[serde(default, rename = "box-color", deserialize_with = "color")]
+    pub box_color: Option<String>,
+}
+
+impl TrackConfig {
+    /// The options of the track set by the file, to fill in those not given on the command line
+    /// with `TrackOptions::or`
+    pub fn options(&self) -> TrackOptions {
+        TrackOptions {
+            color: self.color.clone(),
+            position: self.position,
+            offset: self.offset,
+            size: self.size.map(NonZeroU32::get),
+            box_color: self.box_color.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Read the configuration file at `path`, see `Config`
+pub fn load_config(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path).context(format!("unable to read config {:?}", path))?;
+    toml::from_str(&text).context(format!("invalid config {:?}", path))
+}
+
+/// Deserialize an enum by the name the command line uses for it, e.g. `top-center`
+fn value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: clap::ValueEnum,
+{
+    let name = String::deserialize(deserializer)?;
+    let names = T::value_variants()
+        .iter()
+        .filter_map(|v| Some(v.to_possible_value()?.get_name().to_owned()))
+        .collect::<Vec<_>>();
+    T::from_str(&name, true)
+        .map(Some)
+        .map_err(|_| D::Error::custom(format!("{:?} is not one of {}", name, names.join(", "))))
+}
+
+fn color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let color = String::deserialize(deserializer)?;
+    parse_color(&color).map(Some).map_err(D::Error::custom)
+}
+
+fn offset<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Offset {
+        Seconds(f64),
+        Time(String),
+    }
+
+    match Offset::deserialize(deserializer)? {
+        Offset::Seconds(secs) => Ok(Some(secs)),
+        Offset::Time(time) => parse_offset(&time).map(Some).map_err(D::Error::custom),
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::{path::Path, process::Command, time::Duration};
+use subtp::srt::SubRip;
+
+use crate::{SubPosition, TrackOptions};
+
+/// Frames are scaled down to this size before measuring them, which also averages them
+const SAMPLE_WIDTH: usize = 64;
+const SAMPLE_HEIGHT: usize = 36;
+/// How many frames to sample for each track
+const SAMPLES: usize = 5;
+/// Regions brighter than this average luminance (out of 255) get dark text
+const BRIGHT: f64 = 140.0;
+
+const LIGHT_TEXT: &str = "#ffffff";
+const DARK_TEXT: &str = "#202020";
+
+/// Decode the frame of `media` at `at` with `ffmpeg`, returning its luminance as rows of
+/// `SAMPLE_WIDTH` pixels. `None` if there is no frame at that time.
+fn frame_luma(media: &Path, at: Duration) -> Result<Option<Vec<u8>>> {
+    let scale = format!("scale={}:{},format=gray", SAMPLE_WIDTH, SAMPLE_HEIGHT);
+    let output = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "error",
+            "-ss",
+            &format!("{:.3}", at.as_secs_f64()),
+            "-i",
+        ])
+        .arg(media)
+        .args(["-frames:v", "1", "-vf", &scale, "-f", "rawvideo", "-"])
+        .output()
+        .context("unable to run ffmpeg, make sure it is installed and on the PATH")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed on {:?}: {}",
+            media,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    Ok((output.stdout.len() == SAMPLE_WIDTH * SAMPLE_HEIGHT).then_some(output.stdout))
+}
+
+/// The average luminance of the third of a frame that text at `position` is shown in
+pub fn region_luma(luma: &[u8], position: SubPosition) -> f64 {
+    // The variants are in numpad order, bottom left to top right
+    let band = 2 - position as usize / 3;
+    let rows = SAMPLE_HEIGHT / 3;
+    let region = &luma[band * rows * SAMPLE_WIDTH..(band + 1) * rows * SAMPLE_WIDTH];
+    region.iter().map(|&l| l as f64).sum::<f64>() / region.len() as f64
+}
+
+/// A text color contrasting with a background of the given luminance
+pub fn contrasting_color(luma: f64) -> &'static str {
+    if luma > BRIGHT {
+        DARK_TEXT
+    } else {
+        LIGHT_TEXT
+    }
+}
+
+/// Set the color of a track to contrast with the video of `media` where the track is shown.
+/// Frames are sampled in the middle of cues spread evenly over the track.
+pub fn with_contrast(media: &Path, srt: &SubRip, opts: TrackOptions) -> Result<TrackOptions> {
+    let position = opts.position.unwrap_or_default();
+    let step = srt.subtitles.len().div_ceil(SAMPLES).max(1);
+
+    let mut lumas = Vec::new();
+    for cue in srt.subtitles.iter().step_by(step) {
+        let (start, end): (Duration, Duration) = (cue.start.into(), cue.end.into());
+        if let Some(frame) = frame_luma(media, (start + end) / 2)? {
+            lumas.push(region_luma(&frame, position));
+        }
+    }
+
+    if lumas.is_empty() {
+        warn!(
+            reason = "no_frames", path:% = media.display();
+            "Unable to sample any frames of {:?}, keeping the track color", media
+        );
+        return Ok(opts);
+    }
+
+    let luma = lumas.iter().sum::<f64>() / lumas.len() as f64;
+    let color = contrasting_color(luma);
+    info!(
+        "Average luminance behind {:?} text is {:.0}, using {}",
+        position, luma, color
+    );
+    Ok(TrackOptions {
+        color: Some(color.to_owned()),
+        ..opts
+    })
+}
@@ -0,0 +1,198 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+/// The most common words of the languages written in the Latin script, which tell them apart.
+/// Words shared by several of the languages are left out. Ties go to the language listed first.
+/// Text in a listed language has at least this share of its words in the list, so text with
+/// fewer is likely in another language that merely shares a few words
+const MIN_COMMON_SHARE: f64 = 0.1;
+
+const COMMON_WORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "you", "it", "that", "of", "what", "this", "have", "are", "with", "he",
+            "she", "they", "my", "your", "be", "me", "just", "know", "was", "not",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "que", "y", "los", "es", "lo", "por", "una", "para", "qué", "con", "las", "pero",
+            "está", "estoy", "muy", "sí", "yo", "eso", "bien", "esto", "aquí", "tengo", "hay",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "les", "et", "est", "je", "tu", "vous", "pas", "il", "un", "une", "des", "ce",
+            "qui", "ne", "nous", "avec", "pour", "mais", "moi", "oui", "suis", "ça",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "ich", "nicht", "sie", "ein", "eine", "zu", "mit",
+            "wir", "den", "auf", "ja", "hier", "auch", "mir", "mich", "bin", "habe", "sind",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "che", "di", "e", "è", "non", "per", "sono", "ti", "ma", "cosa", "questo", "della",
+            "come", "ho", "io", "sei", "perché", "anche", "molto", "ci", "bene", "gli",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "não", "o", "os", "uma", "você", "é", "do", "da", "em", "um", "com", "isso", "eu",
+            "mas", "ele", "ela", "muito", "aqui", "então", "obrigado", "tem", "vai", "estou",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "niet", "dat", "ik", "van", "wat", "zijn", "maar", "hij",
+            "ze", "op", "te", "dit", "hebben", "naar", "heb", "jij", "zo",
+        ],
+    ),
+    (
+        "sv",
+        &[
+            "och", "att", "är", "jag", "inte", "för", "hon", "vad", "till", "här", "också",
+            "något", "kanske", "varför", "honom", "mycket",
+        ],
+    ),
+    (
+        "da",
+        &[
+            "hvad", "ved", "af", "lige", "noget", "nogen", "hende", "mere", "efter", "ud", "måske",
+            "jer", "blev", "siger", "meget",
+        ],
+    ),
+    (
+        "no",
+        &[
+            "hva", "vet", "noe", "noen", "henne", "etter", "mer", "nå", "ut", "opp", "kanskje",
+            "dere", "meg", "deg", "seg", "ble", "sier", "mye",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "nie", "się", "to", "jest", "w", "na", "i", "że", "co", "z", "jak", "tak", "mnie",
+            "ale", "czy", "już", "ty", "po", "dobrze", "jestem",
+        ],
+    ),
+    (
+        "tr",
+        &[
+            "bir", "ve", "bu", "için", "ben", "sen", "çok", "var", "değil", "evet", "hayır", "şey",
+            "ama", "gibi", "daha", "onu", "benim", "seni", "burada", "tamam",
+        ],
+    ),
+];
+
+/// The scripts that `detect_language` tells apart, a tie goes to the script listed first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Kana,
+    Han,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Thai,
+    Devanagari,
+}
+
+impl Script {
+    fn of(c: char) -> Option<Script> {
+        let script = match c {
+            '\u{3040}'..='\u{30ff}' => Script::Kana,
+            '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => Script::Han,
+            '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => {
+                Script::Hangul
+            }
+            '\u{0400}'..='\u{04ff}' => Script::Cyrillic,
+            '\u{0370}'..='\u{03ff}' => Script::Greek,
+            '\u{0600}'..='\u{06ff}' => Script::Arabic,
+            '\u{0590}'..='\u{05ff}' => Script::Hebrew,
+            '\u{0e00}'..='\u{0e7f}' => Script::Thai,
+            '\u{0900}'..='\u{097f}' => Script::Devanagari,
+            c if c.is_ascii_alphabetic() || ('\u{00c0}'..='\u{024f}').contains(&c) => Script::Latin,
+            _ => return None,
+        };
+        Some(script)
+    }
+}
+
+/// Detect the language of `text`, returning its ISO 639-1 code (`en`, `ja`, ...) and the
+/// confidence of the guess between 0 and 1. The script of the text decides the language, or
+/// for the Latin script, the most common words. Returns `None` if there is nothing to go by.
+pub fn detect_language(text: &str) -> Option<(&'static str, f64)> {
+    let mut scripts: BTreeMap<Script, usize> = BTreeMap::new();
+    for script in text.chars().filter_map(Script::of) {
+        *scripts.entry(script).or_insert(0) += 1;
+    }
+    let letters: usize = scripts.values().sum();
+
+    let (&script, &most) = scripts
+        .iter()
+        .max_by_key(|&(&script, &n)| (n, Reverse(script)))?;
+    let lang = match script {
+        Script::Latin => return detect_latin(text),
+        // Japanese mixes kana with kanji, which are shared with Chinese
+        Script::Kana | Script::Han => {
+            let count = |script| scripts.get(&script).copied().unwrap_or(0);
+            let (kana, han) = (count(Script::Kana), count(Script::Han));
+            let lang = if kana > 0 { "ja" } else { "zh" };
+            return Some((lang, (kana + han) as f64 / letters as f64));
+        }
+        Script::Hangul => "ko",
+        // Ukrainian has a few letters of its own, Russian is far more common otherwise
+        Script::Cyrillic if text.contains(['ї', 'є', 'ґ', 'і']) => "uk",
+        Script::Cyrillic => "ru",
+        Script::Greek => "el",
+        Script::Arabic => "ar",
+        Script::Hebrew => "he",
+        Script::Thai => "th",
+        Script::Devanagari => "hi",
+    };
+    Some((lang, most as f64 / letters as f64))
+}
+
+/// Detect the language of text in the Latin script by its most common words. The confidence is
+/// lowered for text with too few of them, e.g. in a language that is not listed.
+fn detect_latin(text: &str) -> Option<(&'static str, f64)> {
+    let mut hits = vec![0; COMMON_WORDS.len()];
+    let mut words = 0;
+    for word in text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+    {
+        words += 1;
+        let word = word.to_lowercase();
+        for (i, (_, words)) in COMMON_WORDS.iter().enumerate() {
+            if words.contains(&word.as_str()) {
+                hits[i] += 1;
+            }
+        }
+    }
+
+    let total: usize = hits.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let (i, best) = hits
+        .into_iter()
+        .enumerate()
+        .max_by_key(|&(i, n)| (n, Reverse(i)))?;
+    let common = best as f64 / words as f64 / MIN_COMMON_SHARE;
+    Some((
+        COMMON_WORDS[i].0,
+        best as f64 / total as f64 * common.min(1.0),
+    ))
+}
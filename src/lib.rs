@@ -0,0 +1,116 @@
+#![feature(let_chains)]
+#![warn(missing_docs)]
+//! Merge two or more subtitle tracks into one, e.g. to show subtitles in the language being
+//! learned together with those in a familiar language.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use submerger::{apply_sub_changes, load_sub, merge, write_sub, SubPosition, TrackOptions};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let en = load_sub(Path::new("movie.en.srt"), &Default::default())?;
+//! let mut ja = load_sub(Path::new("movie.ja.srt"), &Default::default())?;
+//! let opts = TrackOptions {
+//!     position: Some(SubPosition::TopCenter),
+//!     ..Default::default()
+//! };
+//! apply_sub_changes(&mut ja, &opts);
+//! let merged = merge(en, ja, &Default::default());
+//! write_sub(Path::new("movie.srt"), &merged, &Default::default())?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod ass;
+mod config;
+/// Coloring tracks to contrast with the video they are shown on, using `ffmpeg`
+#[cfg(feature = "video")]
+pub mod contrast;
+mod lang;
+/// Checks against the media being subtitled, using `ffprobe`
+#[cfg(feature = "ffprobe")]
+pub mod media;
+mod merge;
+/// Reading subtitles muxed into MKV files, using `ffmpeg`
+#[cfg(feature = "mkv")]
+pub mod mkv;
+/// Reading subtitles from `http(s)://` URLs
+#[cfg(feature = "url")]
+pub mod remote;
+/// Drawing the cues shown at a point in time to an image
+#[cfg(feature = "preview")]
+pub mod render;
+mod sami;
+mod stream;
+mod test;
+mod vtt;
+mod warnings;
+
+pub use ass::{merge_ssa, Ass, AssEvent, AssStyle};
+pub use config::{load_config, Config, TrackConfig};
+pub use merge::*;
+pub use stream::{merge_streams, stream_sub, SrtStream};
+pub use vtt::cue_position;
+pub use warnings::{init_logging, write_warnings, Warning};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use core::fmt;
+use std::str::FromStr;
+
+/// Where on the screen a track is shown, written into cues as ASS override tags by `Display`
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[allow(missing_docs)]
+pub enum SubPosition {
+    BottomLeft,
+    #[default]
+    BottomCenter,
+    BottomRight,
+    MiddleLeft,
+    MiddleCenter,
+    MiddleRight,
+    TopLeft,
+    TopCenter,
+    TopRight,
+}
+
+impl fmt::Display for SubPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let printable = match self {
+            SubPosition::BottomLeft => "{\\an1}",
+            SubPosition::BottomCenter => "{\\an2}",
+            SubPosition::BottomRight => "{\\an3}",
+            SubPosition::MiddleLeft => "{\\an4}",
+            SubPosition::MiddleCenter => "{\\an5}",
+            SubPosition::MiddleRight => "{\\an6}",
+            SubPosition::TopLeft => "{\\an7}",
+            SubPosition::TopCenter => "{\\an8}",
+            SubPosition::TopRight => "{\\an9}",
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl SubPosition {
+    /// The position in the same column and the row next to this one, towards the middle of the
+    /// screen, e.g. `MiddleCenter` for `BottomCenter` and `TopCenter` for `MiddleCenter`
+    pub fn vertical_neighbor(self) -> SubPosition {
+        // The variants are in numpad order, bottom left to top right
+        let (row, col) = (self as usize / 3, self as usize % 3);
+        let row = if row == 1 { 2 } else { 1 };
+        SubPosition::value_variants()[row * 3 + col]
+    }
+}
+
+/// Parses a position tag as written by `Display`, e.g. `{\an8}`
+impl FromStr for SubPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SubPosition::value_variants()
+            .iter()
+            .find(|p| p.to_string() == s)
+            .copied()
+            .with_context(|| format!("{:?} is not a position tag", s))
+    }
+}
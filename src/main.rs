@@ -1,31 +1,30 @@
 #![feature(let_chains)]
 
-mod merge;
-mod test;
+#[cfg(feature = "video")]
+use submerger::contrast;
+#[cfg(feature = "ffprobe")]
+use submerger::media;
+#[cfg(feature = "preview")]
+use submerger::render;
+use submerger::*;
 
-use merge::*;
-
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use core::fmt;
-use log::info;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
-
-#[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
-enum SubPosition {
-    BottomLeft,
-    #[default]
-    BottomCenter,
-    BottomRight,
-    MiddleLeft,
-    MiddleCenter,
-    MiddleRight,
-    TopLeft,
-    TopCenter,
-    TopRight,
-}
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{error, info, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::sync::Mutex;
+use std::time::Duration;
+use subtp::srt::SubRip;
 
 #[derive(clap::ValueEnum, Clone, Copy, Default, Debug)]
 enum LogLevel {
@@ -61,20 +60,607 @@ impl From<LogLevel> for log::Level {
     }
 }
 
-impl fmt::Display for SubPosition {
+/// Machine-readable description of what the tool supports, see the `info` subcommand
+#[derive(Serialize)]
+struct Info {
+    input_formats: &'static [&'static str],
+    output_formats: &'static [&'static str],
+    positions: Vec<PositionInfo>,
+}
+
+#[derive(Serialize)]
+struct PositionInfo {
+    name: String,
+    tag: String,
+}
+
+impl Info {
+    fn new() -> Self {
+        let positions = SubPosition::value_variants()
+            .iter()
+            .filter_map(|p| {
+                Some(PositionInfo {
+                    name: p.to_possible_value()?.get_name().to_owned(),
+                    tag: p.to_string(),
+                })
+            })
+            .collect();
+
+        Info {
+            input_formats: INPUT_FORMATS,
+            output_formats: OUTPUT_FORMATS,
+            positions,
+        }
+    }
+}
+
+impl fmt::Display for Info {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let printable = match self {
-            SubPosition::BottomLeft => "{\\an1}",
-            SubPosition::BottomCenter => "{\\an2}",
-            SubPosition::BottomRight => "{\\an3}",
-            SubPosition::MiddleLeft => "{\\an4}",
-            SubPosition::MiddleCenter => "{\\an5}",
-            SubPosition::MiddleRight => "{\\an6}",
-            SubPosition::TopLeft => "{\\an7}",
-            SubPosition::TopCenter => "{\\an8}",
-            SubPosition::TopRight => "{\\an9}",
+        writeln!(f, "Input formats: {}", self.input_formats.join(", "))?;
+        writeln!(f, "Output formats: {}", self.output_formats.join(", "))?;
+        writeln!(f, "Positions:")?;
+        for p in &self.positions {
+            writeln!(f, "  {:<14} {}", p.name, p.tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// A merge performed by the recursive command, see `--manifest`
+#[derive(Serialize)]
+struct ManifestEntry {
+    sources: Vec<ManifestSource>,
+    output: PathBuf,
+    cues: usize,
+}
+
+#[derive(Serialize)]
+struct ManifestSource {
+    path: PathBuf,
+    lang: String,
+    hearing_impaired: bool,
+}
+
+impl From<&SubFile> for ManifestSource {
+    fn from(sub: &SubFile) -> Self {
+        ManifestSource {
+            path: sub.path.clone(),
+            lang: sub.code.clone(),
+            hearing_impaired: sub.variant != Variant::Normal,
+        }
+    }
+}
+
+/// Write `entries` to `path` as a JSON array, sorted by output so runs can be diffed
+fn write_manifest(path: &Path, mut entries: Vec<ManifestEntry>) -> Result<()> {
+    entries.sort_by(|a, b| a.output.cmp(&b.output));
+    let file = File::create(path).context(format!("unable to create manifest {:?}", path))?;
+    serde_json::to_writer_pretty(file, &entries)?;
+    Ok(())
+}
+
+/// Options shared by the merging subcommands that are applied to the individual tracks
+#[derive(Args)]
+struct TrackArgs {
+    /// Sets the color for the second subtitle track, as `#RGB`, `#RRGGBB` or a CSS color name
+    #[arg(short, long, value_parser = parse_color)]
+    color: Option<String>,
+
+    /// Sets the position of the second subtitle track [default: top-center]
+    #[arg(short, long)]
+    position: Option<SubPosition>,
+
+    /// Place the first subtitle track at this exact point (`X,Y` in script pixels) with an ASS
+    /// `{\pos(X,Y)}` tag. Not all players support it.
+    #[arg(long)]
+    sub1_pos_xy: Option<Point>,
+
+    /// Font size of the first track, as `<font size>` tags, or `\fs` overrides in ASS output
+    #[arg(long, value_name = "N")]
+    sub1_size: Option<NonZeroU32>,
+
+    /// Font size of the second track, e.g. smaller than the first to tell them apart
+    #[arg(long, value_name = "N")]
+    sub2_size: Option<NonZeroU32>,
+
+    /// Make the first track bold
+    #[arg(long)]
+    sub1_bold: bool,
+
+    /// Make the second track bold
+    #[arg(long)]
+    sub2_bold: bool,
+
+    /// Make the first track italic
+    #[arg(long)]
+    sub1_italic: bool,
+
+    /// Make the second track italic
+    #[arg(long)]
+    sub2_italic: bool,
+
+    /// Place the second subtitle track at this exact point (`X,Y` in script pixels), instead of
+    /// at `--position`. Not all players support it.
+    #[arg(long)]
+    sub2_pos_xy: Option<Point>,
+
+    /// If the second subtitle track would be shown at the same position as the first, move it
+    /// up or down a row so the tracks do not overlap
+    #[arg(long)]
+    auto_stack: bool,
+
+    /// Sets the color of an opaque background box behind the second subtitle track.
+    /// Note, only ASS output can represent this.
+    #[arg(long, value_parser = parse_color)]
+    sub2_box_color: Option<String>,
+
+    /// Use a named bundle of styling options for the second subtitle track, explicitly given
+    /// options take precedence. Built-in presets: dim-top, dim-bottom, yellow-top, boxed-bottom.
+    /// More can be defined in the `[preset.<NAME>]` tables of `--config`
+    #[arg(long)]
+    sub2_preset: Option<String>,
+
+    /// Extend every cue of both tracks to the start of the next cue, so text is always on screen
+    #[arg(long)]
+    fill_gaps: bool,
+
+    /// Color the bracketed sound descriptions (`[door slams]`) of hearing impaired tracks, those
+    /// named like `movie.en.hi.srt` or `movie.en.sdh.srt`, leaving the dialogue as it is
+    #[arg(long, value_parser = parse_color)]
+    hi_annotation_color: Option<String>,
+
+    /// Combine consecutive cues of each track less than this many milliseconds apart into one,
+    /// as long as it is shown for at most 7 seconds
+    #[arg(long, value_name = "MS")]
+    gap_merge: Option<u64>,
+
+    /// Extend the cues of both tracks shown for less than this many seconds (e.g. `1.2`), but
+    /// never past the start of the next cue
+    #[arg(long, value_name = "SECONDS", value_parser = positive_f64)]
+    min_duration: Option<f64>,
+
+    /// Color lines starting with a speaker label (`JOHN: ...`) in both tracks with a color per speaker
+    #[arg(long)]
+    color_speakers: bool,
+
+    /// Round the timestamps of both tracks to the nearest frame at this framerate (e.g. `23.976`)
+    #[arg(long, value_parser = positive_f64)]
+    snap_fps: Option<f64>,
+
+    /// Drop the cues of both tracks starting before this time (`HH:MM:SS`), e.g. a recap
+    #[arg(long, value_parser = parse_time)]
+    skip_before: Option<Duration>,
+
+    /// Drop the cues of both tracks starting at or after this time (`HH:MM:SS`), e.g. the credits
+    #[arg(long, value_parser = parse_time)]
+    skip_after: Option<Duration>,
+
+    /// The framerate the first track was timed for, retimed to `--sub1-fps-to` (e.g. `23.976`)
+    #[arg(long, value_parser = positive_f64, requires = "sub1_fps_to")]
+    sub1_fps_from: Option<f64>,
+
+    /// The framerate of the video to retime the first track to (e.g. `25`)
+    #[arg(long, value_parser = positive_f64, requires = "sub1_fps_from")]
+    sub1_fps_to: Option<f64>,
+
+    /// The framerate the second track was timed for, retimed to `--sub2-fps-to` (e.g. `23.976`)
+    #[arg(long, value_parser = positive_f64, requires = "sub2_fps_to")]
+    sub2_fps_from: Option<f64>,
+
+    /// The framerate of the video to retime the second track to (e.g. `25`)
+    #[arg(long, value_parser = positive_f64, requires = "sub2_fps_from")]
+    sub2_fps_to: Option<f64>,
+
+    /// Shift the first track by this many seconds, negative values make it earlier (e.g. `-1.5`).
+    /// May also be given as `HH:MM:SS.mmm` or `MM:SS.mmm`, e.g. `2:13.500` or `-0:05`
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_offset)]
+    sub1_offset: Option<f64>,
+
+    /// Shift the second track by this many seconds, negative values make it earlier (e.g. `-1.5`).
+    /// May also be given as `HH:MM:SS.mmm` or `MM:SS.mmm`
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_offset)]
+    sub2_offset: Option<f64>,
+
+    /// Only shift the cues of the first track starting at or after this time (`HH:MM:SS`) by
+    /// `--sub1-offset`, e.g. when it only drifts after an ad break
+    #[arg(long, value_parser = parse_time, requires = "sub1_offset")]
+    sub1_offset_from: Option<Duration>,
+
+    /// Only shift the cues of the second track starting at or after this time (`HH:MM:SS`) by
+    /// `--sub2-offset`
+    #[arg(long, value_parser = parse_time, requires = "sub2_offset")]
+    sub2_offset_from: Option<Duration>,
+
+    /// Shift the second track so its cue at this index (counting from 1) starts at this time,
+    /// e.g. `5=00:01:02.500` or `5=62.5`
+    #[arg(long, value_name = "SRC_IDX=DST_TIME", conflicts_with = "sub2_offset")]
+    sub2_anchor: Option<Anchor>,
+
+    /// Remap the timings of both tracks by this file of `FROM TO` times per line (e.g.
+    /// `00:10:00 00:10:02.500`), interpolating between the lines, for subtitles drifting
+    /// differently throughout the video
+    #[arg(long, value_name = "FILE", value_parser = load_sync_map, conflicts_with_all = [
+        "sub1_offset", "sub2_offset", "sub2_anchor", "sub1_fps_from", "sub2_fps_from",
+    ])]
+    sync_map: Option<SyncMap>,
+
+    /// Only keep this line of each cue of the first track, or a range of lines (e.g. `1-2`)
+    #[arg(long)]
+    sub1_keep_line: Option<LineRange>,
+
+    /// Only keep this line of each cue of the second track, or a range of lines (e.g. `1-2`)
+    #[arg(long)]
+    sub2_keep_line: Option<LineRange>,
+
+    /// Rewrap the lines of both tracks to at most this many characters at word boundaries,
+    /// words longer than this are kept whole
+    #[arg(long, value_name = "N")]
+    wrap: Option<NonZeroUsize>,
+
+    /// Remove all formatting, color and position tags from both tracks, for players and TVs
+    /// that show tags as text
+    #[arg(long)]
+    plain: bool,
+}
+
+impl TrackArgs {
+    /// The options applying to both tracks
+    fn shared(&self) -> TrackOptions {
+        TrackOptions {
+            fill_gaps: self.fill_gaps,
+            gap_merge: self.gap_merge.map(Duration::from_millis),
+            min_duration: self.min_duration.map(Duration::from_secs_f64),
+            color_speakers: self.color_speakers,
+            annotation_color: self.hi_annotation_color.clone(),
+            snap_fps: self.snap_fps,
+            skip_before: self.skip_before,
+            skip_after: self.skip_after,
+            sync_map: self.sync_map.clone(),
+            wrap: self.wrap.map(NonZeroUsize::get),
+            plain: self.plain,
+            ..Default::default()
+        }
+    }
+
+    /// The options of the first track in the language `lang`, falling back to those of `config`
+    /// for any not given
+    fn sub1(&self, config: &Config, lang: Option<&str>) -> TrackOptions {
+        let explicit = TrackOptions {
+            pos_xy: self.sub1_pos_xy,
+            size: self.sub1_size.map(NonZeroU32::get),
+            bold: self.sub1_bold,
+            italic: self.sub1_italic,
+            fps_scale: self.sub1_fps_from.zip(self.sub1_fps_to),
+            offset: self.sub1_offset,
+            offset_from: self.sub1_offset_from,
+            keep_lines: self.sub1_keep_line,
+            ..self.shared()
+        };
+        explicit.or(config.sub1_options(lang))
+    }
+
+    /// The options of the second track in the language `lang`, falling back to its preset, then
+    /// to those of `config` and then to the built-in defaults for any not given
+    fn sub2(&self, config: &Config, lang: Option<&str>) -> Result<TrackOptions> {
+        let explicit = TrackOptions {
+            color: self.color.clone(),
+            position: self.position,
+            pos_xy: self.sub2_pos_xy,
+            size: self.sub2_size.map(NonZeroU32::get),
+            bold: self.sub2_bold,
+            italic: self.sub2_italic,
+            box_color: self.sub2_box_color.clone(),
+            fps_scale: self.sub2_fps_from.zip(self.sub2_fps_to),
+            offset: self.sub2_offset,
+            offset_from: self.sub2_offset_from,
+            anchor: self.sub2_anchor,
+            keep_lines: self.sub2_keep_line,
+            ..self.shared()
+        };
+        let defaults = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+
+        let opts = match &self.sub2_preset {
+            Some(name) => explicit.or(preset(config, name)?),
+            None => explicit,
+        };
+        Ok(opts.or(config.sub2_options(lang)).or(defaults))
+    }
+
+    /// The options of both tracks in the languages `langs`, see `sub1` and `sub2`, with the
+    /// second one moved out of the way of the first with `--auto-stack`
+    fn options(
+        &self,
+        config: &Config,
+        langs: [Option<&str>; 2],
+    ) -> Result<(TrackOptions, TrackOptions)> {
+        let sub1 = self.sub1(config, langs[0]);
+        let mut sub2 = self.sub2(config, langs[1])?;
+        if self.auto_stack {
+            sub2 = auto_stack(&sub1, sub2);
+        }
+        Ok((sub1, sub2))
+    }
+}
+
+fn positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        _ => Err(format!("{s:?} is not a positive number")),
+    }
+}
+
+fn preset(config: &Config, name: &str) -> Result<TrackOptions> {
+    config.preset(name).with_context(|| {
+        format!(
+            "unknown preset {:?}, available presets are: {}",
+            name,
+            config.preset_names().join(", ")
+        )
+    })
+}
+
+/// Options shared by the merging subcommands that control how subtitle files are read
+#[derive(Args)]
+struct LoadArgs {
+    /// Rewrite the sequence numbers of each input to be contiguous, repairing scrambled or duplicated numbers
+    #[arg(long)]
+    renumber_on_load: bool,
+
+    /// Skip input files larger than this many bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_INPUT_BYTES)]
+    max_input_bytes: u64,
+
+    /// Normalize the text of each input to Unicode NFC, so precomposed and decomposed
+    /// characters (e.g. accents) render and match the same
+    #[arg(long)]
+    normalize_unicode: bool,
+
+    /// Remove karaoke timing tags (`{\k20}`, `{\kf20}`, ...) from ASS/SSA inputs, leaving plain text
+    #[arg(long)]
+    strip_karaoke: bool,
+
+    /// End each cue of an input overlapping the cue after it at the start of that cue, instead of
+    /// only warning about it, so a track never shows two of its cues at once
+    #[arg(long)]
+    fix_overlaps: bool,
+
+    /// Keep the placement of VTT cues, translating their `line`, `position` and `align` settings
+    /// to the closest position tag. A position given for the track takes precedence.
+    #[arg(long)]
+    vtt_positions: bool,
+
+    /// Only warn about inputs without any subtitles, rather than failing, e.g. for a track that
+    /// is known to be empty
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Only keep the events of the first subtitle track using this style, if it is an ASS/SSA file
+    #[arg(long)]
+    sub1_style_filter: Option<String>,
+
+    /// Only keep the events of the second subtitle track using this style, if it is an ASS/SSA file
+    #[arg(long)]
+    sub2_style_filter: Option<String>,
+
+    /// The subtitle stream to read if the first subtitle file is an MKV file, counting from 0
+    #[cfg(feature = "mkv")]
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    sub1_track_index: usize,
+
+    /// The subtitle stream to read if the second subtitle file is an MKV file, counting from 0
+    #[cfg(feature = "mkv")]
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    sub2_track_index: usize,
+}
+
+impl LoadArgs {
+    fn shared(&self) -> LoadOptions {
+        LoadOptions {
+            renumber: self.renumber_on_load,
+            max_bytes: self.max_input_bytes,
+            normalize_unicode: self.normalize_unicode,
+            strip_karaoke: self.strip_karaoke,
+            fix_overlaps: self.fix_overlaps,
+            style_filter: None,
+            track_index: 0,
+            vtt_positions: self.vtt_positions,
+        }
+    }
+
+    fn sub1(&self) -> LoadOptions {
+        LoadOptions {
+            style_filter: self.sub1_style_filter.clone(),
+            #[cfg(feature = "mkv")]
+            track_index: self.sub1_track_index,
+            ..self.shared()
+        }
+    }
+
+    fn sub2(&self) -> LoadOptions {
+        LoadOptions {
+            style_filter: self.sub2_style_filter.clone(),
+            #[cfg(feature = "mkv")]
+            track_index: self.sub2_track_index,
+            ..self.shared()
+        }
+    }
+}
+
+/// Options shared by the merging subcommands that control how the output is written
+#[derive(Args)]
+struct OutputArgs {
+    /// How to write output files, `atomic` never leaves partially written files behind,
+    /// but renaming may behave badly on some network filesystems where `truncate` can be used
+    #[arg(long, default_value = "atomic")]
+    write_mode: WriteMode,
+
+    /// The format to write merged subtitles in [default: srt]
+    #[arg(long)]
+    out_format: Option<OutputFormat>,
+
+    /// The line endings to write, whatever those of the inputs are
+    #[arg(long, default_value = "lf")]
+    line_endings: LineEndings,
+
+    /// The character encoding to write, e.g. for hardware players that only read legacy encodings.
+    /// VTT output must be UTF-8
+    #[arg(long, default_value = "utf-8")]
+    out_encoding: OutputEncoding,
+}
+
+impl OutputArgs {
+    /// The output options, falling back to those of `config` for any not given
+    fn options(&self, config: &Config) -> Result<OutputOptions> {
+        let opts = OutputOptions {
+            write_mode: self.write_mode,
+            format: self.out_format.or(config.out_format).unwrap_or_default(),
+            line_endings: self.line_endings,
+            encoding: self.out_encoding,
         };
-        write!(f, "{}", printable)
+        opts.check()?;
+        Ok(opts)
+    }
+}
+
+/// Options shared by the merging subcommands that control how the tracks are combined
+#[derive(Args)]
+struct MergeArgs {
+    /// What to do when a cue from each track has identical timings
+    #[arg(long, default_value = "keep")]
+    on_conflict: ConflictPolicy,
+
+    /// Collapse overlapping cues with the same text into one, e.g. when a track is merged twice
+    #[arg(long)]
+    dedup: bool,
+
+    /// The order of the merged cues, which they are numbered in: interleaved by start time, all
+    /// cues of the first track before those of the second, or in the order of the files
+    #[arg(long, default_value = "time")]
+    sort_by: SortBy,
+
+    /// Combine cues of the tracks overlapping in time at the same position into one cue spanning
+    /// them, with the lines of the first track above those of the second
+    #[arg(long)]
+    combine_overlapping: bool,
+}
+
+impl MergeArgs {
+    fn options(&self) -> MergeOptions {
+        MergeOptions {
+            on_conflict: self.on_conflict,
+            dedup: self.dedup,
+            sort_by: self.sort_by,
+            combine_overlapping: self.combine_overlapping,
+        }
+    }
+}
+
+/// Warn about styling that the output `format` is unable to represent, or that not all players
+/// support
+fn warn_unrepresentable(opts: &TrackOptions, format: OutputFormat) {
+    if opts.box_color.is_some() && format != OutputFormat::Ass {
+        warn!(
+            reason = "unrepresentable_style";
+            "{} output can not represent background boxes, ignoring the box color", format.extension().to_uppercase()
+        );
+    }
+    if let Some(point) = opts.pos_xy {
+        warn!(
+            reason = "renderer_dependent_style";
+            "Placing cues with {} depends on the player rendering ASS tags in SRT files, some show them at the bottom or as text", point
+        );
+    }
+}
+
+/// Move the second track to the neighboring row if it shares its position with the first, see
+/// `--auto-stack`. Tracks without a position are shown at the bottom center.
+fn auto_stack(sub1: &TrackOptions, sub2: TrackOptions) -> TrackOptions {
+    if sub1.pos_xy.is_some() || sub2.pos_xy.is_some() {
+        return sub2;
+    }
+    let taken = sub1.position.unwrap_or_default();
+    if sub2.position.unwrap_or_default() != taken {
+        return sub2;
+    }
+
+    let position = taken.vertical_neighbor();
+    info!(
+        "Both tracks are positioned at {:?}, moving the second track to {:?}",
+        taken, position
+    );
+    TrackOptions {
+        position: Some(position),
+        ..sub2
+    }
+}
+
+/// Start `player` on `media` with the subtitles `subs`, without waiting for it to exit. Without a
+/// `player`, the media is opened in the default player of the system, which can not be told of
+/// the subtitles, so they are only shown if named like the media, e.g. `movie.srt` for `movie.mkv`.
+fn open_in_player(player: Option<&str>, media: &Path, subs: &Path) {
+    let (mut command, name) = match player {
+        Some(player) => {
+            let mut sub_file = OsString::from("--sub-file=");
+            sub_file.push(subs);
+            let mut command = Command::new(player);
+            command.arg(media).arg(sub_file);
+            (command, player)
+        }
+        None => {
+            let loaded = media.with_extension("") == subs.with_extension("");
+            if !loaded {
+                warn!(
+                    reason = "subtitles_not_loaded", path:% = subs.display();
+                    "The default player is only given the media, it loads {:?} only if it is named like {:?}, choose a player with --player", subs, media
+                );
+            }
+            default_opener(media)
+        }
+    };
+
+    match command.spawn() {
+        Ok(_) => info!("Opened {:?} in {}", media, name),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => warn!(
+            reason = "player_not_found";
+            "Unable to find the player {:?}, choose another with --player", name
+        ),
+        Err(e) => warn!(reason = "player_failed"; "Unable to start {:?}: {}", name, e),
+    }
+}
+
+/// The command opening `media` in the default application of the system, and its name
+fn default_opener(media: &Path) -> (Command, &'static str) {
+    let (mut command, name) = if cfg!(target_os = "windows") {
+        // `start` is built into the shell, its first quoted argument is the window title
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        (command, "start")
+    } else if cfg!(target_os = "macos") {
+        (Command::new("open"), "open")
+    } else {
+        (Command::new("xdg-open"), "xdg-open")
+    };
+    command.arg(media);
+    (command, name)
+}
+
+/// Print a report of how the cues of a merged file are laid out, see `--stats`
+fn print_stats(out: &Path, merged: &SubRip) {
+    use std::io::Write;
+
+    // Recursive merges run in parallel, so keep the statistics of each file together
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "Positions in {:?}:", out).unwrap();
+    for (position, count) in position_counts(merged) {
+        match position.and_then(|p| Some((p.to_possible_value()?, p))) {
+            Some((name, tag)) => {
+                writeln!(stdout, "  {:<14} {:<6} {}", name.get_name(), tag, count).unwrap()
+            }
+            None => writeln!(stdout, "  {:<21} {}", "no position", count).unwrap(),
+        }
     }
 }
 
@@ -102,29 +688,138 @@ enum Commands {
         #[arg(required = true)]
         out: PathBuf,
 
-        /// Sets the color for the second subtitle track
-        #[arg(short, long)]
-        color: Option<String>,
+        /// Merge another track, can be repeated. Given as `PATH[,color=..][,position=..][,offset=..]`,
+        /// e.g. `movie.de.srt,color=#fabd2f,position=middle-center`
+        #[arg(long = "track", value_name = "TRACK")]
+        tracks_extra: Vec<ExtraTrack>,
+
+        /// Language code of the first subtitle file (e.g. `en`), selecting its defaults in the
+        /// `[lang]` tables of `--config`
+        #[arg(long, value_name = "LANG")]
+        sub1_lang: Option<String>,
+
+        /// Language code of the second subtitle file (e.g. `ja`), see `--sub1-lang`
+        #[arg(long, value_name = "LANG")]
+        sub2_lang: Option<String>,
+
+        /// Warn if a track extends well beyond the duration of this media file (uses ffprobe)
+        #[cfg(feature = "ffprobe")]
+        #[arg(long, conflicts_with_all = ["streaming", "preserve_style"])]
+        match_media: Option<PathBuf>,
+
+        /// Scale tracks extending beyond the media given by `--match-media` to end with it
+        #[cfg(feature = "ffprobe")]
+        #[arg(long, requires = "match_media")]
+        scale_to_media: bool,
+
+        /// Make the subtitle in the audio language of this media file the first track, swapping
+        /// the tracks if needed. The languages are taken from the file names, e.g. `movie.ja.srt`
+        #[cfg(feature = "ffprobe")]
+        #[arg(long, conflicts_with = "base")]
+        audio_lang_from: Option<PathBuf>,
+
+        /// Color each track light or dark, whichever contrasts most with the video of this media
+        /// file where the track is shown. Uses ffmpeg to sample a few frames.
+        #[cfg(feature = "video")]
+        #[arg(long, conflicts_with_all = ["color", "streaming", "preserve_style"])]
+        auto_contrast_from: Option<PathBuf>,
+
+        /// Treat the first subtitle file as an immutable, possibly hand-styled, base. It gets no
+        /// styling or timing changes, only the second track is styled and merged into it. Its
+        /// cue text is kept verbatim, only the cues are renumbered. This is a flag rather than
+        /// taking the base file, as `<SUB1>` already names it.
+        #[arg(long)]
+        base: bool,
 
-        /// Sets the position of the second subtitle track
-        #[arg(short, long, default_value = "top-center")]
-        position: SubPosition,
+        /// Read the tracks one cue at a time and write each merged cue as soon as it is known,
+        /// rather than holding whole files in memory. Needs SRT files with cues in order, and
+        /// no options that depend on the cues around each cue.
+        #[arg(long, conflicts_with_all = [
+            "on_conflict", "dedup", "sort_by", "combine_overlapping", "stats", "crossref",
+        ])]
+        streaming: bool,
+
+        /// Merge two ASS/SSA files as ASS, keeping the styles, positions and override tags of
+        /// both rather than converting them to SRT. Only timing options apply to the tracks.
+        #[arg(long, conflicts_with_all = [
+            "streaming", "tracks_extra", "position", "auto_stack", "sub2_preset", "on_conflict",
+            "dedup", "sort_by", "combine_overlapping", "out_format", "stats", "crossref",
+        ])]
+        preserve_style: bool,
+
+        #[command(flatten)]
+        loading: LoadArgs,
+
+        #[command(flatten)]
+        tracks: TrackArgs,
+
+        #[command(flatten)]
+        merging: MergeArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Read default track styling and output format from this TOML file, options given on
+        /// the command line take precedence
+        #[arg(long)]
+        config: Option<PathBuf>,
 
         /// Sets the level of logging
         #[arg(short, long, default_value = "warn")]
         log_level: LogLevel,
+
+        /// Also write all warnings as JSON to this file, with a reason code and the concerned file
+        #[arg(long)]
+        warnings_json: Option<PathBuf>,
+
+        /// Print statistics about each merged file, such as how many cues use each position
+        #[arg(long)]
+        stats: bool,
+
+        /// Also write a tab separated file mapping each merged cue to the track and index it came from
+        #[arg(long)]
+        crossref: Option<PathBuf>,
+
+        /// Rather than merging, write the cues of each track as parsed and styled to the output
+        /// file as JSON, with their sequence numbers, timings and text
+        #[arg(long, conflicts_with_all = [
+            "streaming", "preserve_style", "stats", "crossref", "open",
+        ])]
+        dump_json: bool,
+
+        /// Print the first N merged cues to stderr, 5 if no number is given as `--preview=N`,
+        /// without writing the output file unless `--write` is also given
+        #[arg(long, value_name = "N", num_args = 0..=1, require_equals = true,
+            default_missing_value = "5", conflicts_with_all = ["streaming", "preserve_style", "dump_json"])]
+        preview: Option<usize>,
+
+        /// Also write the output file when previewing with `--preview`
+        #[arg(long, requires = "preview")]
+        write: bool,
+
+        /// After merging, play this media file with the merged subtitles
+        #[arg(long)]
+        open: Option<PathBuf>,
+
+        /// The player used by `--open`, which must accept a `--sub-file=<FILE>` argument (e.g. mpv,
+        /// vlc). Defaults to the default player of the system, which is only given the media.
+        #[arg(long, requires = "open")]
+        player: Option<String>,
     },
     /// Recursively merge srt files matching the given specification
     ///
     /// By default, this will search the directory for any files matching
     /// the languages given ("en", "ja", "da", etc), but also match hearing
-    /// impaired subs ("en.hi", "ja.hi", "da.hi", etc) if no normal subs are found
+    /// impaired subs ("en.hi", "en.sdh", etc) if no normal subs are found
     Recursive {
-        /// Language code for the first subtitle file (e.g., `en` for English)
+        /// Language code for the first subtitle file (e.g., `en` for English). May be a glob
+        /// pattern, e.g. `en*` to match `en`, `eng` and `en-US`, or a comma separated list of
+        /// codes, e.g. `en,de`, of which the first one a file exists for is used
         #[arg(required = true)]
         sub1_lang: String,
 
-        /// Language code for the second subtitle file (e.g., `ja` for Japanese)
+        /// Language code for the second subtitle file (e.g., `ja` for Japanese). May be a glob
+        /// pattern or a comma separated list of codes
         #[arg(required = true)]
         sub2_lang: String,
 
@@ -132,29 +827,227 @@ enum Commands {
         #[arg(required = true)]
         path: PathBuf,
 
-        /// The file extension for the output file (e.g. `file.en.srt` -> `file.merged.srt` if set to `merged.srt`)
-        #[arg(short, long, default_value = "srt")]
-        out_ext: String,
+        /// The file extension for the output file (e.g. `file.en.srt` -> `file.merged.srt` if set to `merged.srt`).
+        /// `{lang1}` and `{lang2}` are replaced by the language codes of the files, so `{lang1}-{lang2}.srt` gives `file.en-ja.srt`.
+        /// Defaults to the extension of `--out-format`, prefixed by `{lang1}-{lang2}.` for pairs with a detected language
+        #[arg(short, long)]
+        out_ext: Option<String>,
 
-        /// Also match and convert VTT files. Note, this will not output VTT files, only SRT is supported as output.
+        /// Also match and convert VTT files. Note, this does not output VTT files, see `--out-format` for that.
         #[arg(short, long, default_value = "true")]
         vtt: bool,
 
-        /// Sets the color for the second subtitle track
+        /// Also match gzipped subtitle files, e.g. `movie.en.srt.gz`
+        #[arg(long)]
+        gzip: bool,
+
+        /// Never use hearing impaired subtitles (`hi` and `sdh`), skipping files that only have those
+        #[arg(long)]
+        no_hi: bool,
+
+        /// Also take subtitles with this segment after the language as hearing impaired, like
+        /// `hi`, e.g. `cc` for `movie.en.cc.srt`. Can be given several times.
+        #[arg(
+            long,
+            value_name = "TOKEN",
+            value_parser = clap::builder::NonEmptyStringValueParser::new()
+        )]
+        hi_token: Vec<String>,
+
+        /// Only search the given directory itself, without descending into subdirectories
+        #[arg(long)]
+        flat: bool,
+
+        /// Detect the language of subtitle files without a language code (e.g. `movie.srt`) from
+        /// their text, so they are paired too. Detected languages are two-letter codes like `en`.
+        #[arg(long)]
+        detect_lang: bool,
+
+        /// Only merge subtitle files next to a video file (`.mkv`, `.mp4` or `.avi`) with the
+        /// same name, e.g. `movie.en.srt` next to `movie.mkv`, ignoring stray subtitles
+        #[arg(long)]
+        require_video: bool,
+
+        /// Skip directories whose name or path relative to `PATH` matches this glob pattern,
+        /// e.g. `extras` or `Show/Season 1`, without searching them. Can be repeated.
+        #[arg(long, value_name = "PATTERN")]
+        exclude_dir: Vec<String>,
+
+        /// Write every merged file into this directory instead of next to its sources, named
+        /// like them. Outputs that would share a name are numbered, e.g. `movie (2).en-ja.srt`.
+        #[arg(long, value_name = "DIR")]
+        flatten_output: Option<PathBuf>,
+
+        /// Warn when the time spans of two paired tracks differ by more than this ratio (e.g. `1.5`)
+        #[arg(long)]
+        max_span_ratio: Option<f64>,
+
+        /// When a file has subtitles in only one of the two languages, write that track alone,
+        /// styled as usual, to the output name rather than skipping it
+        #[arg(long)]
+        single_ok: bool,
+
+        /// Skip pairs that fail a sanity check (such as `--max-span-ratio`) instead of only warning
+        #[arg(long)]
+        strict: bool,
+
+        /// Skip pairs whose output file already exists, without reading their inputs, so re-running
+        /// over a growing library only merges the new files and keeps manual edits
+        #[arg(long, conflicts_with = "overwrite")]
+        skip_existing: bool,
+
+        /// Merge pairs whose output file already exists again, replacing it (the default)
+        #[arg(long)]
+        overwrite: bool,
+
+        /// Only log the merges that would be performed, as `SUB1 + SUB2 -> OUT`, without reading
+        /// or writing any subtitle files. Logs at least at the info level.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// How many pairs to merge in parallel [default: the number of CPUs]
         #[arg(short, long)]
+        jobs: Option<NonZeroUsize>,
+
+        /// Use hearing impaired subtitles (`hi`, then `sdh`) over normal ones for both tracks when
+        /// both exist, a shorthand for `--sub1-prefer hi,sdh,normal --sub2-prefer hi,sdh,normal`
+        #[arg(long, conflicts_with_all = ["sub1_prefer", "sub2_prefer", "no_hi"])]
+        prefer_hi: bool,
+
+        /// Which variants of the first track to use when several exist, most preferred first.
+        /// Variants left out are never used, so `normal` only ignores `hi` and `sdh` subtitles
+        #[arg(long, value_delimiter = ',', default_value = "normal,hi,sdh")]
+        sub1_prefer: Vec<Variant>,
+
+        /// Which variants of the second track to use when several exist, most preferred first
+        #[arg(long, value_delimiter = ',', default_value = "normal,hi,sdh")]
+        sub2_prefer: Vec<Variant>,
+
+        #[command(flatten)]
+        loading: LoadArgs,
+
+        #[command(flatten)]
+        tracks: TrackArgs,
+
+        #[command(flatten)]
+        merging: MergeArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        /// Read default track styling and output format from this TOML file, options given on
+        /// the command line take precedence
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Sets the level of logging
+        #[arg(short, long, default_value = "warn")]
+        log_level: LogLevel,
+
+        /// Also write all warnings as JSON to this file, with a reason code and the concerned file
+        #[arg(long)]
+        warnings_json: Option<PathBuf>,
+
+        /// Print statistics about each merged file, such as how many cues use each position
+        #[arg(long)]
+        stats: bool,
+
+        /// Write a JSON list of the merges performed to this file, with the sources, their
+        /// languages, the output and how many cues it has
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Convert a single subtitle file to another format without merging, optionally styling
+    /// or shifting it
+    Convert {
+        /// Path to the subtitle file to convert
+        #[arg(required = true)]
+        input: PathBuf,
+
+        /// Output file where the converted subtitles will be saved
+        #[arg(required = true)]
+        out: PathBuf,
+
+        /// Sets the color of the subtitles, as `#RGB`, `#RRGGBB` or a CSS color name
+        #[arg(short, long, value_parser = parse_color)]
         color: Option<String>,
 
-        /// Sets the position of the second subtitle track
-        #[arg(short, long, default_value = "top-center")]
-        position: SubPosition,
+        /// Sets the position of the subtitles
+        #[arg(short, long)]
+        position: Option<SubPosition>,
+
+        /// Shift the subtitles by this many seconds, negative values make them earlier (e.g. `-1.5`).
+        /// May also be given as `HH:MM:SS.mmm` or `MM:SS.mmm`
+        #[arg(long, allow_hyphen_values = true, value_parser = parse_offset)]
+        offset: Option<f64>,
+
+        #[command(flatten)]
+        output: OutputArgs,
 
         /// Sets the level of logging
         #[arg(short, long, default_value = "warn")]
         log_level: LogLevel,
     },
+    /// Draw the cues of two merged subtitle files shown at a given time to a PNG, to check
+    /// their positions, colors and overlap without a video player
+    #[cfg(feature = "preview")]
+    PreviewRender {
+        /// Path to the first subtitle file
+        #[arg(required = true)]
+        sub1: PathBuf,
+
+        /// Path to the second subtitle file
+        #[arg(required = true)]
+        sub2: PathBuf,
+
+        /// The time to draw, as `HH:MM:SS` or `HH:MM:SS.mmm`
+        #[arg(long, value_parser = parse_time)]
+        at: Duration,
+
+        /// Path of the PNG to write
+        #[arg(short, long, required = true)]
+        out: PathBuf,
+
+        #[command(flatten)]
+        loading: LoadArgs,
+
+        #[command(flatten)]
+        tracks: TrackArgs,
+
+        #[command(flatten)]
+        merging: MergeArgs,
+
+        /// Sets the level of logging
+        #[arg(short, long, default_value = "warn")]
+        log_level: LogLevel,
+    },
+    /// Print the supported subtitle formats and positions
+    Info {
+        /// Print the information as JSON, useful for building front-ends
+        #[arg(short, long)]
+        json: bool,
+    },
 }
 
-fn main() -> Result<()> {
+/// Exit code for inputs in a format submerger can not read, to tell them apart from other
+/// failures such as missing files, which exit with 1
+const EXIT_UNSUPPORTED_FORMAT: u8 = 3;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if e.chain().any(|cause| cause.is::<UnsupportedFormat>()) {
+                ExitCode::from(EXIT_UNSUPPORTED_FORMAT)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.subcommand {
@@ -162,72 +1055,551 @@ fn main() -> Result<()> {
             sub1,
             sub2,
             out,
-            color,
-            position,
+            tracks_extra,
+            sub1_lang,
+            sub2_lang,
+            #[cfg(feature = "ffprobe")]
+            match_media,
+            #[cfg(feature = "ffprobe")]
+            scale_to_media,
+            #[cfg(feature = "ffprobe")]
+            audio_lang_from,
+            #[cfg(feature = "video")]
+            auto_contrast_from,
+            base,
+            streaming,
+            preserve_style,
+            loading,
+            tracks,
+            merging,
+            output,
+            config,
             log_level,
+            warnings_json,
+            stats,
+            crossref,
+            dump_json,
+            preview,
+            write,
+            open,
+            player,
         } => {
-            simple_logger::init_with_level(log_level.into())?;
+            init_logging(log_level.into(), warnings_json.is_some())?;
+            let config = config
+                .as_deref()
+                .map(load_config)
+                .transpose()?
+                .unwrap_or_default();
+            let out_opts = output.options(&config)?;
+
+            #[cfg(feature = "ffprobe")]
+            let ((sub1, sub1_lang), (sub2, sub2_lang)) = match &audio_lang_from {
+                Some(media) if media::swap_for_audio_language(media, &sub1, &sub2)? => {
+                    ((sub2, sub2_lang), (sub1, sub1_lang))
+                }
+                _ => ((sub1, sub1_lang), (sub2, sub2_lang)),
+            };
+
+            if sub1_lang.is_some() || sub2_lang.is_some() {
+                let lang = |l: &Option<String>| l.clone().unwrap_or_else(|| "?".to_owned());
+                info!(
+                    "Merging {:?} ({}) with {:?} ({})",
+                    sub1,
+                    lang(&sub1_lang),
+                    sub2,
+                    lang(&sub2_lang)
+                );
+            }
 
-            let merged = merge(&load_sub(sub1)?, &load_sub(sub2)?, color, position);
+            let langs = [sub1_lang.as_deref(), sub2_lang.as_deref()];
+            let (sub1_opts, sub2_opts) = tracks.options(&config, langs)?;
+            let sub1_opts = sub1_opts.for_variant(Variant::from_path(&sub1));
+            let sub2_opts = sub2_opts.for_variant(Variant::from_path(&sub2));
+            warn_unrepresentable(&sub1_opts, out_opts.format);
+            warn_unrepresentable(&sub2_opts, out_opts.format);
+
+            if preserve_style {
+                let mut ass1 = load_ass(&sub1, &loading.sub1())?;
+                let mut ass2 = load_ass(&sub2, &loading.sub2())?;
+                check_not_empty(ass1.events.is_empty(), &sub1, loading.allow_empty)?;
+                check_not_empty(ass2.events.is_empty(), &sub2, loading.allow_empty)?;
+                if !base {
+                    apply_ass_changes(&mut ass1, &sub1_opts)?;
+                }
+                apply_ass_changes(&mut ass2, &sub2_opts)?;
+                let merged = merge_ssa(ass1, ass2)?;
+                write_ass(&out, &merged, &out_opts)?;
 
-            let mut file = File::create(&out)?;
-            file.write_all(merged.render().as_bytes())?;
+                info!("Successfully merged subtitles into {:?}", out);
+                if let Some(media) = &open {
+                    open_in_player(player.as_deref(), media, &out);
+                }
+                if let Some(p) = &warnings_json {
+                    write_warnings(p)?;
+                }
+                return Ok(());
+            }
+
+            if streaming {
+                let sub1_opts = if base {
+                    TrackOptions::default()
+                } else {
+                    sub1_opts
+                };
+                let mut streams = vec![
+                    (stream_sub(&sub1, &loading.sub1())?, sub1_opts),
+                    (stream_sub(&sub2, &loading.sub2())?, sub2_opts),
+                ];
+                for extra in &tracks_extra {
+                    let opts = extra
+                        .options(tracks.shared())
+                        .for_variant(Variant::from_path(&extra.path));
+                    warn_unrepresentable(&opts, out_opts.format);
+                    streams.push((stream_sub(&extra.path, &loading.shared())?, opts));
+                }
+                for (stream, _) in &streams {
+                    check_not_empty(stream.is_empty(), stream.path(), loading.allow_empty)?;
+                }
+                let count = merge_streams(streams, &out, &out_opts)?;
+
+                info!("Successfully merged {} subtitles into {:?}", count, out);
+                if let Some(media) = &open {
+                    open_in_player(player.as_deref(), media, &out);
+                }
+                if let Some(p) = &warnings_json {
+                    write_warnings(p)?;
+                }
+                return Ok(());
+            }
+
+            // Parsing large files takes a while, so read both at once
+            let (srt1, srt2) = rayon::join(
+                || load_sub(&sub1, &loading.sub1()),
+                || load_sub(&sub2, &loading.sub2()),
+            );
+            let (mut srt1, mut srt2) = (srt1?, srt2?);
+            check_not_empty(srt1.subtitles.is_empty(), &sub1, loading.allow_empty)?;
+            check_not_empty(srt2.subtitles.is_empty(), &sub2, loading.allow_empty)?;
+
+            #[cfg(feature = "ffprobe")]
+            if let Some(media) = &match_media {
+                let duration = media::media_duration(media)?;
+                if !base {
+                    media::check_against_media(&mut srt1, &sub1, duration, scale_to_media);
+                }
+                media::check_against_media(&mut srt2, &sub2, duration, scale_to_media);
+            }
+
+            #[cfg(feature = "video")]
+            let (sub1_opts, sub2_opts) = match &auto_contrast_from {
+                Some(media) if base => {
+                    (sub1_opts, contrast::with_contrast(media, &srt2, sub2_opts)?)
+                }
+                Some(media) => (
+                    contrast::with_contrast(media, &srt1, sub1_opts)?,
+                    contrast::with_contrast(media, &srt2, sub2_opts)?,
+                ),
+                None => (sub1_opts, sub2_opts),
+            };
+
+            if !base {
+                apply_sub_changes(&mut srt1, &sub1_opts);
+            }
+            apply_sub_changes(&mut srt2, &sub2_opts);
+
+            let mut srts = vec![srt1, srt2];
+            for extra in &tracks_extra {
+                let opts = extra
+                    .options(tracks.shared())
+                    .for_variant(Variant::from_path(&extra.path));
+                warn_unrepresentable(&opts, out_opts.format);
+                let mut srt = load_sub(&extra.path, &loading.shared())?;
+                check_not_empty(srt.subtitles.is_empty(), &extra.path, loading.allow_empty)?;
+                apply_sub_changes(&mut srt, &opts);
+                srts.push(srt);
+            }
+
+            if dump_json {
+                let paths = [&sub1, &sub2]
+                    .into_iter()
+                    .chain(tracks_extra.iter().map(|extra| &extra.path));
+                let dumped: Vec<_> = paths
+                    .zip(&srts)
+                    .map(|(path, srt)| DumpedTrack::new(path, srt))
+                    .collect();
+                submerger::dump_json(&out, &dumped)?;
+                info!("Wrote the cues of {} tracks to {:?}", dumped.len(), out);
+                if let Some(p) = &warnings_json {
+                    write_warnings(p)?;
+                }
+                return Ok(());
+            }
+            let (merged, sources, merge_stats) = merge_tracks_with_stats(srts, &merging.options());
+            info!("{}", merge_stats);
+
+            if let Some(n) = preview {
+                eprint!("{}", preview_cues(&merged, n));
+                if !write {
+                    if let Some(p) = &warnings_json {
+                        write_warnings(p)?;
+                    }
+                    return Ok(());
+                }
+            }
+
+            write_sub(&out, &merged, &out_opts)?;
+            if let Some(p) = &crossref {
+                write_crossref(p, &sources)?;
+            }
+            if stats {
+                print_stats(&out, &merged);
+            }
 
             info!("Successfully merged subtitles into {:?}", out);
+            if let Some(media) = &open {
+                open_in_player(player.as_deref(), media, &out);
+            }
+
+            if let Some(p) = &warnings_json {
+                write_warnings(p)?;
+            }
         }
         Commands::Recursive {
             path,
             sub1_lang,
             sub2_lang,
-            color,
-            position,
+            loading,
+            tracks,
+            merging,
+            output,
+            config,
             log_level,
+            warnings_json,
+            stats,
+            manifest,
             out_ext,
             vtt,
+            gzip,
+            no_hi,
+            hi_token,
+            flat,
+            detect_lang,
+            require_video,
+            exclude_dir,
+            flatten_output,
+            single_ok,
+            max_span_ratio,
+            strict,
+            dry_run,
+            skip_existing,
+            overwrite: _,
+            jobs,
+            prefer_hi,
+            sub1_prefer,
+            sub2_prefer,
         } => {
-            simple_logger::init_with_level(log_level.into())?;
-
-            let matches = find_matching_subtitle_files(&path, &sub1_lang, &sub2_lang, vtt)?;
-
-            for (dir, subs) in matches {
-                for sub1 in &subs {
-                    let mut l1 = None;
-                    let mut l2 = None;
-
-                    for sub2 in &subs {
-                        if base_file_stem(&sub1.path)? == base_file_stem(&sub2.path)?
-                            && sub1.lang == sub1_lang
-                            && sub2.lang == sub2_lang
-                        {
-                            if !sub1.hi || l1.is_none() {
-                                l1 = Some(sub1.clone())
-                            }
-                            if !sub2.hi || l2.is_none() {
-                                l2 = Some(sub2.clone())
+            let level = log::Level::from(log_level);
+            let level = if dry_run {
+                level.max(log::Level::Info)
+            } else {
+                level
+            };
+            init_logging(level, warnings_json.is_some())?;
+            let config = config
+                .as_deref()
+                .map(load_config)
+                .transpose()?
+                .unwrap_or_default();
+
+            let (sub1_load, sub2_load) = (loading.sub1(), loading.sub2());
+            let out_opts = output.options(&config)?;
+            // Warn about the options of the command line and `--config` once rather than for
+            // every pair, which may only differ by their languages
+            let (sub1_opts, sub2_opts) = tracks.options(&config, [None, None])?;
+            warn_unrepresentable(&sub1_opts, out_opts.format);
+            warn_unrepresentable(&sub2_opts, out_opts.format);
+
+            let search = SearchOptions {
+                find_vtt: vtt,
+                gzip,
+                no_hi,
+                hi_tokens: hi_token,
+                flat,
+                detect_lang,
+                require_video,
+                exclude_dirs: exclude_dir,
+            };
+            let sub1_lang: Vec<String> = sub1_lang.split(',').map(|l| l.to_owned()).collect();
+            let sub2_lang: Vec<String> = sub2_lang.split(',').map(|l| l.to_owned()).collect();
+            let langs: Vec<&str> = sub1_lang
+                .iter()
+                .chain(&sub2_lang)
+                .map(|l| l.as_str())
+                .collect();
+            let matches = find_subtitle_files(&path, &langs, &search)?;
+            let (sub1_prefer, sub2_prefer) = match prefer_hi {
+                true => (PREFER_HI, PREFER_HI),
+                false => (&sub1_prefer[..], &sub2_prefer[..]),
+            };
+
+            // Pair up the tracks first, so the pairs can be merged in parallel
+            let mut pairs = Vec::new();
+            let mut unpaired = Vec::new();
+            for (dir, subs) in &matches {
+                let found = pair_subs(
+                    subs,
+                    &sub1_lang,
+                    &sub2_lang,
+                    sub1_prefer,
+                    sub2_prefer,
+                    single_ok,
+                );
+                if found.is_empty() {
+                    let mut langs: Vec<_> = subs.iter().map(|s| s.code.as_str()).collect();
+                    langs.sort_unstable();
+                    langs.dedup();
+                    unpaired.push((dir, langs));
+                }
+                pairs.extend(found.into_iter().map(|(s1, s2)| (dir, s1, s2)));
+            }
+
+            if let Some(flat) = &flatten_output
+                && !dry_run
+            {
+                fs::create_dir_all(flat)
+                    .context(format!("unable to create output directory {:?}", flat))?;
+            }
+            // Name the outputs up front, so outputs colliding in `--flatten-output` are numbered
+            // the same way on every run rather than by which merge finishes first
+            let first = |(_, s1, s2): &(_, Option<_>, Option<_>)| -> &SubFile {
+                s1.or(*s2).expect("pairs have at least one track")
+            };
+            pairs.sort_by(|a, b| first(a).path.cmp(&first(b).path));
+            let mut taken = HashSet::new();
+            let mut outputs = Vec::with_capacity(pairs.len());
+            for pair @ &(dir, s1, s2) in &pairs {
+                // Create extension for new file, e.g. "en-ja.srt". A track missing under
+                // `--single-ok` is named by the first language asked for. The name of a track
+                // whose language was detected has no language code, so by default the output
+                // is named by both codes, lest it be named like that track
+                let detected = [s1, s2].iter().flatten().any(|s| s.detected);
+                let template = match &out_ext {
+                    Some(ext) => ext.clone(),
+                    None if detected => {
+                        format!("{{lang1}}-{{lang2}}.{}", out_opts.format.extension())
+                    }
+                    None => out_opts.format.extension().to_owned(),
+                };
+                let code1 = s1.map_or(&sub1_lang[0], |s| &s.code);
+                let code2 = s2.map_or(&sub2_lang[0], |s| &s.code);
+                let ext = output_extension(&template, code1, code2);
+                let name = first(pair).stem.with_extension(&ext);
+                outputs.push(match &flatten_output {
+                    Some(flat) => flattened_output(flat, &name, &ext, &mut taken),
+                    None => dir.join(name),
+                });
+            }
+
+            let merges = Mutex::new(Vec::new());
+            let merge_pair =
+                |(s1, s2, out): (Option<&SubFile>, Option<&SubFile>, PathBuf)| -> Result<()> {
+                    let langs = [s1.map(|s| s.code.as_str()), s2.map(|s| s.code.as_str())];
+                    let (sub1_opts, sub2_opts) = tracks.options(&config, langs)?;
+                    // The tracks to write, only one of them if the other is missing under `--single-ok`
+                    let tracks: Vec<_> = [(s1, &sub1_load, sub1_opts), (s2, &sub2_load, sub2_opts)]
+                        .into_iter()
+                        .filter_map(|(sub, load, opts)| Some((sub?, load, opts)))
+                        .collect();
+                    let sources: Vec<_> = tracks.iter().map(|(sub, _, _)| *sub).collect();
+
+                    if overwrites_input(&out, &sources) {
+                        warn!(
+                            reason = "output_is_input", path:% = out.display();
+                            "Skipping pair: the output {:?} is one of its inputs, choose another --out-ext", out
+                        );
+                        return Ok(());
+                    }
+                    if skip_existing && exists_already(&out) {
+                        return Ok(());
+                    }
+                    if dry_run {
+                        let inputs: Vec<_> = sources
+                            .iter()
+                            .map(|s| s.path.display().to_string())
+                            .collect();
+                        info!("{} -> {}", inputs.join(" + "), out.display());
+                        return Ok(());
+                    }
+
+                    let mut loaded = Vec::with_capacity(tracks.len());
+                    for (sub, load, _) in &tracks {
+                        match load_sub_file(sub, load) {
+                            Ok(srt) => loaded.push(srt),
+                            Err(e) => {
+                                let e = e.downcast::<InputTooLarge>()?;
+                                warn!(reason = "input_too_large", path:% = e.path.display(); "Skipping pair: {}", e);
+                                return Ok(());
                             }
                         }
                     }
+                    for (srt, sub) in loaded.iter().zip(&sources) {
+                        check_not_empty(srt.subtitles.is_empty(), &sub.path, loading.allow_empty)?;
+                    }
 
-                    // If we have found lang each for a file, continue
-                    if let Some(s1) = l1
-                        && let Some(s2) = l2
+                    if let Some(max) = max_span_ratio
+                        && let [sub1, sub2] = &loaded[..]
+                        && let [s1, s2] = &sources[..]
                     {
-                        let sub1 = load_sub(s1.path.clone())?;
-                        let sub2 = load_sub(s2.path.clone())?;
-
-                        // Create extension for new file, e.g. "enja"
-                        let no_ext = base_file_stem(&s1.path)?;
-                        let out = dir.join(no_ext.with_extension(&out_ext));
+                        let ratio = span_ratio(sub1, sub2);
+                        if ratio > max {
+                            warn!(
+                                reason = "span_mismatch", path:% = s2.path.display();
+                                "Spans of {:?} and {:?} differ by a ratio of {:.2}, which exceeds {}",
+                                s1.path, s2.path, ratio, max
+                            );
+                            if strict {
+                                return Ok(());
+                            }
+                        }
+                    }
 
-                        info!("Writing subs to {:?}", out);
+                    info!("Writing subs to {:?}", out);
 
-                        // Create extension for new file, e.g. "enja"
-                        let merged = merge(&sub1, &sub2, color.clone(), position);
-                        let mut file = File::create(&out)?;
-                        file.write_all(merged.render().as_bytes())?;
+                    for (srt, (sub, _, opts)) in loaded.iter_mut().zip(&tracks) {
+                        apply_sub_changes(srt, &opts.clone().for_variant(sub.variant));
                     }
+                    let (merged, _, merge_stats) =
+                        merge_tracks_with_stats(loaded, &merging.options());
+                    info!("{:?}: {}", out, merge_stats);
+                    match write_sub(&out, &merged, &out_opts) {
+                        Ok(()) => (),
+                        Err(e) => {
+                            let e = e.downcast::<OutputExists>()?;
+                            warn!(reason = "output_exists", path:% = out.display(); "Skipping pair: {}", e);
+                            return Ok(());
+                        }
+                    }
+                    if stats {
+                        print_stats(&out, &merged);
+                    }
+                    merges.lock().unwrap().push(ManifestEntry {
+                        sources: sources.into_iter().map(ManifestSource::from).collect(),
+                        cues: merged.subtitles.len(),
+                        output: out,
+                    });
+                    Ok(())
+                };
+
+            // The bar would be torn apart by the lines logged for every pair at debug level
+            let progress = match level < log::Level::Debug && io::stdout().is_terminal() {
+                true => {
+                    ProgressBar::new(pairs.len() as u64).with_style(ProgressStyle::with_template(
+                        "[{elapsed_precise}] {wide_bar} {pos}/{len} pairs",
+                    )?)
+                }
+                false => ProgressBar::hidden(),
+            };
+
+            // Each pair is written to its own output file, so they can be merged independently
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs.map_or(0, NonZeroUsize::get))
+                .build()?;
+            let errors: Vec<anyhow::Error> = pool.install(|| {
+                pairs
+                    .par_iter()
+                    .zip(&outputs)
+                    .filter_map(|(pair, out)| {
+                        let result = merge_pair((pair.1, pair.2, out.clone()))
+                            .with_context(|| format!("failed to merge {:?}", first(pair).path));
+                        progress.inc(1);
+                        result.err()
+                    })
+                    .collect()
+            });
+            progress.finish_and_clear();
+
+            // Tell where subtitles are missing, rather than skipping those directories silently
+            if !unpaired.is_empty() {
+                unpaired.sort();
+                info!(
+                    "{} directories had subtitles but no pair to merge:",
+                    unpaired.len()
+                );
+                for (dir, langs) in &unpaired {
+                    info!("  {}: found only {}", dir.display(), langs.join(", "));
                 }
             }
+
+            if let Some(p) = &manifest {
+                write_manifest(p, merges.into_inner().unwrap())?;
+            }
+            if let Some(p) = &warnings_json {
+                write_warnings(p)?;
+            }
+
+            for e in &errors {
+                error!("{:#}", e);
+            }
+            if !errors.is_empty() {
+                bail!("{} of {} pairs failed to merge", errors.len(), pairs.len());
+            }
+        }
+        Commands::Convert {
+            input,
+            out,
+            color,
+            position,
+            offset,
+            output,
+            log_level,
+        } => {
+            init_logging(log_level.into(), false)?;
+
+            let mut srt = load_sub(&input, &LoadOptions::default())?;
+            let opts = TrackOptions {
+                color,
+                position,
+                offset,
+                ..Default::default()
+            };
+            apply_sub_changes(&mut srt, &opts);
+            write_sub(&out, &srt, &output.options(&Config::default())?)?;
+
+            info!("Successfully converted {:?} into {:?}", input, out);
+        }
+        #[cfg(feature = "preview")]
+        Commands::PreviewRender {
+            sub1,
+            sub2,
+            at,
+            out,
+            loading,
+            tracks,
+            merging,
+            log_level,
+        } => {
+            init_logging(log_level.into(), false)?;
+
+            let mut srt1 = load_sub(&sub1, &loading.sub1())?;
+            let mut srt2 = load_sub(&sub2, &loading.sub2())?;
+            let config = Config::default();
+            let (sub1_opts, sub2_opts) = tracks.options(&config, [None, None])?;
+            let sub1_opts = sub1_opts.for_variant(Variant::from_path(&sub1));
+            let sub2_opts = sub2_opts.for_variant(Variant::from_path(&sub2));
+            apply_sub_changes(&mut srt1, &sub1_opts);
+            apply_sub_changes(&mut srt2, &sub2_opts);
+            let merged = merge(srt1, srt2, &merging.options());
+
+            render::render_frame(&merged, at).write_png(&out)?;
+            info!("Rendered the cues at {:?} to {:?}", at, out);
+        }
+        Commands::Info { json } => {
+            let info = Info::new();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                print!("{}", info);
+            }
         }
     }
 
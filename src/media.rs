@@ -0,0 +1,205 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path, process::Command, time::Duration};
+use subtp::srt::SubRip;
+
+use crate::{language_segment, scale};
+
+/// How far past the end of the media a track may extend before it is considered mistimed
+const MEDIA_TOLERANCE: f64 = 1.05;
+
+/// Run `ffprobe` on `path` with `args`, returning its output
+fn ffprobe(path: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error"])
+        .args(args)
+        .arg(path)
+        .output()
+        .context("unable to run ffprobe, make sure it is installed and on the PATH")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed on {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Get the duration of a media file by running `ffprobe`
+pub fn media_duration(path: &Path) -> Result<Duration> {
+    let output = ffprobe(
+        path,
+        &[
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ],
+    )?;
+
+    let secs: f64 = output
+        .trim()
+        .parse()
+        .context(format!("unable to parse the duration of {:?}", path))?;
+    Ok(Duration::from_secs_f64(secs))
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    #[serde(default)]
+    disposition: HashMap<String, u8>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Get the language of the default audio stream of a media file, or of its first audio stream
+/// if none is marked as the default. `None` if the stream has no language tag.
+pub fn audio_language(path: &Path) -> Result<Option<String>> {
+    let output = ffprobe(
+        path,
+        &[
+            "-select_streams",
+            "a",
+            "-show_entries",
+            "stream_disposition=default:stream_tags=language",
+            "-of",
+            "json",
+        ],
+    )?;
+    let probe: Probe = serde_json::from_str(&output)
+        .context(format!("unable to parse the streams of {:?}", path))?;
+
+    let stream = probe
+        .streams
+        .iter()
+        .find(|s| s.disposition.get("default") == Some(&1))
+        .or(probe.streams.first());
+    Ok(stream
+        .and_then(|s| s.tags.get("language"))
+        .filter(|lang| *lang != "und")
+        .cloned())
+}
+
+/// ISO 639-1 codes, as used in subtitle file names, with the ISO 639-2 codes media files are
+/// tagged with. Both the terminological and bibliographic 639-2 codes are listed.
+const LANGUAGE_CODES: &[(&str, &str, &str)] = &[
+    ("ar", "ara", "ara"),
+    ("cs", "ces", "cze"),
+    ("da", "dan", "dan"),
+    ("de", "deu", "ger"),
+    ("el", "ell", "gre"),
+    ("en", "eng", "eng"),
+    ("es", "spa", "spa"),
+    ("fi", "fin", "fin"),
+    ("fr", "fra", "fre"),
+    ("he", "heb", "heb"),
+    ("hi", "hin", "hin"),
+    ("hu", "hun", "hun"),
+    ("it", "ita", "ita"),
+    ("ja", "jpn", "jpn"),
+    ("ko", "kor", "kor"),
+    ("nl", "nld", "dut"),
+    ("no", "nor", "nor"),
+    ("pl", "pol", "pol"),
+    ("pt", "por", "por"),
+    ("ru", "rus", "rus"),
+    ("sv", "swe", "swe"),
+    ("th", "tha", "tha"),
+    ("tr", "tur", "tur"),
+    ("uk", "ukr", "ukr"),
+    ("vi", "vie", "vie"),
+    ("zh", "zho", "chi"),
+];
+
+/// Whether two language codes name the same language, e.g. `ja` and `jpn`
+pub fn same_language(a: &str, b: &str) -> bool {
+    let canonical = |code: &str| {
+        let code = code.to_ascii_lowercase();
+        LANGUAGE_CODES
+            .iter()
+            .find(|(one, t, b)| *one == code || *t == code || *b == code)
+            .map(|(one, _, _)| one.to_string())
+            .unwrap_or(code)
+    };
+    canonical(a) == canonical(b)
+}
+
+/// The language of a subtitle file from its name, e.g. `ja` for `movie.ja.srt`
+pub fn file_language(path: &Path) -> Option<&str> {
+    language_segment(path.file_name()?.to_str()?)
+}
+
+/// Whether to swap the tracks, so that the first track is the subtitle in the audio language
+/// of `media`. The languages of the subtitles are taken from their names.
+pub fn swap_for_audio_language(media: &Path, sub1: &Path, sub2: &Path) -> Result<bool> {
+    let Some(audio) = audio_language(media)? else {
+        warn!(
+            reason = "unknown_audio_language", path:% = media.display();
+            "{:?} has no audio language tag, keeping the order of the tracks", media
+        );
+        return Ok(false);
+    };
+    let matches = |p: &Path| file_language(p).is_some_and(|lang| same_language(lang, &audio));
+
+    match (matches(sub1), matches(sub2)) {
+        (true, _) => Ok(false),
+        (false, true) => {
+            info!(
+                "{:?} is in the audio language ({}), making it the first track",
+                sub2, audio
+            );
+            Ok(true)
+        }
+        (false, false) => {
+            warn!(
+                reason = "audio_language_mismatch", path:% = media.display();
+                "Neither {:?} nor {:?} is in the audio language ({}) of {:?}, keeping the order of the tracks",
+                sub1, sub2, audio, media
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Warn if the last cue of `srt` ends well after `duration`.
+///
+/// This is a strong hint that the subtitles were timed for a different framerate or cut. If
+/// `rescale` is set, the track is scaled so that its last cue ends with the media instead.
+pub fn check_against_media(srt: &mut SubRip, name: &Path, duration: Duration, rescale: bool) {
+    let end = last_end(srt);
+    if end.as_secs_f64() <= duration.as_secs_f64() * MEDIA_TOLERANCE {
+        return;
+    }
+
+    warn!(
+        reason = "media_duration_mismatch", path:% = name.display();
+        "The last cue of {:?} ends at {:.1}s, well after the media ends at {:.1}s",
+        name,
+        end.as_secs_f64(),
+        duration.as_secs_f64()
+    );
+    if rescale {
+        let factor = duration.as_secs_f64() / end.as_secs_f64();
+        info!("Scaling {:?} by {:.4} to match the media", name, factor);
+        scale(srt, factor);
+    }
+}
+
+/// The latest end of any cue in `srt`
+fn last_end(srt: &SubRip) -> Duration {
+    srt.subtitles
+        .iter()
+        .map(|s| s.end.into())
+        .max()
+        .unwrap_or(Duration::ZERO)
+}
@@ -1,72 +1,450 @@
 use anyhow::{bail, Context, Result};
-use log::{info, trace};
+use chardetng::EncodingDetector;
+use encoding_rs::EncoderResult;
+use flate2::read::GzDecoder;
+use log::{info, trace, warn};
 use regex::Regex;
+use serde::Serialize;
 use std::{
-    collections::HashMap,
-    fs,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+    time::Duration,
 };
 use subtp::{
     srt::{SrtSubtitle, SrtTimestamp, SubRip},
     vtt::{VttBlock, WebVtt},
 };
+use unicode_normalization::UnicodeNormalization;
 use walkdir::WalkDir;
 
-use crate::SubPosition;
+use crate::{
+    ass::{ass_color, Ass},
+    lang::detect_language,
+    sami,
+    vtt::cue_position,
+    SubPosition,
+};
+
+/// File extensions that `load_sub` knows how to parse.
+#[cfg(not(feature = "mkv"))]
+pub const INPUT_FORMATS: &[&str] = &["srt", "vtt", "ass", "ssa"];
+/// File extensions that `load_sub` knows how to parse, including MKV files to extract a
+/// subtitle stream from.
+#[cfg(feature = "mkv")]
+pub const INPUT_FORMATS: &[&str] = &["srt", "vtt", "ass", "ssa", "mkv"];
+
+/// File formats that merged subtitles can be written as.
+pub const OUTPUT_FORMATS: &[&str] = &["srt", "vtt", "smi", "ass"];
+
+/// The kind of a subtitle file, from the segment after its language (`movie.en.sdh.srt`)
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq, Hash)]
+pub enum Variant {
+    /// No segment, e.g. `movie.en.srt`
+    #[default]
+    Normal,
+    /// Hearing impaired, e.g. `movie.en.hi.srt`
+    Hi,
+    /// Subtitles for the deaf and hard of hearing, e.g. `movie.en.sdh.srt`
+    Sdh,
+}
+
+impl Variant {
+    fn from_segment(segment: Option<&str>) -> Self {
+        match segment {
+            Some("sdh") => Variant::Sdh,
+            // `hi`, or one of `SearchOptions::hi_tokens`
+            Some(_) => Variant::Hi,
+            None => Variant::Normal,
+        }
+    }
 
+    /// The variant marked in the name of a subtitle file, e.g. `Hi` for `movie.en.hi.srt`
+    pub fn from_path(p: &Path) -> Self {
+        let name = p.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let captures = SUB_SUFFIX.captures(name);
+        Variant::from_segment(
+            captures
+                .as_ref()
+                .and_then(|c| c.name("hearing"))
+                .map(|m| m.as_str()),
+        )
+    }
+}
+
+/// Variant preference picking hearing impaired subtitles over normal ones when both exist
+pub const PREFER_HI: &[Variant] = &[Variant::Hi, Variant::Sdh, Variant::Normal];
+
+/// A subtitle file found by `find_matching_subtitle_files`, e.g. `movie.en.hi.srt`
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SubFile {
+    /// Path of the file, or of its first part if it is split into parts
     pub path: PathBuf,
+    /// The language the file was matched as, as given to `find_matching_subtitle_files`.
+    /// This is a pattern such as `en*` if one was given.
     pub lang: String,
-    pub hi: bool,
+    /// Language code of the file name, e.g. `en-US`
+    pub code: String,
+    /// Whether the file name marks the subtitles as being for the hearing impaired
+    pub variant: Variant,
+    /// All files of the subtitle in order, if it is split into parts (`movie.en.part1.srt`, ...).
+    /// Empty for subtitles consisting of a single file.
+    pub parts: Vec<PathBuf>,
+    /// Whether the language was detected from the text, as the file name has no language code,
+    /// see `SearchOptions::detect_lang`
+    pub detected: bool,
+    /// The file name without the segments `find_subtitle_files` matches, see `base_file_stem`.
+    /// For files whose language was detected, this is the name without its extension.
+    pub stem: PathBuf,
 }
 
 /// Matches a subtitle file of either `.srt` or `.vtt` for the specified languages
 /// for example `movie.en.srt` or `movie.ja.srt` if the languages are `en` and `ja`.
+/// The languages may be glob patterns, see `lang_pattern`. The language matched is captured
+/// as `lang1`, `lang2`, ... in the order of `langs`, the first one winning if several match.
+/// With `gzip`, gzipped files such as `movie.en.srt.gz` are matched too. The `hi_tokens` are
+/// matched literally as hearing impaired segments besides `hi` and `sdh`, e.g. `cc`.
 ///
 /// Yes, this is awful. I hate regex. Without variables it's:
 ///
-/// > `r"[^\.]+\.(?P<lang>en|ja)(\.(?P<hearing>hi))?\.(?P<ext>srt|vtt)$"`
+/// > `r"[^\.]+\.(?P<lang>(?P<lang1>en)|(?P<lang2>ja))(\.(?P<hearing>hi|sdh))?(\.part(?P<part>\d+))?\.(?P<ext>srt|vtt)(?P<gz>\.gz)?$"`
 ///
 /// Which is still not good, but see the corresponding test to see how it behaves in more detail.
-pub fn get_sub_path_regex(lang1: &String, lang2: &String, find_vtt: bool) -> String {
-    let langs = lang1.to_owned() + "|" + lang2;
+pub fn get_sub_path_regex(
+    langs: &[&str],
+    find_vtt: bool,
+    gzip: bool,
+    hi_tokens: &[String],
+) -> String {
+    let langs: Vec<String> = langs
+        .iter()
+        .enumerate()
+        .map(|(i, lang)| format!("(?P<lang{}>{})", i + 1, lang_pattern(lang)))
+        .collect();
     let ext = if find_vtt { "srt|vtt" } else { "srt" };
-    r"[^\.]+\.(?P<lang>".to_owned() + &langs + r")(\.(?P<hearing>hi))?\.(?P<ext>" + ext + ")$"
+    let gz = if gzip { r"(?P<gz>\.gz)?" } else { "" };
+    r"[^\.]+\.(?P<lang>".to_owned()
+        + &langs.join("|")
+        + r")(\.(?P<hearing>"
+        + &hearing_pattern(hi_tokens)
+        + r"))?(\.part(?P<part>\d+))?\.(?P<ext>"
+        + ext
+        + ")"
+        + gz
+        + "$"
+}
+
+/// The alternation of hearing impaired segments, `hi`, `sdh` and the `hi_tokens` matched
+/// literally. Empty tokens are left out, as they would match any segment.
+fn hearing_pattern(hi_tokens: &[String]) -> String {
+    let hearing: Vec<String> = ["hi", "sdh"]
+        .into_iter()
+        .map(str::to_owned)
+        .chain(
+            hi_tokens
+                .iter()
+                .filter(|t| !t.is_empty())
+                .map(|t| regex::escape(t)),
+        )
+        .collect();
+    hearing.join("|")
+}
+
+/// Translate a language glob to a regex matching a single segment of a file name. `*` matches
+/// any number of characters and `?` a single one, so `en*` matches `en`, `eng` and `en-US`.
+/// Anything else is matched literally.
+pub fn lang_pattern(glob: &str) -> String {
+    let mut pattern = String::new();
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(r"[^\.]*"),
+            '?' => pattern.push_str(r"[^\.]"),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    pattern
+}
+
+/// Matches the language, variant, part and extension segments ending a subtitle file name,
+/// e.g. `.en.hi.part1.srt.gz`, taking the `hi_tokens` as variants too, see `get_sub_path_regex`
+fn sub_suffix_pattern(hi_tokens: &[String]) -> String {
+    r"\.(?P<lang>[^\.]+)(\.(?P<hearing>".to_owned()
+        + &hearing_pattern(hi_tokens)
+        + r"))?(\.part\d+)?\.[^\.]+(\.gz)?$"
+}
+
+/// `sub_suffix_pattern` without custom hearing impaired tokens
+static SUB_SUFFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&sub_suffix_pattern(&[])).unwrap());
+
+/// The language segment of a subtitle file name, e.g. `ja` for `The.Matrix.1999.ja.hi.srt`
+pub fn language_segment(name: &str) -> Option<&str> {
+    let captures = SUB_SUFFIX.captures(name)?;
+    // A name made only of the suffix, like `.en.srt`, has no stem the segment belongs to
+    (captures.get(0)?.start() > 0).then(|| captures.name("lang").map(|m| m.as_str()))?
 }
 
-/// Return the filename, as in, all characters up to a `.`
+/// Return the filename without the segments `find_matching_subtitle_files` matches, so
+/// `The.Matrix.1999.en.srt` gives `The.Matrix.1999` and `song.ja.hi.srt` gives `song`.
 /// `let p: Pathbuf; p.file_stem` returns `filename.en`, this returns `filename`
-pub fn base_file_stem(p: &Path) -> Result<PathBuf> {
-    let pattern = Regex::new(r"[^\.]+")?;
+/// The `hi_tokens` are taken as hearing impaired segments, so with `cc` `song.en.cc.srt`
+/// gives `song` too.
+pub fn base_file_stem(p: &Path, hi_tokens: &[String]) -> Result<PathBuf> {
+    if hi_tokens.iter().all(|t| t.is_empty()) {
+        return strip_sub_suffix(p, &SUB_SUFFIX);
+    }
+    strip_sub_suffix(p, &Regex::new(&sub_suffix_pattern(hi_tokens))?)
+}
+
+/// `base_file_stem` with the suffix matched by `suffix`, see `sub_suffix_pattern`
+fn strip_sub_suffix(p: &Path, suffix: &Regex) -> Result<PathBuf> {
     let path_string = p
         .file_name()
         .and_then(|x| x.to_str())
         .context(format!("unable to parse filepath {:?}", p))?;
-    let x = pattern
-        .find(path_string)
-        .context(format!("unable to compute filestem for {:?}", path_string))?
-        .as_str();
+    let x = match suffix.find(path_string) {
+        Some(suffix) if suffix.start() > 0 => &path_string[..suffix.start()],
+        _ => bail!("unable to compute filestem for {:?}", path_string),
+    };
     Ok(Path::new(x).to_path_buf())
 }
 
-/// Recursively search a directory for the specified subtitle files.
+/// Fill in the `{lang1}` and `{lang2}` placeholders of an output extension template, so the
+/// track order is kept in the name and e.g. `en`/`ja` and `ja`/`en` merges don't clobber each other
+pub fn output_extension(template: &str, lang1: &str, lang2: &str) -> String {
+    template.replace("{lang1}", lang1).replace("{lang2}", lang2)
+}
+
+/// The path in `dir` to write the output `name` (e.g. `movie.en-ja.srt`) to, with its extension
+/// `ext` (e.g. `en-ja.srt`), for outputs of several directories written into one. If another
+/// output of `taken` already has the name, it is numbered instead, e.g. `movie (2).en-ja.srt`.
+pub fn flattened_output(
+    dir: &Path,
+    name: &Path,
+    ext: &str,
+    taken: &mut HashSet<PathBuf>,
+) -> PathBuf {
+    let out = dir.join(name);
+    if taken.insert(out.clone()) {
+        return out;
+    }
+
+    let name = name.to_string_lossy();
+    let stem = name.strip_suffix(&format!(".{ext}")).unwrap_or(&name);
+    let out = (2..)
+        .map(|n| dir.join(format!("{stem} ({n}).{ext}")))
+        .find(|out| taken.insert(out.clone()))
+        .unwrap();
+    warn!(
+        reason = "output_collision", path:% = out.display();
+        "Another output is named {:?} already, writing to {:?} instead", dir.join(&*name), out
+    );
+    out
+}
+
+/// Whether writing to `out` would overwrite any of the files of `subs`, e.g. when the output
+/// extension is `en.srt`. Paths are compared canonicalized, so `./movie.en.srt` matches too.
+pub fn overwrites_input(out: &Path, subs: &[&SubFile]) -> bool {
+    let Ok(out) = out.canonicalize() else {
+        // Inputs exist, so an output that does not yet exist is none of them
+        return false;
+    };
+    subs.iter()
+        .flat_map(|sub| std::iter::once(&sub.path).chain(&sub.parts))
+        .any(|path| path.canonicalize().is_ok_and(|p| p == out))
+}
+
+/// Options controlling which files `find_matching_subtitle_files` picks up
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// Also match `.vtt` files
+    pub find_vtt: bool,
+    /// Also match gzipped files, e.g. `movie.en.srt.gz`
+    pub gzip: bool,
+    /// Skip hearing impaired subtitles (`hi` and `sdh`) entirely
+    pub no_hi: bool,
+    /// Segments marking hearing impaired subtitles besides `hi` and `sdh`, e.g. `cc` for
+    /// `movie.en.cc.srt`. Files with them are of the `Variant::Hi` variant.
+    pub hi_tokens: Vec<String>,
+    /// Only search the root directory itself, without descending into subdirectories
+    pub flat: bool,
+    /// Detect the language of subtitle files without a language code from their text, e.g.
+    /// `movie.srt`, so they can be matched too
+    pub detect_lang: bool,
+    /// Only keep subtitle files next to a video file with the same stem, e.g. `movie.en.srt`
+    /// next to `movie.mkv`, ignoring stray subtitles
+    pub require_video: bool,
+    /// Glob patterns of directories not to search, matched against their name and their path
+    /// relative to the root, e.g. `extras` or `Show/Season 1`. `*` matches any characters but
+    /// `/`, and `?` a single one.
+    pub exclude_dirs: Vec<String>,
+}
+
+/// Convert a glob pattern of `SearchOptions::exclude_dirs` to a regex matching the whole path
+fn dir_pattern(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.trim_end_matches('/').chars() {
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
+
+/// Whether the directory at `relative`, the path relative to the root of the search, matches any
+/// of the `excluded` patterns, by name or by path
+fn is_excluded(relative: &Path, excluded: &[Regex]) -> bool {
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    let (Some(name), path) = (components.last(), components.join("/")) else {
+        return false;
+    };
+    excluded
+        .iter()
+        .any(|r| r.is_match(name) || r.is_match(&path))
+}
+
+/// File extensions of the videos `SearchOptions::require_video` looks for
+const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "avi"];
+
+/// Matches a segment of a file name that looks like a language code, e.g. `en`, `eng` or `pt-BR`
+static LANG_CODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-z]{2,3}([-_][A-Za-z]{2,4})?$").unwrap());
+
+/// Detections less confident than this are not trusted, e.g. for files mixing two languages
+const MIN_DETECTION_CONFIDENCE: f64 = 0.5;
+
+/// Whether `file_name` is a subtitle file `find_subtitle_files` would match if it had a language
+/// code, such as `movie.srt` or `The.Matrix.1999.srt`
+fn is_unlabelled(file_name: &str, opts: &SearchOptions) -> bool {
+    let name = match opts.gzip {
+        true => file_name.strip_suffix(".gz").unwrap_or(file_name),
+        false => file_name,
+    };
+    let stem = name
+        .strip_suffix(".srt")
+        .or_else(|| name.strip_suffix(".vtt").filter(|_| opts.find_vtt));
+    match stem {
+        Some(stem) => match stem.rsplit_once('.') {
+            Some((_, last)) => !LANG_CODE.is_match(last),
+            None => !stem.is_empty(),
+        },
+        None => false,
+    }
+}
+
+/// Detect the language of the subtitle file `path` from its text, returning it as a `SubFile` if
+/// it is one of `langs`
+fn detect_sub_file(path: &Path, langs: &[&str]) -> Option<SubFile> {
+    let srt = match load_sub(path, &LoadOptions::default()) {
+        Ok(srt) => srt,
+        Err(e) => {
+            warn!(reason = "undetected_language", path:% = path.display(); "Unable to detect the language of {:?}: {:#}", path, e);
+            return None;
+        }
+    };
+    let text: Vec<_> = srt
+        .subtitles
+        .iter()
+        .flat_map(|s| &s.text)
+        .map(|txt| TAG.replace_all(txt, ""))
+        .collect();
+    let Some((code, confidence)) = detect_language(&text.join(" ")) else {
+        warn!(reason = "undetected_language", path:% = path.display(); "Unable to detect the language of {:?}, it has no text to go by", path);
+        return None;
+    };
+    info!(
+        "Detected the language of {:?} as {} with a confidence of {:.2}",
+        path, code, confidence
+    );
+    if confidence < MIN_DETECTION_CONFIDENCE {
+        warn!(reason = "undetected_language", path:% = path.display(); "Skipping {:?}, its language is too uncertain", path);
+        return None;
+    }
+
+    let lang = langs.iter().find(|lang| {
+        Regex::new(&format!("^(?:{})$", lang_pattern(lang))).is_ok_and(|r| r.is_match(code))
+    })?;
+    // The name has no language code, so only its extension is left out of the stem
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(".gz").unwrap_or(name);
+    Some(SubFile {
+        path: path.to_owned(),
+        lang: lang.to_string(),
+        code: code.to_owned(),
+        variant: Variant::Normal,
+        parts: Vec::new(),
+        detected: true,
+        stem: PathBuf::from(Path::new(name).file_stem()?),
+    })
+}
+
+/// Recursively search a directory for the subtitle files of two languages.
 pub fn find_matching_subtitle_files(
     root_dir: &PathBuf,
-    lang1: &String,
-    lang2: &String,
-    find_vtt: bool,
+    lang1: &str,
+    lang2: &str,
+    opts: &SearchOptions,
+) -> Result<HashMap<PathBuf, Vec<SubFile>>> {
+    find_subtitle_files(root_dir, &[lang1, lang2], opts)
+}
+
+/// Recursively search a directory for the subtitle files of any of `langs`. Each file's
+/// `SubFile::lang` is the first of `langs` matching it. With `SearchOptions::detect_lang`, files
+/// without a language code are read to detect it.
+pub fn find_subtitle_files(
+    root_dir: &PathBuf,
+    langs: &[&str],
+    opts: &SearchOptions,
 ) -> Result<HashMap<PathBuf, Vec<SubFile>>> {
-    let regex = get_sub_path_regex(lang1, lang2, find_vtt);
+    let regex = get_sub_path_regex(langs, opts.find_vtt, opts.gzip, &opts.hi_tokens);
     let subtitle_pattern = Regex::new(regex.as_str())?;
+    let suffix = Regex::new(&sub_suffix_pattern(&opts.hi_tokens))?;
     let mut ret = HashMap::new();
 
     if root_dir.is_file() {
         bail!("the given path must be a directory!")
     }
 
-    for entry in WalkDir::new(root_dir).follow_links(true) {
-        let entry = entry?;
+    let excluded = opts
+        .exclude_dirs
+        .iter()
+        .map(|glob| Regex::new(&dir_pattern(glob)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let max_depth = if opts.flat { 0 } else { usize::MAX };
+    let walk = WalkDir::new(root_dir)
+        .follow_links(true)
+        .max_depth(max_depth)
+        .into_iter()
+        // Skip excluded directories without reading them, and everything below them
+        .filter_entry(|entry| {
+            let relative = entry.path().strip_prefix(root_dir).unwrap_or(entry.path());
+            let skip =
+                entry.depth() > 0 && entry.file_type().is_dir() && is_excluded(relative, &excluded);
+            if skip {
+                info!("Skipping the excluded directory {:?}", entry.path());
+            }
+            !skip
+        });
+    for entry in walk {
+        // One unreadable directory, e.g. on a network share, should not end the whole search
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(root_dir).to_owned();
+                warn!(reason = "unreadable_path", path:% = path.display(); "Skipping {:?}: {}", path, e);
+                continue;
+            }
+        };
         trace!("Found entry: {:?}", entry.path());
 
         let dir_path = entry.path();
@@ -75,16 +453,44 @@ pub fn find_matching_subtitle_files(
             continue;
         }
 
+        // Subtitles split into parts, by their stem, language, language code and variant
+        let mut split: HashMap<_, Vec<(u32, PathBuf)>> = HashMap::new();
+        // Stems of the videos in this directory, see `SearchOptions::require_video`
+        let mut videos = HashSet::new();
+
         // Now find files with matching subtitle names in this directory
-        for entry in dir_path.read_dir()? {
-            let file_path = entry?.path();
-            if file_path.is_file()
+        let entries = match dir_path.read_dir() {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(reason = "unreadable_path", path:% = dir_path.display(); "Skipping {:?}: {}", dir_path, e);
+                continue;
+            }
+        };
+        for entry in entries {
+            let file_path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    warn!(reason = "unreadable_path", path:% = dir_path.display(); "Skipping an entry of {:?}: {}", dir_path, e);
+                    continue;
+                }
+            };
+            let is_video = file_path.extension().is_some_and(|ext| {
+                VIDEO_EXTENSIONS
+                    .iter()
+                    .any(|video| ext.eq_ignore_ascii_case(video))
+            });
+            if opts.require_video
+                && is_video
+                && let Some(stem) = file_path.file_stem()
+            {
+                videos.insert(PathBuf::from(stem));
+            } else if file_path.is_file()
                 && let Some(file_name) = file_path.file_name().and_then(|n| n.to_str())
                 && let Some(captures) = subtitle_pattern.captures(file_name)
             {
                 trace!("Found file: {}", file_name);
 
-                let lang = captures
+                let code = captures
                     .name("lang")
                     .context(format!(
                         "impossible error: unable to find lang in {}",
@@ -92,17 +498,84 @@ pub fn find_matching_subtitle_files(
                     ))?
                     .as_str()
                     .to_owned();
-                let hi = captures.name("hearing").is_some();
+                let lang = (0..langs.len())
+                    .find(|i| captures.name(&format!("lang{}", i + 1)).is_some())
+                    .map(|i| langs[i].to_owned())
+                    .context(format!(
+                        "impossible error: unable to find the language of {}",
+                        file_name
+                    ))?;
+                let variant = Variant::from_segment(captures.name("hearing").map(|m| m.as_str()));
+                if opts.no_hi && variant != Variant::Normal {
+                    trace!("Skipping hearing impaired subtitle: {}", file_name);
+                    continue;
+                }
+
+                let stem = strip_sub_suffix(&file_path, &suffix)?;
+                if let Some(part) = captures.name("part") {
+                    let key = (stem, lang, code, variant);
+                    let part = part.as_str().parse()?;
+                    split.entry(key).or_default().push((part, file_path));
+                    continue;
+                }
+
                 let val = SubFile {
                     path: file_path,
                     lang,
-                    hi,
+                    code,
+                    variant,
+                    parts: Vec::new(),
+                    detected: false,
+                    stem,
                 };
+                ret.entry(dir_path.to_owned())
+                    .or_insert_with(Vec::new)
+                    .push(val);
+            } else if opts.detect_lang
+                && file_path.is_file()
+                && file_path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| is_unlabelled(n, opts))
+                && let Some(val) = detect_sub_file(&file_path, langs)
+            {
+                ret.entry(dir_path.to_owned())
+                    .or_insert_with(Vec::new)
+                    .push(val);
+            }
+        }
 
-                if !ret.contains_key(dir_path) {
-                    let _ = ret.insert(dir_path.to_owned(), Vec::new());
-                };
-                ret.get_mut(dir_path).unwrap().push(val);
+        for ((stem, lang, code, variant), mut parts) in split {
+            parts.sort();
+            let parts: Vec<_> = parts.into_iter().map(|(_, path)| path).collect();
+            trace!("Found split subtitle: {:?}", parts);
+
+            let val = SubFile {
+                path: parts[0].clone(),
+                lang,
+                code,
+                variant,
+                parts,
+                detected: false,
+                stem,
+            };
+            ret.entry(dir_path.to_owned())
+                .or_insert_with(Vec::new)
+                .push(val);
+        }
+
+        if opts.require_video
+            && let Some(subs) = ret.get_mut(dir_path)
+        {
+            subs.retain(|sub| {
+                let has_video = videos.contains(&sub.stem);
+                if !has_video {
+                    info!("Skipping {:?}, there is no video named like it", sub.path);
+                }
+                has_video
+            });
+            if subs.is_empty() {
+                ret.remove(dir_path);
             }
         }
     }
@@ -110,25 +583,358 @@ pub fn find_matching_subtitle_files(
     Ok(ret)
 }
 
-pub fn load_sub(path: PathBuf) -> Result<SubRip> {
-    let file = fs::read_to_string(&path)?;
-    let ext = path
-        .extension()
-        .context(format!("unable to retrieve extension from file {}", file))?
-        .to_str()
-        .context(format!(
-            "unable to parse extension as a string from file {}",
-            file
-        ))?;
-    let subfile = match ext {
-        "vtt" => vtt_to_subrip(WebVtt::parse(&file)?),
-        "srt" => SubRip::parse(&file)?,
-        _ => bail!(
-            "invalid extension ({}), supported extensions are: srt, vtt",
-            ext
-        ),
+/// Pick the subtitle of `subs` for the file `stem` in `lang` whose variant comes first in
+/// `preference`. Variants missing from `preference` are never picked.
+pub fn preferred_sub<'a>(
+    subs: &'a [SubFile],
+    stem: &Path,
+    lang: &str,
+    preference: &[Variant],
+) -> Option<&'a SubFile> {
+    let mut best: Option<(usize, &SubFile)> = None;
+    for sub in subs {
+        if sub.lang != lang || sub.stem != stem {
+            continue;
+        }
+        let Some(rank) = preference.iter().position(|v| *v == sub.variant) else {
+            continue;
+        };
+        if best.is_none_or(|(r, _)| rank < r) {
+            best = Some((rank, sub));
+        }
+    }
+
+    best.map(|(_, sub)| sub)
+}
+
+/// Pick the subtitle of `subs` for the file `stem` in the first of `langs` it exists in, see
+/// `preferred_sub`
+pub fn first_preferred_sub<'a>(
+    subs: &'a [SubFile],
+    stem: &Path,
+    langs: &[String],
+    preference: &[Variant],
+) -> Option<&'a SubFile> {
+    langs
+        .iter()
+        .find_map(|lang| preferred_sub(subs, stem, lang, preference))
+}
+
+/// Pair up the subtitles of a directory, each file in `sub1_lang` with the one in `sub2_lang`
+/// of the same name, picking among them as `first_preferred_sub` does. Files missing the
+/// other language are left out, unless `single_ok` is given, in which case they are returned
+/// alone, so they can be written without merging.
+pub fn pair_subs<'a>(
+    subs: &'a [SubFile],
+    sub1_lang: &[String],
+    sub2_lang: &[String],
+    sub1_prefer: &[Variant],
+    sub2_prefer: &[Variant],
+    single_ok: bool,
+) -> Vec<(Option<&'a SubFile>, Option<&'a SubFile>)> {
+    let mut pairs = Vec::new();
+    for sub in subs {
+        // Only pair each file once, from the preferred language and variant of its first
+        // track, or of its second one if it has no first
+        let l1 = first_preferred_sub(subs, &sub.stem, sub1_lang, sub1_prefer);
+        let l2 = first_preferred_sub(subs, &sub.stem, sub2_lang, sub2_prefer);
+        if l1.or(l2) != Some(sub) {
+            continue;
+        }
+
+        match (l1, l2) {
+            (Some(s1), Some(s2)) if s1 != s2 => pairs.push((l1, l2)),
+            (Some(_), None) | (None, Some(_)) if single_ok => pairs.push((l1, l2)),
+            _ => (),
+        }
+    }
+    pairs
+}
+
+/// Default for `LoadOptions::max_bytes`, generous for any real subtitle file
+pub const DEFAULT_MAX_INPUT_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Options controlling how `load_sub` reads subtitle files
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    /// Rewrite the sequence numbers to be contiguous in cue order
+    pub renumber: bool,
+    /// Refuse to read files larger than this, guarding against e.g. videos renamed to `.srt`
+    pub max_bytes: u64,
+    /// Normalize the text of every cue to Unicode NFC
+    pub normalize_unicode: bool,
+    /// Remove karaoke timing tags from ASS/SSA files
+    pub strip_karaoke: bool,
+    /// Only keep the events of ASS/SSA files that use the style of this name
+    pub style_filter: Option<String>,
+    /// End each cue overlapping the cue after it at the start of that cue, instead of only
+    /// warning about it
+    pub fix_overlaps: bool,
+    /// The subtitle stream to read from MKV files, counting from 0 like `ffmpeg`'s `-map 0:s:N`
+    pub track_index: usize,
+    /// Place the cues of VTT files with position tags translated from their `line`, `position`
+    /// and `align` settings, see `cue_position`, rather than dropping them
+    pub vtt_positions: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            renumber: false,
+            max_bytes: DEFAULT_MAX_INPUT_BYTES,
+            normalize_unicode: false,
+            strip_karaoke: false,
+            style_filter: None,
+            fix_overlaps: false,
+            track_index: 0,
+            vtt_positions: false,
+        }
+    }
+}
+
+/// Returned by `load_sub` for files larger than `LoadOptions::max_bytes`
+#[derive(Debug)]
+pub struct InputTooLarge {
+    /// The file that was too large
+    pub path: PathBuf,
+    /// Size of the file, or of its decompressed contents if it is gzipped, in bytes
+    pub size: u64,
+    /// The limit it exceeded, in bytes
+    pub max_bytes: u64,
+}
+
+impl std::fmt::Display for InputTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} is {} bytes, which is larger than the maximum of {} bytes",
+            self.path, self.size, self.max_bytes
+        )
+    }
+}
+
+impl std::error::Error for InputTooLarge {}
+
+/// Returned by `load_sub` for files whose extension is none of the `INPUT_FORMATS`
+#[derive(Debug)]
+pub struct UnsupportedFormat {
+    /// The file that could not be read
+    pub path: PathBuf,
+    /// Its extension, empty if it has none
+    pub ext: String,
+}
+
+impl std::fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ext.as_str() {
+            "" => write!(f, "{:?} has no extension", self.path)?,
+            ext => write!(f, "{:?} has the unsupported extension {:?}", self.path, ext)?,
+        }
+        write!(
+            f,
+            ", supported extensions are: {}",
+            INPUT_FORMATS.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+/// Decode the contents of a subtitle file. Files that are not UTF-8, such as the Windows-1252
+/// or Shift-JIS files of older releases, are decoded using the detected encoding.
+fn decode(path: &Path, bytes: Vec<u8>) -> String {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(txt) => return txt,
+        Err(e) => e.into_bytes(),
+    };
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(&bytes, true);
+    let encoding = detector.guess(None, false);
+    match encoding.decode_without_bom_handling_and_without_replacement(&bytes) {
+        Some(txt) => {
+            info!("Decoded {:?} as {}", path, encoding.name());
+            txt.into_owned()
+        }
+        None => {
+            warn!(
+                reason = "invalid_encoding", path:% = path.display();
+                "{:?} is neither valid UTF-8 nor {}, replacing the invalid characters", path, encoding.name()
+            );
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+    }
+}
+
+/// Read the contents of `path`, decompressing it if it is gzipped (`movie.en.srt.gz`).
+/// Returns the contents along with the path without the `.gz` extension, which names the format.
+fn read_input(path: &Path, max_bytes: u64) -> Result<(Vec<u8>, PathBuf)> {
+    let too_large = |size| InputTooLarge {
+        path: path.to_owned(),
+        size,
+        max_bytes,
+    };
+
+    let size = fs::metadata(path)?.len();
+    if size > max_bytes {
+        return Err(too_large(size).into());
+    }
+    if path.extension().is_none_or(|ext| ext != "gz") {
+        return Ok((fs::read(path)?, path.to_owned()));
+    }
+
+    // Stop reading into memory at the limit, the contents could be a decompression bomb
+    let mut decoder = GzDecoder::new(File::open(path)?);
+    let mut bytes = Vec::new();
+    let unzip_error = || format!("unable to decompress {:?}", path);
+    (&mut decoder)
+        .take(max_bytes + 1)
+        .read_to_end(&mut bytes)
+        .with_context(unzip_error)?;
+    if bytes.len() as u64 > max_bytes {
+        let rest = io::copy(&mut decoder, &mut io::sink()).with_context(unzip_error)?;
+        return Err(too_large(bytes.len() as u64 + rest).into());
+    }
+
+    Ok((bytes, path.with_extension("")))
+}
+
+/// Extract the subtitle stream `LoadOptions::track_index` of an MKV file, returning its text and
+/// the extension of its format. `max_bytes` applies to the extracted subtitles.
+#[cfg(feature = "mkv")]
+fn read_mkv(path: &Path, opts: &LoadOptions) -> Result<(String, String)> {
+    let (text, ext) = crate::mkv::extract_subtitles(path, opts.track_index)?;
+    if text.len() as u64 > opts.max_bytes {
+        return Err(InputTooLarge {
+            path: path.to_owned(),
+            size: text.len() as u64,
+            max_bytes: opts.max_bytes,
+        }
+        .into());
+    }
+    Ok((text, ext.to_owned()))
+}
+
+#[cfg(not(feature = "mkv"))]
+fn read_mkv(path: &Path, _: &LoadOptions) -> Result<(String, String)> {
+    bail!(
+        "reading the subtitles of {:?} needs ffmpeg, which submerger was built without (see the `mkv` feature)",
+        path
+    )
+}
+
+/// Whether `path` is an `http://` or `https://` URL rather than a file, see `load_sub`
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
+
+/// Download the subtitles at the URL `path`, returning their text and format
+#[cfg(feature = "url")]
+fn read_url(path: &Path, opts: &LoadOptions) -> Result<(String, String)> {
+    let url = path.to_str().context(format!("invalid URL {:?}", path))?;
+    let (bytes, ext) = crate::remote::fetch_subtitles(url, opts.max_bytes)?;
+    Ok((decode(path, bytes), ext.to_owned()))
+}
+
+#[cfg(not(feature = "url"))]
+fn read_url(path: &Path, _: &LoadOptions) -> Result<(String, String)> {
+    bail!(
+        "downloading {:?} needs submerger to be built with the `url` feature",
+        path
+    )
+}
+
+/// Matches the timing line of an SRT cue, such as `00:00:01.000 --> 00:00:02.500`, whose
+/// milliseconds may be separated by a dot rather than a comma
+static SRT_TIMING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^(\s*\d{2}:\d{2}:\d{2})[.,](\d{3}\s*-->\s*\d{2}:\d{2}:\d{2})[.,](\d{3})")
+        .unwrap()
+});
+
+/// Rewrite the timestamps of SRT timing lines separating milliseconds with a dot, as some tools
+/// write them, to the comma the parser expects. Lines of cue text are left as they are.
+pub(crate) fn normalize_srt_timings(text: &str) -> Cow<'_, str> {
+    // Most files use commas, which is much quicker to tell from the timestamps around each arrow
+    // than by running the regex over the whole file
+    let dotted = text.match_indices("-->").any(|(i, _)| {
+        let (start, end) = (text[..i].trim_end(), text[i + 3..].trim_start());
+        start.len() >= 4 && start.as_bytes()[start.len() - 4] == b'.'
+            || end.as_bytes().get(8) == Some(&b'.')
+    });
+    if !dotted {
+        return Cow::Borrowed(text);
+    }
+    SRT_TIMING.replace_all(text, "${1},${2},${3}")
+}
+
+/// Load a subtitle file in any of the `INPUT_FORMATS`, converting it to SRT. Gzipped files are
+/// decompressed first, their format is taken from the extension before `.gz`. MKV files are
+/// read with the `mkv` feature, see `LoadOptions::track_index`. With the `url` feature, `path`
+/// may also be an `http(s)://` URL, which is downloaded.
+pub fn load_sub(path: &Path, opts: &LoadOptions) -> Result<SubRip> {
+    let (file, ext) = if is_url(path) {
+        read_url(path, opts)?
+    } else if path.extension().is_some_and(|ext| ext == "mkv") {
+        read_mkv(path, opts)?
+    } else {
+        let (bytes, inner) = read_input(path, opts.max_bytes)?;
+        let ext = inner
+            .extension()
+            .unwrap_or_default()
+            .to_str()
+            .context(format!(
+                "unable to parse extension as a string from file {:?}",
+                path
+            ))?
+            .to_owned();
+        (decode(path, bytes), ext)
+    };
+    // Files exported by Windows tools often start with a byte order mark, which the parsers reject
+    let file = file.strip_prefix('\u{feff}').unwrap_or(&file);
+    let ext = ext.as_str();
+    if opts.style_filter.is_some() && !matches!(ext, "ass" | "ssa") {
+        warn!(
+            reason = "style_filter_unsupported", path:% = path.display();
+            "Style filters only apply to ASS/SSA files, keeping all of {:?}", path
+        );
+    }
+
+    let mut subfile = match ext {
+        "vtt" => vtt_to_subrip(WebVtt::parse(file)?, opts.vtt_positions),
+        "srt" => SubRip::parse(&normalize_srt_timings(file))?,
+        "ass" | "ssa" => {
+            let mut ass = Ass::parse(file)?;
+            if let Some(style) = &opts.style_filter {
+                ass.retain_style(style);
+            }
+            if opts.strip_karaoke {
+                ass.strip_karaoke();
+            }
+            ass.to_subrip()
+        }
+        _ => {
+            return Err(UnsupportedFormat {
+                path: path.to_owned(),
+                ext: ext.to_owned(),
+            }
+            .into())
+        }
     };
 
+    if opts.renumber {
+        renumber(&mut subfile);
+    } else if ext == "srt" {
+        // The other formats have no sequence numbers, their cues are numbered when converted
+        check_sequence(&subfile, path);
+    }
+    if opts.normalize_unicode {
+        for txt in subfile.subtitles.iter_mut().flat_map(|s| &mut s.text) {
+            *txt = txt.nfc().collect();
+        }
+    }
+    check_overlaps(&mut subfile, path, opts.fix_overlaps);
+
     info!(
         "Loaded {} subtitles from {:?}",
         subfile.subtitles.len(),
@@ -138,53 +944,2083 @@ pub fn load_sub(path: PathBuf) -> Result<SubRip> {
     Ok(subfile)
 }
 
-pub fn merge(
-    srt1: &SubRip,
-    srt2: &SubRip,
-    srt2_color_opt: Option<String>,
-    srt2_position: SubPosition,
-) -> SubRip {
-    let position = srt2_position.to_string();
-    let (color_start, color_end) = if let Some(color) = srt2_color_opt {
-        (format!("<font color=\"{color}\">"), "</font>".to_owned())
-    } else {
-        ("".to_owned(), "".to_owned())
-    };
+/// Load an ASS/SSA file with its styles, rather than converting it to SRT like `load_sub`, to
+/// merge it with `merge_ssa`. Only the `max_bytes`, `strip_karaoke` and `style_filter` options
+/// apply.
+pub fn load_ass(path: &Path, opts: &LoadOptions) -> Result<Ass> {
+    let (bytes, inner) = read_input(path, opts.max_bytes)?;
+    if inner
+        .extension()
+        .is_none_or(|ext| ext != "ass" && ext != "ssa")
+    {
+        bail!(
+            "only ASS/SSA files have styles to keep, unable to load {:?} with its styles",
+            path
+        );
+    }
+    let file = decode(path, bytes);
+    let file = file.strip_prefix('\u{feff}').unwrap_or(&file);
 
-    let mut subs = srt2.subtitles.clone();
-    for sub in &mut subs {
-        for txt in &mut sub.text {
-            *txt = format!("{position} {color_start}{txt}{color_end}");
+    let mut ass = Ass::parse(file)?;
+    if let Some(style) = &opts.style_filter {
+        ass.retain_style(style);
+    }
+    if opts.strip_karaoke {
+        ass.strip_karaoke();
+    }
+
+    info!("Loaded {} events from {:?}", ass.events.len(), path);
+    Ok(ass)
+}
+
+/// Warn about each cue still shown when the cue after it starts, which would show two lines of
+/// the same track at once, or end it at the start of that cue if `fix` is set
+pub(crate) fn check_overlaps(srt: &mut SubRip, path: &Path, fix: bool) {
+    for i in 1..srt.subtitles.len() {
+        let next = &srt.subtitles[i];
+        let (next_start, next_seq) = (next.start, next.sequence);
+        let cue = &mut srt.subtitles[i - 1];
+        if !(cue.start < next_start && next_start < cue.end) {
+            continue;
+        }
+
+        if fix {
+            cue.end = next_start;
+        } else {
+            warn!(
+                reason = "overlapping_cues", path:% = path.display();
+                "Cue {} of {:?} overlaps cue {}, use --fix-overlaps to shorten it", cue.sequence, path, next_seq
+            );
+        }
+    }
+}
+
+/// How many misnumbered cues `check_sequence` names
+const MISNUMBERED_SHOWN: usize = 5;
+
+/// The sequence numbers of each cue of `srt` numbered the same as or lower than the cue before
+/// it, along with that of the cue before it
+pub fn misnumbered_cues(srt: &SubRip) -> Vec<(u32, u32)> {
+    srt.subtitles
+        .windows(2)
+        .filter(|pair| pair[1].sequence <= pair[0].sequence)
+        .map(|pair| (pair[0].sequence, pair[1].sequence))
+        .collect()
+}
+
+/// Warn if the cues of `srt`, loaded from `path`, have duplicate or decreasing sequence numbers,
+/// which merging fixes but which may confuse other tools reading the file
+fn check_sequence(srt: &SubRip, path: &Path) {
+    let misnumbered = misnumbered_cues(srt);
+    if misnumbered.is_empty() {
+        return;
+    }
+
+    let mut shown: Vec<_> = misnumbered
+        .iter()
+        .take(MISNUMBERED_SHOWN)
+        .map(|(before, seq)| format!("{} after {}", seq, before))
+        .collect();
+    if misnumbered.len() > MISNUMBERED_SHOWN {
+        shown.push(format!(
+            "and {} more",
+            misnumbered.len() - MISNUMBERED_SHOWN
+        ));
+    }
+    warn!(
+        reason = "misnumbered_cues", path:% = path.display();
+        "Cues of {:?} are numbered out of order ({}), use --renumber-on-load to renumber them",
+        path, shown.join(", ")
+    );
+}
+
+/// Fail if the track loaded from `path` is `empty`, having no cues because the file is empty or
+/// not a subtitle file at all, which would otherwise silently merge into a copy of the other
+/// track. With `allow_empty`, only warn about it.
+pub fn check_not_empty(empty: bool, path: &Path, allow_empty: bool) -> Result<()> {
+    if !empty {
+        return Ok(());
+    }
+    if !allow_empty {
+        bail!(
+            "{:?} has no subtitles, merge with --allow-empty to merge it anyway",
+            path
+        );
+    }
+    warn!(reason = "empty_input", path:% = path.display(); "{:?} has no subtitles", path);
+    Ok(())
+}
+
+/// Load the subtitle described by `sub`, concatenating its parts if it is split.
+/// Each part is offset by the end of the last cue of the parts before it.
+pub fn load_sub_file(sub: &SubFile, opts: &LoadOptions) -> Result<SubRip> {
+    if sub.parts.is_empty() {
+        return load_sub(&sub.path, opts);
+    }
+
+    let mut stitched = SubRip::default();
+    let mut offset = Duration::ZERO;
+    for part in &sub.parts {
+        let mut srt = load_sub(part, opts)?;
+        let end = srt.subtitles.iter().map(|s| s.end.into()).max();
+
+        for cue in &mut srt.subtitles {
+            cue.start = (Into::<Duration>::into(cue.start) + offset).into();
+            cue.end = (Into::<Duration>::into(cue.end) + offset).into();
         }
+        stitched.subtitles.extend(srt.subtitles);
+        offset += end.unwrap_or(Duration::ZERO);
     }
 
-    let mut merged_subs = srt1.clone();
-    merged_subs.subtitles.extend(subs);
+    renumber(&mut stitched);
+    info!("Stitched {} parts from {:?}", sub.parts.len(), sub.parts);
+
+    Ok(stitched)
+}
+
+/// Styling and timing changes applied to a single subtitle track before it is merged
+#[derive(Clone, Debug, Default)]
+pub struct TrackOptions {
+    /// Color of the text, e.g. `#fbf1c7`
+    pub color: Option<String>,
+    /// Where on the screen the text is shown
+    pub position: Option<SubPosition>,
+    /// Exact point the text is shown at, used instead of `position` when set
+    pub pos_xy: Option<Point>,
+    /// Color of an opaque background box behind the text, written as a `box_tag` override block
+    /// that ASS output turns into a boxed style. Other formats can not represent it.
+    pub box_color: Option<String>,
+    /// Font size of the text, as `<font size>` tags nested inside the color, which ASS output
+    /// turns into `\fs` overrides
+    pub size: Option<u32>,
+    /// Make the text bold, with `<b>` tags inside the color and size
+    pub bold: bool,
+    /// Make the text italic, with `<i>` tags inside the color, size and `<b>`
+    pub italic: bool,
+    /// Extend each cue to the start of the next one, so there are no gaps between cues
+    pub fill_gaps: bool,
+    /// Combine consecutive cues less than this far apart into one, as long as it is shown for at
+    /// most `MAX_GAP_MERGED`, e.g. for the many tiny cues of karaoke or transcribed tracks
+    pub gap_merge: Option<Duration>,
+    /// Extend cues shown for less than this long, but never past the start of the next cue
+    pub min_duration: Option<Duration>,
+    /// Color lines starting with a speaker label (`JOHN: ...`) with a color per speaker
+    pub color_speakers: bool,
+    /// Color of the bracketed sound descriptions (`[door slams]`) of hearing impaired subtitles.
+    /// Applied to any track it is set for, see `for_variant`.
+    pub annotation_color: Option<String>,
+    /// Round every timestamp to the nearest frame at this framerate
+    pub snap_fps: Option<f64>,
+    /// Only keep these lines of each cue, e.g. the kanji and not the furigana below them
+    pub keep_lines: Option<LineRange>,
+    /// Retime the track from the first framerate to the second, e.g. `(23.976, 25.0)` for
+    /// subtitles timed for a 23.976fps release used with a 25fps one. Applied before `offset`.
+    pub fps_scale: Option<(f64, f64)>,
+    /// Shift every cue by this many seconds, negative values moving cues earlier
+    pub offset: Option<f64>,
+    /// Only shift the cues starting at or after this time by `offset`, e.g. those after an ad
+    /// break the track drifts from, leaving the earlier cues as they are
+    pub offset_from: Option<Duration>,
+    /// Shift every cue so the anchored cue starts at the anchor's time. Applied after
+    /// `fps_scale` and before `offset`.
+    pub anchor: Option<Anchor>,
+    /// Remap the time of every cue, e.g. to subtitles drifting differently throughout the video.
+    /// Applied after `fps_scale` and before `anchor`.
+    pub sync_map: Option<SyncMap>,
+    /// Drop the cues starting before this time, e.g. a recap
+    pub skip_before: Option<Duration>,
+    /// Drop the cues starting at or after this time, e.g. the credits
+    pub skip_after: Option<Duration>,
+    /// Rewrap lines longer than this many characters at word boundaries, not counting tags
+    pub wrap: Option<usize>,
+    /// Remove all HTML-style tags and ASS override blocks, leaving bare text, for renderers
+    /// that show tags literally. Overrides `color`, `size` and `position`.
+    pub plain: bool,
+}
+
+impl TrackOptions {
+    /// Adjust the options to a track of the given variant, only coloring the sound descriptions
+    /// of hearing impaired tracks
+    pub fn for_variant(self, variant: Variant) -> TrackOptions {
+        match variant {
+            Variant::Normal => TrackOptions {
+                annotation_color: None,
+                ..self
+            },
+            Variant::Hi | Variant::Sdh => self,
+        }
+    }
 
-    for i in 0..merged_subs.subtitles.len() {
-        merged_subs.subtitles[i].sequence = i as u32 + 1;
+    /// Fill in any unset fields of `self` with those of `fallback`
+    pub fn or(self, fallback: TrackOptions) -> TrackOptions {
+        TrackOptions {
+            color: self.color.or(fallback.color),
+            position: self.position.or(fallback.position),
+            pos_xy: self.pos_xy.or(fallback.pos_xy),
+            box_color: self.box_color.or(fallback.box_color),
+            size: self.size.or(fallback.size),
+            bold: self.bold || fallback.bold,
+            italic: self.italic || fallback.italic,
+            fill_gaps: self.fill_gaps || fallback.fill_gaps,
+            gap_merge: self.gap_merge.or(fallback.gap_merge),
+            min_duration: self.min_duration.or(fallback.min_duration),
+            color_speakers: self.color_speakers || fallback.color_speakers,
+            annotation_color: self.annotation_color.or(fallback.annotation_color),
+            snap_fps: self.snap_fps.or(fallback.snap_fps),
+            keep_lines: self.keep_lines.or(fallback.keep_lines),
+            fps_scale: self.fps_scale.or(fallback.fps_scale),
+            offset: self.offset.or(fallback.offset),
+            offset_from: self.offset_from.or(fallback.offset_from),
+            anchor: self.anchor.or(fallback.anchor),
+            sync_map: self.sync_map.or(fallback.sync_map),
+            skip_before: self.skip_before.or(fallback.skip_before),
+            skip_after: self.skip_after.or(fallback.skip_after),
+            wrap: self.wrap.or(fallback.wrap),
+            plain: self.plain || fallback.plain,
+        }
     }
-    merged_subs
 }
 
-fn vtt_block_to_srt(vtt_block: VttBlock, sequence: u32) -> Option<SrtSubtitle> {
-    let cue = match vtt_block {
-        VttBlock::Que(y) => y,
-        _ => return None,
-    };
+/// An inclusive range of the lines of a cue, counting from 1. Parsed from `N` or `N-M`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    /// The first line to keep
+    pub first: usize,
+    /// The last line to keep
+    pub last: usize,
+}
 
-    let start = SrtTimestamp {
-        hours: cue.timings.start.hours,
-        minutes: cue.timings.start.minutes,
-        seconds: cue.timings.start.seconds,
-        milliseconds: cue.timings.start.milliseconds,
-    };
-    let end = SrtTimestamp {
-        hours: cue.timings.end.hours,
-        minutes: cue.timings.end.minutes,
-        seconds: cue.timings.end.seconds,
-        milliseconds: cue.timings.end.milliseconds,
-    };
+impl FromStr for LineRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let invalid = || format!("{:?} is not a line number or a range of them (`1-2`)", s);
+        let first: usize = first.trim().parse().with_context(invalid)?;
+        let last: usize = last.trim().parse().with_context(invalid)?;
+        if first == 0 || last < first {
+            bail!(invalid())
+        }
+        Ok(LineRange { first, last })
+    }
+}
+
+/// A point on the screen in the pixels of the script resolution, parsed from `X,Y` and
+/// written into cues as an ASS `{\pos(X,Y)}` tag by `Display`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Point {
+    /// Distance from the left edge
+    pub x: i32,
+    /// Distance from the top edge
+    pub y: i32,
+}
+
+impl FromStr for Point {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("{:?} is not a point given as two integers `X,Y`", s);
+        let (x, y) = s.split_once(',').with_context(invalid)?;
+        Ok(Point {
+            x: x.trim().parse().with_context(invalid)?,
+            y: y.trim().parse().with_context(invalid)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{\\pos({},{})}}", self.x, self.y)
+    }
+}
+
+/// A cue of a track and the time it should start at, parsed from `INDEX=TIME`, e.g.
+/// `5=00:01:02.500`. The index counts from 1 and the time is given like `parse_offset` takes it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Anchor {
+    /// Index of the cue in its track
+    pub cue: usize,
+    /// The time the cue should start at
+    pub at: Duration,
+}
+
+impl FromStr for Anchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("{:?} is not an anchor given as `INDEX=TIME`", s);
+        let (cue, at) = s.split_once('=').with_context(invalid)?;
+        let cue: usize = cue.trim().parse().with_context(invalid)?;
+        let at = parse_offset(at.trim()).with_context(invalid)?;
+        if cue == 0 || at < 0.0 {
+            bail!(
+                "{}, the index counts from 1 and the time can not be negative",
+                invalid()
+            )
+        }
+        Ok(Anchor {
+            cue,
+            at: Duration::from_secs_f64(at),
+        })
+    }
+}
+
+/// A piecewise linear remapping of time, parsed from lines of `FROM TO` times given like
+/// `parse_offset` takes them, e.g. `00:10:00 00:10:02.500`. Empty lines and lines starting with
+/// `#` are ignored. A time between two anchors is mapped between their times in proportion,
+/// one before the first or after the last anchor is shifted like the nearest anchor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncMap {
+    /// The anchors as `(from, to)` times in seconds, ordered by the time mapped from
+    anchors: Vec<(f64, f64)>,
+}
+
+impl SyncMap {
+    /// The time, in seconds, that `t` is mapped to
+    pub fn map(&self, t: f64) -> f64 {
+        let i = self.anchors.partition_point(|&(from, _)| from <= t);
+        let (first, last) = (self.anchors[0], self.anchors[self.anchors.len() - 1]);
+        match i {
+            0 => t + first.1 - first.0,
+            i if i == self.anchors.len() => t + last.1 - last.0,
+            i => {
+                let ((from0, to0), (from1, to1)) = (self.anchors[i - 1], self.anchors[i]);
+                to0 + (t - from0) * (to1 - to0) / (from1 - from0)
+            }
+        }
+    }
+}
+
+impl FromStr for SyncMap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut anchors = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {} is not two times `FROM TO`: {:?}", i + 1, line);
+            let [from, to] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                bail!(invalid())
+            };
+            let from = parse_offset(from).with_context(invalid)?;
+            let to = parse_offset(to).with_context(invalid)?;
+            if from < 0.0 || to < 0.0 {
+                bail!("{}, the times can not be negative", invalid());
+            }
+            anchors.push((from, to));
+        }
+
+        if anchors.is_empty() {
+            bail!("the sync map has no anchors");
+        }
+        anchors.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // Mapping times backwards would put the cues out of order
+        if anchors
+            .windows(2)
+            .any(|w| w[0].0 == w[1].0 || w[0].1 >= w[1].1)
+        {
+            bail!("the times of a sync map must increase from one anchor to the next");
+        }
+        Ok(SyncMap { anchors })
+    }
+}
+
+/// Read a `SyncMap` from the file at `path`. Its errors name the line rather than the file, as
+/// the `--sync-map` argument they are shown with already does.
+pub fn load_sync_map(path: &str) -> Result<SyncMap> {
+    let text = fs::read_to_string(path).context(format!("unable to read {:?}", path))?;
+    text.parse()
+}
+
+/// An additional track to merge, parsed from `PATH[,color=..][,position=..][,offset=..]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtraTrack {
+    /// The subtitle file of the track
+    pub path: PathBuf,
+    /// See `TrackOptions::color`
+    pub color: Option<String>,
+    /// See `TrackOptions::position`
+    pub position: Option<SubPosition>,
+    /// See `TrackOptions::offset`
+    pub offset: Option<f64>,
+}
+
+impl FromStr for ExtraTrack {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(',');
+        let path = parts.next().filter(|p| !p.is_empty());
+        let mut track = ExtraTrack {
+            path: path
+                .with_context(|| format!("{:?} does not start with a path", s))?
+                .into(),
+            color: None,
+            position: None,
+            offset: None,
+        };
+
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .with_context(|| format!("{:?} is not of the form `key=value`", part))?;
+            match key {
+                "color" => track.color = Some(parse_color(value)?),
+                "position" => {
+                    let position = clap::ValueEnum::from_str(value, true)
+                        .map_err(|_| anyhow::anyhow!("{:?} is not a position", value))?;
+                    track.position = Some(position);
+                }
+                "offset" => track.offset = Some(parse_offset(value)?),
+                _ => bail!(
+                    "unknown track option {:?}, expected color, position or offset",
+                    key
+                ),
+            }
+        }
+        Ok(track)
+    }
+}
+
+impl ExtraTrack {
+    /// The options of this track, falling back to `shared` for those it does not set
+    pub fn options(&self, shared: TrackOptions) -> TrackOptions {
+        TrackOptions {
+            color: self.color.clone(),
+            position: self.position,
+            offset: self.offset,
+            ..shared
+        }
+    }
+}
+
+/// Parse a time of the form `HH:MM:SS`, optionally with a fraction of a second (`HH:MM:SS.mmm`
+/// or `HH:MM:SS,mmm`), of which digits past the milliseconds are ignored
+pub fn parse_time(s: &str) -> Result<Duration> {
+    let invalid = || format!("invalid time {:?}, expected HH:MM:SS", s);
+    let (hms, millis) = match s.split_once(['.', ',']) {
+        Some((hms, fraction)) => {
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                bail!("invalid fraction of a second in {:?}", s);
+            }
+            // `.5` is half a second, so the digits are padded or cut to milliseconds
+            let millis: String = fraction.chars().chain("00".chars()).take(3).collect();
+            (hms, millis.parse::<u64>()?)
+        }
+        None => (s, 0),
+    };
+    let parts: Vec<u64> = hms
+        .split(':')
+        .map(|p| p.parse())
+        .collect::<Result<_, _>>()
+        .with_context(invalid)?;
+    let [h, m, sec] = parts[..] else {
+        bail!(invalid())
+    };
+    if m >= 60 || sec >= 60 {
+        bail!("invalid time {:?}, minutes and seconds must be below 60", s);
+    }
+
+    h.checked_mul(3600)
+        .and_then(|secs| secs.checked_add(m * 60 + sec))
+        .and_then(|secs| secs.checked_mul(1000))
+        .and_then(|ms| ms.checked_add(millis))
+        .map(Duration::from_millis)
+        .with_context(|| format!("time {:?} is too large", s))
+}
+
+/// Parse an offset in seconds, given either as a number of seconds (`-1.5`) or as a time of the
+/// form `HH:MM:SS.mmm` or `MM:SS.mmm`, where a leading `-` makes the offset negative (`-0:05`)
+pub fn parse_offset(s: &str) -> Result<f64> {
+    let (sign, time) = match s.strip_prefix('-') {
+        Some(time) => (-1.0, time),
+        None => (1.0, s),
+    };
+    let time = match time.matches(':').count() {
+        0 => {
+            return s
+                .parse()
+                .ok()
+                .filter(|x: &f64| x.is_finite())
+                .with_context(|| format!("{:?} is not a number of seconds or a time", s))
+        }
+        1 => parse_time(&format!("0:{time}"))?,
+        _ => parse_time(time)?,
+    };
+    Ok(sign * time.as_secs_f64())
+}
+
+/// The named colors of CSS, which players accept in `<font color>` tags as well, and their
+/// `0xRRGGBB` values
+const CSS_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+/// Parse a color as accepted by `<font color>`: `#RGB`, `#RRGGBB` or a CSS color name such as
+/// `gold`, which is returned lowercased
+pub fn parse_color(s: &str) -> Result<String> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(s.to_owned());
+        }
+        bail!(
+            "invalid color {:?}, hex colors must be of the form #RGB or #RRGGBB",
+            s
+        )
+    }
+
+    let name = s.to_ascii_lowercase();
+    if CSS_COLORS.iter().any(|(n, _)| *n == name) {
+        return Ok(name);
+    }
+    bail!(
+        "invalid color {:?}, expected #RGB, #RRGGBB or a CSS color name such as white or gold",
+        s
+    )
+}
+
+/// The `0xRRGGBB` value of a color as accepted by `parse_color`, or `None` if it is not one
+pub fn color_rgb(color: &str) -> Option<u32> {
+    let Some(hex) = color.strip_prefix('#') else {
+        let name = color.to_ascii_lowercase();
+        return CSS_COLORS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, rgb)| *rgb);
+    };
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_owned(),
+        _ => return None,
+    };
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+/// Names of the built-in styling presets, see `builtin_preset`
+pub const BUILTIN_PRESETS: &[&str] = &["dim-top", "dim-bottom", "yellow-top", "boxed-bottom"];
+
+/// Look up one of the built-in styling presets, which bundle several styling options
+pub fn builtin_preset(name: &str) -> Option<TrackOptions> {
+    let (color, position, box_color) = match name {
+        "dim-top" => ("#a0a0a0", SubPosition::TopCenter, None),
+        "dim-bottom" => ("#a0a0a0", SubPosition::BottomCenter, None),
+        "yellow-top" => ("#ffff00", SubPosition::TopCenter, None),
+        "boxed-bottom" => ("#ffffff", SubPosition::BottomCenter, Some("#000000")),
+        _ => return None,
+    };
+    Some(TrackOptions {
+        color: Some(color.to_owned()),
+        position: Some(position),
+        box_color: box_color.map(|c| c.to_owned()),
+        ..Default::default()
+    })
+}
+
+/// Apply the changes described by `opts` to every cue of `srt`
+pub fn apply_sub_changes(srt: &mut SubRip, opts: &TrackOptions) {
+    retime(srt, opts).warn();
+    if opts.skip_before.is_some() || opts.skip_after.is_some() {
+        skip_outside(srt, opts.skip_before, opts.skip_after);
+    }
+    if let Some(range) = opts.keep_lines {
+        keep_lines(srt, range);
+    }
+    if let Some(gap) = opts.gap_merge {
+        merge_close(srt, gap);
+    }
+    if opts.fill_gaps {
+        fill_gaps(srt);
+    }
+    if let Some(min) = opts.min_duration {
+        extend_short(srt, min);
+    }
+    if opts.color_speakers {
+        color_speakers(srt);
+    }
+    if let Some(color) = &opts.annotation_color {
+        color_annotations(srt, color);
+    }
+
+    // Most tracks are only positioned or styled, so build each line in place
+    let position = match (opts.pos_xy, opts.position) {
+        (Some(point), _) => format!("{point} "),
+        (None, Some(p)) => format!("{p} "),
+        (None, None) => String::new(),
+    };
+    let box_tag = opts
+        .box_color
+        .as_deref()
+        .and_then(color_rgb)
+        .map(box_tag)
+        .unwrap_or_default();
+    let (color_start, color_end) = match &opts.color {
+        Some(color) => (format!("<font color=\"{color}\">"), "</font>"),
+        None => (String::new(), ""),
+    };
+    // The size goes inside the color, which stays the outermost tag for ASS output to style by
+    let (size_start, size_end) = match opts.size {
+        Some(size) => (format!("<font size=\"{size}\">"), "</font>"),
+        None => (String::new(), ""),
+    };
+    let (bold_start, bold_end) = if opts.bold { ("<b>", "</b>") } else { ("", "") };
+    let (italic_start, italic_end) = if opts.italic {
+        ("<i>", "</i>")
+    } else {
+        ("", "")
+    };
+    let extra = position.len()
+        + box_tag.len()
+        + color_start.len()
+        + color_end.len()
+        + size_start.len()
+        + size_end.len()
+        + bold_start.len()
+        + bold_end.len()
+        + italic_start.len()
+        + italic_end.len();
+
+    if extra > 0 {
+        for sub in &mut srt.subtitles {
+            for txt in &mut sub.text {
+                // A position tag the line already has is replaced, or kept if none is applied
+                let rest = split_position(txt).1;
+                let tag = if position.is_empty() {
+                    &txt[..txt.len() - rest.len()]
+                } else {
+                    position.as_str()
+                };
+                let (open, inner, close) = split_formatting(rest);
+
+                let mut line = String::with_capacity(txt.len() + extra);
+                line.push_str(tag);
+                line.push_str(&box_tag);
+                line.push_str(open);
+                line.push_str(&color_start);
+                line.push_str(&size_start);
+                line.push_str(bold_start);
+                line.push_str(italic_start);
+                line.push_str(inner);
+                line.push_str(italic_end);
+                line.push_str(bold_end);
+                line.push_str(size_end);
+                line.push_str(color_end);
+                line.push_str(close);
+                *txt = line;
+            }
+        }
+    }
+
+    // Wrapping goes after styling, so the tags added stay at the start of the first line
+    if let Some(width) = opts.wrap {
+        wrap_lines(srt, width);
+    }
+    if opts.plain {
+        strip_tags(srt);
+    }
+
+    // Snapping goes last, so the final timings are on the frame grid
+    if let Some(fps) = opts.snap_fps {
+        snap_to_frames(srt, fps);
+    }
+}
+
+/// Matches HTML-style tags (`<i>`, `<font color="..">`) and ASS override blocks (`{\an8}`)
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>|\{[^}]*\}").unwrap());
+
+/// Remove all tags from the text of each cue, see `TrackOptions::plain`
+fn strip_tags(srt: &mut SubRip) {
+    for sub in &mut srt.subtitles {
+        for txt in &mut sub.text {
+            if TAG.is_match(txt) {
+                *txt = TAG.replace_all(txt, "").trim().to_string();
+            }
+        }
+    }
+}
+
+/// Split each line longer than `width` characters into several at spaces, see `TrackOptions::wrap`
+fn wrap_lines(srt: &mut SubRip, width: usize) {
+    for sub in &mut srt.subtitles {
+        sub.text = sub
+            .text
+            .iter()
+            .flat_map(|txt| wrap_line(txt, width))
+            .collect();
+    }
+}
+
+/// Greedily fill lines of at most `width` visible characters with the words of `txt`. Tags are
+/// not counted and stay with the word following them, and words longer than `width` are kept
+/// whole on their own line.
+fn wrap_line(txt: &str, width: usize) -> Vec<String> {
+    let visible = |s: &str| TAG.replace_all(s, "").chars().count();
+    if visible(txt) <= width {
+        return vec![txt.to_owned()];
+    }
+
+    // Spaces inside tags, as in `<font color="..">`, do not separate words
+    let mut words = Vec::new();
+    let (mut start, mut tag_end) = (0, None);
+    for (i, c) in txt.char_indices() {
+        match (tag_end, c) {
+            (None, '<') => tag_end = Some('>'),
+            (None, '{') => tag_end = Some('}'),
+            (Some(end), c) if c == end => tag_end = None,
+            (None, ' ') => {
+                words.push(&txt[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    words.push(&txt[start..]);
+
+    let mut lines = Vec::new();
+    let (mut line, mut len) = (String::new(), 0);
+    for word in words.into_iter().filter(|w| !w.is_empty()) {
+        let n = visible(word);
+        if len > 0 && len + 1 + n > width {
+            lines.push(std::mem::take(&mut line));
+            len = 0;
+        }
+        if !line.is_empty() {
+            line.push(' ');
+            len += usize::from(len > 0);
+        }
+        line.push_str(word);
+        len += n;
+    }
+    lines.push(line);
+    lines
+}
+
+/// Split the formatting tags wrapping a whole line off it, e.g. `<i><b>` and `</b></i>` of
+/// `<i><b>text</b></i>`, returning the opening tags, the text and the closing tags. Colors are
+/// inserted between them, as some players mishandle `<font>` tags around other tags.
+fn split_formatting(txt: &str) -> (&str, &str, &str) {
+    let (mut start, mut end) = (0, txt.len());
+    'peel: loop {
+        let inner = &txt[start..end];
+        for (open, close) in [("<i>", "</i>"), ("<b>", "</b>"), ("<u>", "</u>")] {
+            // The tags only wrap the whole line if the closing tag is not also used before the end
+            if let Some(body) = inner
+                .strip_prefix(open)
+                .and_then(|rest| rest.strip_suffix(close))
+                && !body.contains(close)
+            {
+                start += open.len();
+                end -= close.len();
+                continue 'peel;
+            }
+        }
+        return (&txt[..start], &txt[start..end], &txt[end..]);
+    }
+}
+
+/// Multiply every timestamp of `srt` by `factor`
+pub(crate) fn scale(srt: &mut SubRip, factor: f64) {
+    let scale = |ts: SrtTimestamp| Into::<Duration>::into(ts).mul_f64(factor).into();
+    for cue in &mut srt.subtitles {
+        cue.start = scale(cue.start);
+        cue.end = scale(cue.end);
+    }
+}
+
+/// The cues that retiming moved before 00:00:00, see `retime`
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct NegativeCues {
+    /// Cues ending at or before 00:00:00, which are dropped
+    pub dropped: usize,
+    /// Cues starting before 00:00:00 but ending after it, which start at 00:00:00 instead
+    pub clamped: usize,
+}
+
+impl NegativeCues {
+    pub(crate) fn add(&mut self, other: NegativeCues) {
+        self.dropped += other.dropped;
+        self.clamped += other.clamped;
+    }
+
+    pub(crate) fn warn(&self) {
+        if self.dropped > 0 {
+            warn!(
+                reason = "negative_timestamps";
+                "The offset, anchor or sync map moves {} cues entirely before 00:00:00, dropping them", self.dropped
+            );
+        }
+        if self.clamped > 0 {
+            warn!(
+                reason = "negative_timestamps";
+                "The offset, anchor or sync map moves the start of {} cues before 00:00:00, starting them at 00:00:00 instead",
+                self.clamped
+            );
+        }
+    }
+}
+
+/// Apply the timing changes of `opts` to `srt`: the framerate, sync map, anchor and offset.
+/// Returns the cues moved before 00:00:00, which are dropped or start at 00:00:00 instead.
+pub(crate) fn retime(srt: &mut SubRip, opts: &TrackOptions) -> NegativeCues {
+    let mut negative = NegativeCues::default();
+    // Scaling goes before the offset, so the offset is in the timing of the target framerate
+    if let Some((from, to)) = opts.fps_scale {
+        scale(srt, from / to);
+    }
+    if let Some(map) = &opts.sync_map {
+        negative.add(warp(srt, map));
+    }
+    if let Some(anchor) = opts.anchor {
+        negative.add(anchor_track(srt, anchor));
+    }
+    if let Some(offset) = opts.offset {
+        negative.add(shift(srt, offset, opts.offset_from));
+    }
+    if negative.dropped > 0 {
+        renumber(srt);
+    }
+    negative
+}
+
+/// Shift every timestamp of `srt` by `offset` seconds, or only those of the cues starting at or
+/// after `from`. Cues moved entirely before the start are dropped, and the start of those still
+/// shown after it is clamped to zero.
+fn shift(srt: &mut SubRip, offset: f64, from: Option<Duration>) -> NegativeCues {
+    let by = Duration::from_secs_f64(offset.abs());
+    let shift = |ts: SrtTimestamp| {
+        let ts: Duration = ts.into();
+        if offset < 0.0 {
+            ts.saturating_sub(by).into()
+        } else {
+            (ts + by).into()
+        }
+    };
+
+    let shifted = |cue: &SrtSubtitle| {
+        let start: Duration = cue.start.into();
+        from.is_none_or(|from| start >= from)
+    };
+    // Only cues shifted earlier by more than their start or end can be moved before the start
+    let hidden = |cue: &SrtSubtitle| {
+        let end: Duration = cue.end.into();
+        offset < 0.0 && shifted(cue) && end <= by
+    };
+    let clamped = |cue: &SrtSubtitle| {
+        let start: Duration = cue.start.into();
+        offset < 0.0 && shifted(cue) && start < by
+    };
+    let len = srt.subtitles.len();
+    srt.subtitles.retain(|cue| !hidden(cue));
+    let clamped = srt.subtitles.iter().filter(|cue| clamped(cue)).count();
+    for cue in srt.subtitles.iter_mut().filter(|cue| shifted(cue)) {
+        cue.start = shift(cue.start);
+        cue.end = shift(cue.end);
+    }
+
+    NegativeCues {
+        dropped: len - srt.subtitles.len(),
+        clamped,
+    }
+}
+
+/// Remap the timings of every cue of `srt` by `map`. Cues mapped entirely before 00:00:00 are
+/// dropped, and the start of those still shown after it is clamped to 00:00:00.
+fn warp(srt: &mut SubRip, map: &SyncMap) -> NegativeCues {
+    let mapped = |ts: SrtTimestamp| {
+        let ts: Duration = ts.into();
+        map.map(ts.as_secs_f64())
+    };
+    let len = srt.subtitles.len();
+    srt.subtitles.retain(|cue| mapped(cue.end) > 0.0);
+    let clamped = srt
+        .subtitles
+        .iter()
+        .filter(|cue| mapped(cue.start) < 0.0)
+        .count();
+    let warped =
+        |ts: SrtTimestamp| Duration::from_millis((mapped(ts) * 1000.0).round().max(0.0) as u64);
+    for cue in &mut srt.subtitles {
+        cue.start = warped(cue.start).into();
+        cue.end = warped(cue.end).into();
+    }
+
+    NegativeCues {
+        dropped: len - srt.subtitles.len(),
+        clamped,
+    }
+}
+
+/// Fail if `opts` changes the text of cues, which ASS events styled by their file keep as it is
+fn check_preservable(opts: &TrackOptions) -> Result<()> {
+    let text_changes = [
+        (opts.color.is_some(), "--color"),
+        (opts.size.is_some(), "--sub1-size/--sub2-size"),
+        (opts.bold, "--sub1-bold/--sub2-bold"),
+        (opts.italic, "--sub1-italic/--sub2-italic"),
+        (opts.pos_xy.is_some(), "--sub1-pos-xy/--sub2-pos-xy"),
+        (opts.box_color.is_some(), "--sub2-box-color"),
+        (opts.fill_gaps, "--fill-gaps"),
+        (opts.gap_merge.is_some(), "--gap-merge"),
+        (opts.min_duration.is_some(), "--min-duration"),
+        (opts.color_speakers, "--color-speakers"),
+        (opts.annotation_color.is_some(), "--hi-annotation-color"),
+        (
+            opts.keep_lines.is_some(),
+            "--sub1-keep-line/--sub2-keep-line",
+        ),
+        (opts.wrap.is_some(), "--wrap"),
+        (opts.plain, "--plain"),
+    ];
+    if let Some((_, flag)) = text_changes.iter().find(|(set, _)| *set) {
+        bail!(
+            "{} only applies to tracks merged as SRT, merge without --preserve-style",
+            flag
+        );
+    }
+    Ok(())
+}
+
+/// Apply the timing changes of `opts` to the events of `ass`: the framerate, anchor, offset,
+/// skipping and snapping. Options changing the text of cues are refused, as the events keep
+/// their own styling. The position is ignored, events are placed by their style.
+pub fn apply_ass_changes(ass: &mut Ass, opts: &TrackOptions) -> Result<()> {
+    check_preservable(opts)?;
+    let timing = TrackOptions {
+        fps_scale: opts.fps_scale,
+        anchor: opts.anchor,
+        sync_map: opts.sync_map.clone(),
+        offset: opts.offset,
+        offset_from: opts.offset_from,
+        skip_before: opts.skip_before,
+        skip_after: opts.skip_after,
+        snap_fps: opts.snap_fps,
+        ..Default::default()
+    };
+
+    // Reuse the SRT timing changes, with each cue holding the index of its event as its text
+    let mut srt = SubRip {
+        subtitles: ass
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| SrtSubtitle {
+                sequence: i as u32 + 1,
+                start: event.start.into(),
+                end: event.end.into(),
+                text: vec![i.to_string()],
+                line_position: None,
+            })
+            .collect(),
+    };
+    apply_sub_changes(&mut srt, &timing);
+
+    let mut events: Vec<_> = std::mem::take(&mut ass.events)
+        .into_iter()
+        .map(Some)
+        .collect();
+    for cue in srt.subtitles {
+        let i: usize = cue.text[0].parse()?;
+        let mut event = events[i]
+            .take()
+            .context("impossible error: event retimed twice")?;
+        event.start = cue.start.into();
+        event.end = cue.end.into();
+        ass.events.push(event);
+    }
+    Ok(())
+}
+
+/// Shift every cue of `srt` so the cue of `anchor` starts at its time
+fn anchor_track(srt: &mut SubRip, anchor: Anchor) -> NegativeCues {
+    let Some(cue) = srt.subtitles.get(anchor.cue - 1) else {
+        warn!(
+            reason = "missing_anchor";
+            "The track has only {} cues, unable to anchor cue {}", srt.subtitles.len(), anchor.cue
+        );
+        return NegativeCues::default();
+    };
+    let start: Duration = cue.start.into();
+    let offset = anchor.at.as_secs_f64() - start.as_secs_f64();
+    info!(
+        "Anchoring cue {} shifts the track by {:.3}s",
+        anchor.cue, offset
+    );
+    shift(srt, offset, None)
+}
+
+/// Drop the cues starting before `before` or at or after `after`, renumbering the rest
+fn skip_outside(srt: &mut SubRip, before: Option<Duration>, after: Option<Duration>) {
+    let len = srt.subtitles.len();
+    srt.subtitles.retain(|s| {
+        let start: Duration = s.start.into();
+        before.is_none_or(|t| start >= t) && after.is_none_or(|t| start < t)
+    });
+
+    info!("Skipped {} cues", len - srt.subtitles.len());
+    renumber(srt);
+}
+
+/// The longest a cue combined by `TrackOptions::gap_merge` may be shown for
+pub const MAX_GAP_MERGED: Duration = Duration::from_secs(7);
+
+/// Combine each cue starting less than `gap` after the end of the cue before it into that cue,
+/// joining their text with a space, unless it would be shown for longer than `MAX_GAP_MERGED`
+fn merge_close(srt: &mut SubRip, gap: Duration) {
+    let mut merged: Vec<SrtSubtitle> = Vec::with_capacity(srt.subtitles.len());
+    for cue in std::mem::take(&mut srt.subtitles) {
+        if let Some(last) = merged.last_mut() {
+            let (first_start, end): (Duration, Duration) = (last.start.into(), last.end.into());
+            let (start, last_end): (Duration, Duration) = (cue.start.into(), cue.end.into());
+            let shown = last_end.saturating_sub(first_start);
+            if start >= end && start - end < gap && shown <= MAX_GAP_MERGED {
+                let mut lines = cue.text.into_iter();
+                match (last.text.last_mut(), lines.next()) {
+                    (Some(txt), Some(first)) => {
+                        txt.push(' ');
+                        txt.push_str(&first);
+                    }
+                    (None, first) => last.text.extend(first),
+                    (_, None) => (),
+                }
+                last.text.extend(lines);
+                last.end = last.end.max(cue.end);
+                continue;
+            }
+        }
+        merged.push(cue);
+    }
+
+    srt.subtitles = merged;
+    renumber(srt);
+}
+
+/// Only keep the lines of each cue in `range`. Cues with fewer lines than the start of the
+/// range are kept whole, with a warning.
+fn keep_lines(srt: &mut SubRip, range: LineRange) {
+    let mut short = 0;
+    for sub in &mut srt.subtitles {
+        if sub.text.len() < range.first {
+            short += 1;
+            continue;
+        }
+        sub.text.truncate(range.last);
+        sub.text.drain(..range.first - 1);
+    }
+
+    if short > 0 {
+        warn!(
+            reason = "short_cue";
+            "{} cues have fewer than {} lines, keeping them whole", short, range.first
+        );
+    }
+}
+
+/// Extend the end of every cue but the last to the start of the cue following it.
+/// Cues already overlapping the following cue are left as they are.
+fn fill_gaps(srt: &mut SubRip) {
+    for i in 1..srt.subtitles.len() {
+        let next_start = srt.subtitles[i].start;
+        let cue = &mut srt.subtitles[i - 1];
+        if cue.end < next_start {
+            cue.end = next_start;
+        }
+    }
+}
+
+/// Extend the end of every cue shown for less than `min`, up to the start of the cue following it
+fn extend_short(srt: &mut SubRip, min: Duration) {
+    for i in 0..srt.subtitles.len() {
+        let next_start = srt.subtitles.get(i + 1).map(|next| next.start);
+        let cue = &mut srt.subtitles[i];
+        let (start, end): (Duration, Duration) = (cue.start.into(), cue.end.into());
+        if end.saturating_sub(start) >= min {
+            continue;
+        }
+
+        let extended: SrtTimestamp = (start + min).into();
+        cue.end = match next_start {
+            Some(next_start) => extended.min(next_start).max(cue.end),
+            None => extended,
+        };
+    }
+}
+
+/// Round every timestamp of `srt` to the nearest frame boundary at `fps`, and then to the
+/// nearest millisecond, since that is the precision of SRT timestamps
+fn snap_to_frames(srt: &mut SubRip, fps: f64) {
+    let snap = |ts: SrtTimestamp| {
+        let ms = Into::<Duration>::into(ts).as_millis() as f64;
+        let frame = (ms * fps / 1000.0).round();
+        Duration::from_millis((frame * 1000.0 / fps).round() as u64).into()
+    };
+    for cue in &mut srt.subtitles {
+        cue.start = snap(cue.start);
+        cue.end = snap(cue.end);
+    }
+}
+
+/// Matches a sound description of hearing impaired subtitles, e.g. `[door slams]`
+static ANNOTATION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[[^\]]*\]").unwrap());
+
+/// Color the sound descriptions of `srt`, leaving the dialogue around them as it is
+fn color_annotations(srt: &mut SubRip, color: &str) {
+    let replacement = format!("<font color=\"{color}\">$0</font>");
+    for txt in srt.subtitles.iter_mut().flat_map(|s| &mut s.text) {
+        if ANNOTATION.is_match(txt) {
+            *txt = ANNOTATION
+                .replace_all(txt, replacement.as_str())
+                .into_owned();
+        }
+    }
+}
+
+/// Colors assigned to speakers by `color_speakers`, in order of appearance
+pub const SPEAKER_PALETTE: &[&str] = &[
+    "#fabd2f", "#83a598", "#fb4934", "#b8bb26", "#d3869b", "#8ec07c", "#fe8019", "#bdae93",
+];
+
+/// Matches a leading speaker label such as `JOHN:` or `- DR. SMITH:`
+static SPEAKER_LABEL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(-\s*)?(?P<speaker>[A-Z][A-Z0-9 .'-]*):\s").unwrap());
+
+/// Wrap every line starting with a speaker label in a color for that speaker. Speakers get
+/// colors from `SPEAKER_PALETTE` in the order they first appear, so a speaker keeps their
+/// color throughout the track.
+fn color_speakers(srt: &mut SubRip) {
+    let mut colors: HashMap<String, &str> = HashMap::new();
+
+    for sub in &mut srt.subtitles {
+        for txt in &mut sub.text {
+            let Some(captures) = SPEAKER_LABEL.captures(txt) else {
+                continue;
+            };
+            let speaker = captures["speaker"].trim().to_owned();
+            let next = SPEAKER_PALETTE[colors.len() % SPEAKER_PALETTE.len()];
+            let color = *colors.entry(speaker).or_insert(next);
+            *txt = format!("<font color=\"{color}\">{txt}</font>");
+        }
+    }
+    trace!("Colored speakers: {:?}", colors);
+}
+
+/// What to do when a cue from each track claims the same slot, i.e. they have identical timings
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep both cues
+    #[default]
+    Keep,
+    /// Keep only the cue from the first track (the earliest track, when merging more than two)
+    Prefer1,
+    /// Keep only the cue from the second track (the latest track, when merging more than two)
+    Prefer2,
+    /// Combine both cues into one, with the text of the first track on top
+    Concat,
+}
+
+/// The order of the cues of a merged track, which it is numbered in
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// Interleave the tracks by start time
+    #[default]
+    Time,
+    /// All cues of the first track by start time, then those of the second
+    Track,
+    /// All cues of the first track in the order of its file, then those of the second
+    None,
+}
+
+/// Options controlling how `merge` combines the tracks
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    /// What to do with cues of several tracks that have identical timings
+    pub on_conflict: ConflictPolicy,
+    /// Collapse overlapping cues with the same text, ignoring the styling added by
+    /// `apply_sub_changes`, into one spanning both
+    pub dedup: bool,
+    /// The order of the merged cues. Cues combined from several tracks are ordered as the one
+    /// of the earliest track.
+    pub sort_by: SortBy,
+    /// Combine cues of different tracks overlapping in time at the same position into one cue
+    /// spanning them all, with the lines of the earlier track first
+    pub combine_overlapping: bool,
+}
+
+/// Where a cue of a merged track came from, see `merge_with_sources`. All indices count from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CueSource {
+    /// Index of the cue in the merged track
+    pub merged: usize,
+    /// The track the cue came from, `1` for the first track
+    pub track: usize,
+    /// Index of the cue in its original track
+    pub original: usize,
+}
+
+/// A summary of a merge, to sanity check it, see `merge_tracks_with_stats`. Displayed like
+/// `120 + 98 cues merged into 218 cues spanning 00:00:01,000 --> 01:42:03,500`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergeStats {
+    /// The number of cues of each track before merging, e.g. `0` for a file without any cues
+    pub track_cues: Vec<usize>,
+    /// The number of cues after merging, fewer than those of the tracks if conflicting or
+    /// duplicate cues were combined
+    pub merged_cues: usize,
+    /// The start of the earliest merged cue
+    pub earliest: Option<Duration>,
+    /// The end of the latest merged cue
+    pub latest: Option<Duration>,
+}
+
+impl std::fmt::Display for MergeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let counts: Vec<String> = self.track_cues.iter().map(|n| n.to_string()).collect();
+        write!(
+            f,
+            "{} cues merged into {} cues",
+            counts.join(" + "),
+            self.merged_cues
+        )?;
+        if let Some((earliest, latest)) = self.earliest.zip(self.latest) {
+            let (earliest, latest): (SrtTimestamp, SrtTimestamp) = (earliest.into(), latest.into());
+            write!(f, " spanning {} --> {}", earliest, latest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Combine two subtitle tracks into one in the order of `MergeOptions::sort_by`, chronological
+/// by default, renumbering the result
+pub fn merge(srt1: SubRip, srt2: SubRip, opts: &MergeOptions) -> SubRip {
+    merge_tracks(vec![srt1, srt2], opts)
+}
+
+/// Combine any number of subtitle tracks into one in the order of `MergeOptions::sort_by`,
+/// renumbering the result
+pub fn merge_tracks(tracks: Vec<SubRip>, opts: &MergeOptions) -> SubRip {
+    merge_tracks_with_sources(tracks, opts).0
+}
+
+/// Like `merge_tracks`, but also return where each merged cue came from. A cue combined from
+/// several tracks (see `ConflictPolicy::Concat`) has a source for each track.
+pub fn merge_tracks_with_sources(
+    tracks: Vec<SubRip>,
+    opts: &MergeOptions,
+) -> (SubRip, Vec<CueSource>) {
+    let (merged, sources, _) = merge_tracks_with_stats(tracks, opts);
+    (merged, sources)
+}
+
+/// Like `merge_tracks_with_sources`, but also return a summary of the merge
+pub fn merge_tracks_with_stats(
+    tracks: Vec<SubRip>,
+    opts: &MergeOptions,
+) -> (SubRip, Vec<CueSource>, MergeStats) {
+    let track_cues = tracks.iter().map(|srt| srt.subtitles.len()).collect();
+    let mut merged_subs = SubRip::default();
+    // The (track, original index) pairs of each merged cue
+    let mut origins: Vec<Vec<(usize, usize)>> = Vec::new();
+    // Cues of the earlier tracks by their timings, used to detect conflicting cues
+    let mut slots: HashMap<_, usize> = HashMap::new();
+
+    for (track, srt) in tracks.into_iter().enumerate() {
+        let first = merged_subs.subtitles.len();
+
+        for (j, sub) in srt.subtitles.into_iter().enumerate() {
+            let origin = (track + 1, j + 1);
+            let Some(&i) = slots.get(&(sub.start, sub.end)) else {
+                merged_subs.subtitles.push(sub);
+                origins.push(vec![origin]);
+                continue;
+            };
+
+            trace!(
+                "Cue at {} --> {} is present in several tracks",
+                sub.start,
+                sub.end
+            );
+            match opts.on_conflict {
+                ConflictPolicy::Keep => {
+                    merged_subs.subtitles.push(sub);
+                    origins.push(vec![origin]);
+                }
+                ConflictPolicy::Prefer1 => (),
+                ConflictPolicy::Prefer2 => {
+                    merged_subs.subtitles[i].text = sub.text;
+                    origins[i] = vec![origin];
+                }
+                ConflictPolicy::Concat => {
+                    merged_subs.subtitles[i].text.extend(sub.text);
+                    origins[i].push(origin);
+                }
+            }
+        }
+
+        if opts.on_conflict != ConflictPolicy::Keep {
+            for (i, sub) in merged_subs.subtitles.iter().enumerate().skip(first) {
+                slots.entry((sub.start, sub.end)).or_insert(i);
+            }
+        }
+    }
+
+    // Interleave the tracks by start time, the sort being stable keeps the cue of the earlier
+    // track first when both start at the same time
+    let mut cues: Vec<_> = merged_subs.subtitles.into_iter().zip(origins).collect();
+    cues.sort_by_key(|(sub, _)| sub.start);
+    if opts.dedup {
+        cues = dedup(cues);
+    }
+    if opts.combine_overlapping {
+        cues = combine_overlapping(cues);
+    }
+    // Duplicates are found in chronological order, whichever order the cues end up in
+    match opts.sort_by {
+        SortBy::Time => (),
+        SortBy::Track => cues.sort_by_key(|(sub, origin)| (origin[0].0, sub.start)),
+        SortBy::None => cues.sort_by_key(|(_, origin)| origin[0]),
+    }
+    let (subtitles, origins): (Vec<_>, Vec<_>) = cues.into_iter().unzip();
+    merged_subs.subtitles = subtitles;
+
+    renumber(&mut merged_subs);
+    let sources = origins
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, origin)| {
+            origin.into_iter().map(move |(track, original)| CueSource {
+                merged: i + 1,
+                track,
+                original,
+            })
+        })
+        .collect();
+
+    let stats = MergeStats {
+        track_cues,
+        merged_cues: merged_subs.subtitles.len(),
+        earliest: merged_subs
+            .subtitles
+            .iter()
+            .map(|sub| sub.start.into())
+            .min(),
+        latest: merged_subs.subtitles.iter().map(|sub| sub.end.into()).max(),
+    };
+    (merged_subs, sources, stats)
+}
+
+/// Matches the position and color tags added by `apply_sub_changes`
+static STYLING_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\\(an[1-9]|pos\([^)]*\))\} ?|</?font[^>]*>").unwrap());
+
+/// Collapse each cue overlapping the one before it with the same text into it, extending it to
+/// the end of both. Text is compared without styling tags, so the same line in two differently
+/// styled tracks is a duplicate. Expects the cues sorted by start time.
+fn dedup<T>(cues: Vec<(SrtSubtitle, Vec<T>)>) -> Vec<(SrtSubtitle, Vec<T>)> {
+    let unstyled = |sub: &SrtSubtitle| -> Vec<String> {
+        sub.text
+            .iter()
+            .map(|txt| STYLING_TAG.replace_all(txt, "").into_owned())
+            .collect()
+    };
+
+    let mut deduped: Vec<(SrtSubtitle, Vec<T>)> = Vec::with_capacity(cues.len());
+    for (sub, origin) in cues {
+        if let Some((last, last_origin)) = deduped.last_mut()
+            && sub.start < last.end
+            && unstyled(last) == unstyled(&sub)
+        {
+            trace!("Removing duplicate cue at {} --> {}", sub.start, sub.end);
+            last.end = last.end.max(sub.end);
+            last_origin.extend(origin);
+            continue;
+        }
+        deduped.push((sub, origin));
+    }
+    deduped
+}
+
+/// Combine each cue overlapping the ones before it at the same position into them, if they
+/// include a cue of another track, into one cue spanning all of them. The lines of each track
+/// follow each other in track order, only the first keeping its position tag, as a blank line
+/// would end the cue. Expects the cues sorted by start time.
+fn combine_overlapping(
+    cues: Vec<(SrtSubtitle, Vec<(usize, usize)>)>,
+) -> Vec<(SrtSubtitle, Vec<(usize, usize)>)> {
+    let position = |text: &[String]| {
+        let first = text.first().and_then(|txt| split_position(txt).0);
+        first.unwrap_or_default()
+    };
+
+    // The cues combined so far, and the lines of each of their cues by track
+    let mut combined: Vec<(SrtSubtitle, Vec<(usize, usize)>)> = Vec::with_capacity(cues.len());
+    let mut lines: Vec<Vec<(usize, Vec<String>)>> = Vec::with_capacity(cues.len());
+    for (mut sub, origin) in cues {
+        let track = origin[0].0;
+        if let Some((last, last_origin)) = combined.last_mut()
+            && let Some(last_lines) = lines.last_mut()
+            && sub.start < last.end
+            && last_origin.iter().any(|&(t, _)| t != track)
+            && position(&last_lines[0].1) == position(&sub.text)
+        {
+            trace!("Combining overlapping cue at {} --> {}", sub.start, sub.end);
+            last.end = last.end.max(sub.end);
+            last_origin.extend(origin);
+            last_lines.push((track, std::mem::take(&mut sub.text)));
+            continue;
+        }
+        lines.push(vec![(track, std::mem::take(&mut sub.text))]);
+        combined.push((sub, origin));
+    }
+
+    combined
+        .into_iter()
+        .zip(lines)
+        .map(|((mut sub, mut origin), mut lines)| {
+            lines.sort_by_key(|(track, _)| *track);
+            origin.sort();
+            sub.text = lines.into_iter().flat_map(|(_, text)| text).collect();
+            for txt in sub.text.iter_mut().skip(1) {
+                let rest = split_position(txt).1;
+                if rest.len() < txt.len() {
+                    *txt = rest.to_owned();
+                }
+            }
+            (sub, origin)
+        })
+        .collect()
+}
+
+/// Write `sources` as tab separated `merged_index`, `track` and `original_index` lines
+pub fn write_crossref(path: &Path, sources: &[CueSource]) -> Result<()> {
+    let mut tsv = String::new();
+    for s in sources {
+        tsv.push_str(&format!("{}\t{}\t{}\n", s.merged, s.track, s.original));
+    }
+    fs::write(path, tsv).context(format!("unable to write crossref file {:?}", path))
+}
+
+/// A cue as written by `dump_json`, mirroring `SrtSubtitle`, which is not serializable
+#[derive(Serialize, Debug)]
+pub struct DumpedCue {
+    /// The sequence number of the cue in its track
+    pub sequence: u32,
+    /// The start time as written in SRT, e.g. `00:01:02,500`
+    pub start: String,
+    /// The end time as written in SRT
+    pub end: String,
+    /// The lines of the cue, with all tags written literally
+    pub text: Vec<String>,
+}
+
+/// The cues of a track, see `dump_json`
+#[derive(Serialize, Debug)]
+pub struct DumpedTrack {
+    /// The file the track was read from
+    pub path: PathBuf,
+    /// The cues of the track in order
+    pub cues: Vec<DumpedCue>,
+}
+
+impl DumpedTrack {
+    /// Mirror the cues of `srt`, read from `path`
+    pub fn new(path: &Path, srt: &SubRip) -> Self {
+        let cues = srt
+            .subtitles
+            .iter()
+            .map(|sub| DumpedCue {
+                sequence: sub.sequence,
+                start: sub.start.to_string(),
+                end: sub.end.to_string(),
+                text: sub.text.clone(),
+            })
+            .collect();
+        DumpedTrack {
+            path: path.to_owned(),
+            cues,
+        }
+    }
+}
+
+/// Write `tracks` to `path` as a JSON array, for inspecting what parsing and
+/// `apply_sub_changes` made of each track before merging
+pub fn dump_json(path: &Path, tracks: &[DumpedTrack]) -> Result<()> {
+    let file = File::create(path).context(format!("unable to create {:?}", path))?;
+    serde_json::to_writer_pretty(io::BufWriter::new(file), tracks)
+        .context(format!("unable to write {:?}", path))
+}
+
+/// Render the first `n` cues of `srt` as SRT, with all tags written literally, followed by how
+/// many cues are left out
+pub fn preview_cues(srt: &SubRip, n: usize) -> String {
+    let shown = SubRip {
+        subtitles: srt.subtitles.iter().take(n).cloned().collect(),
+    };
+    let mut preview = shown.render();
+    let rest = srt.subtitles.len().saturating_sub(n);
+    if rest > 0 {
+        preview.push_str(&format!("\n... and {} more cues\n", rest));
+    }
+    preview
+}
+
+/// Number the cues of `srt` from 1 in the order they appear
+pub fn renumber(srt: &mut SubRip) {
+    for (i, cue) in srt.subtitles.iter_mut().enumerate() {
+        cue.sequence = i as u32 + 1;
+    }
+}
+
+/// Matches a position tag at the start of a line, such as `{\an8}`
+static POSITION_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\{\\an[1-9]\}").unwrap());
+
+/// Split the position tag off the start of a line written by `apply_sub_changes`, returning
+/// the position and the rest of the line
+pub fn split_position(txt: &str) -> (Option<SubPosition>, &str) {
+    match POSITION_TAG.find(txt) {
+        Some(tag) => {
+            let rest = &txt[tag.end()..];
+            (
+                tag.as_str().parse().ok(),
+                rest.strip_prefix(' ').unwrap_or(rest),
+            )
+        }
+        None => (None, txt),
+    }
+}
+
+/// Matches the override block of a background box written by `box_tag` at the start of a line,
+/// capturing its color as an ASS `&HBBGGRR` color
+static BOX_TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\{\\bord0\\shad0\\3c(?P<color>&H[0-9A-F]{6})&\\4c&H[0-9A-F]{6}&\}").unwrap()
+});
+
+/// The override block giving a line an opaque background box of the `0xRRGGBB` color `rgb`,
+/// written after its position tag. Players honoring override tags drop the outline and shadow
+/// and color the box, which ASS output shows with a boxed style, see `ass::render`.
+pub fn box_tag(rgb: u32) -> String {
+    let color = ass_color(rgb);
+    format!("{{\\bord0\\shad0\\3c{color}&\\4c{color}&}}")
+}
+
+/// Split the background box tag off the start of a line, after its position tag, returning its
+/// ASS color and the rest of the line
+pub fn split_box(txt: &str) -> (Option<&str>, &str) {
+    match BOX_TAG.captures(txt) {
+        Some(c) => (
+            Some(c.name("color").unwrap().as_str()),
+            &txt[c.get(0).unwrap().end()..],
+        ),
+        None => (None, txt),
+    }
+}
+
+/// `srt` without the background box tags of its lines, for the output formats unable to show
+/// boxes
+fn without_boxes(srt: &SubRip) -> Cow<'_, SubRip> {
+    let boxed = |txt: &String| BOX_TAG.is_match(split_position(txt).1);
+    if !srt.subtitles.iter().flat_map(|s| &s.text).any(boxed) {
+        return Cow::Borrowed(srt);
+    }
+    let mut srt = srt.clone();
+    for txt in srt.subtitles.iter_mut().flat_map(|s| &mut s.text) {
+        strip_box(txt);
+    }
+    Cow::Owned(srt)
+}
+
+/// Remove the background box tag of a line, keeping its position tag
+pub(crate) fn strip_box(txt: &mut String) {
+    let rest = split_position(txt).1;
+    let (color, after) = split_box(rest);
+    if color.is_some() {
+        let start = txt.len() - rest.len();
+        let end = txt.len() - after.len();
+        txt.replace_range(start..end, "");
+    }
+}
+
+/// Count how many cues of `srt` carry each position tag, `None` counting cues without one.
+/// The position of a cue is taken from its first line.
+pub fn position_counts(srt: &SubRip) -> BTreeMap<Option<SubPosition>, usize> {
+    let mut counts = BTreeMap::new();
+    for sub in &srt.subtitles {
+        let position = sub.text.first().and_then(|txt| split_position(txt).0);
+        *counts.entry(position).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Whether `out` exists and should be skipped, for incremental runs. Unlike `WriteMode::NewOnly`,
+/// this is meant to be checked before any input is read.
+pub fn exists_already(out: &Path) -> bool {
+    let exists = out.exists();
+    if exists {
+        info!("Skipping {:?}, it already exists", out);
+    }
+    exists
+}
+
+/// How output files are written
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Write to a temporary file next to the output and rename it into place, so a partially
+    /// written file is never seen. Renaming may behave badly on some network filesystems, and
+    /// falls back to copying the file if the rename crosses filesystems.
+    #[default]
+    Atomic,
+    /// Truncate and write the output file directly
+    Truncate,
+    /// Refuse to write to an output file that already exists
+    NewOnly,
+}
+
+/// The format merged subtitles are written in
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// SubRip
+    #[default]
+    Srt,
+    /// WebVTT, used by web players
+    Vtt,
+    /// SAMI, used by some (mostly Korean) media players
+    Smi,
+    /// Advanced SubStation Alpha, with positions and colors as styles rather than inline tags
+    Ass,
+}
+
+impl OutputFormat {
+    /// The file extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Srt => "srt",
+            OutputFormat::Vtt => "vtt",
+            OutputFormat::Smi => "smi",
+            OutputFormat::Ass => "ass",
+        }
+    }
+}
+
+/// The line endings written files use
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum LineEndings {
+    /// `\n`, as used on Linux and macOS
+    #[default]
+    Lf,
+    /// `\r\n`, as used on Windows and expected by some hardware players
+    Crlf,
+}
+
+impl LineEndings {
+    /// Convert all line endings of `text`, which may mix `\r\n`, `\n` and lone `\r` when cues
+    /// come from files written on different systems
+    pub fn normalize(self, text: &str) -> String {
+        let lf = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self {
+            LineEndings::Lf => lf,
+            LineEndings::Crlf => lf.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// The character encoding written files use
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// UTF-8, without a byte order mark
+    #[default]
+    #[value(name = "utf-8")]
+    Utf8,
+    /// UTF-16 little endian, starting with a byte order mark
+    #[value(name = "utf-16le")]
+    Utf16le,
+    /// The legacy encoding of Western European languages, expected by some hardware players
+    #[value(name = "windows-1252")]
+    Windows1252,
+}
+
+impl OutputEncoding {
+    /// The byte order mark files in this encoding start with, empty if they have none
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            OutputEncoding::Utf16le => &[0xff, 0xfe],
+            OutputEncoding::Utf8 | OutputEncoding::Windows1252 => &[],
+        }
+    }
+
+    /// Encode `text`, without a byte order mark. Returns the bytes and how many characters the
+    /// encoding is unable to represent, which are written as `?`.
+    pub fn encode(self, text: &str) -> (Vec<u8>, usize) {
+        match self {
+            OutputEncoding::Utf8 => (text.as_bytes().to_vec(), 0),
+            // encoding_rs only decodes UTF-16, it encodes the UTF-16 encodings as UTF-8
+            OutputEncoding::Utf16le => {
+                (text.encode_utf16().flat_map(u16::to_le_bytes).collect(), 0)
+            }
+            OutputEncoding::Windows1252 => {
+                let mut encoder = encoding_rs::WINDOWS_1252.new_encoder();
+                let mut bytes = Vec::with_capacity(text.len());
+                let mut unmappable = 0;
+                let mut rest = text;
+                loop {
+                    // Every character is a single byte or replaced by one
+                    bytes.reserve(rest.len());
+                    let (result, read) =
+                        encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut bytes, true);
+                    rest = &rest[read..];
+                    match result {
+                        EncoderResult::InputEmpty => break,
+                        EncoderResult::OutputFull => (),
+                        EncoderResult::Unmappable(_) => {
+                            unmappable += 1;
+                            bytes.push(b'?');
+                        }
+                    }
+                }
+                (bytes, unmappable)
+            }
+        }
+    }
+
+    /// Encode the contents of the file `path` like `encode`, starting with the byte order mark
+    /// and warning about any characters the encoding is unable to represent
+    pub(crate) fn encode_file(self, path: &Path, text: &str) -> Vec<u8> {
+        let (bytes, unmappable) = self.encode(text);
+        self.warn_unmappable(path, unmappable);
+        [self.bom(), &bytes].concat()
+    }
+
+    pub(crate) fn warn_unmappable(self, path: &Path, unmappable: usize) {
+        if unmappable > 0 {
+            warn!(
+                reason = "unrepresentable_characters", path:% = path.display();
+                "{} characters of {:?} can not be represented in {}, writing them as `?`",
+                unmappable, path, clap::ValueEnum::to_possible_value(&self).unwrap().get_name()
+            );
+        }
+    }
+}
+
+/// Options controlling how `write_sub` writes merged subtitles
+#[derive(Clone, Debug, Default)]
+pub struct OutputOptions {
+    /// How the file is written
+    pub write_mode: WriteMode,
+    /// What format the file is written in
+    pub format: OutputFormat,
+    /// The line endings of the file
+    pub line_endings: LineEndings,
+    /// The character encoding of the file
+    pub encoding: OutputEncoding,
+}
+
+impl OutputOptions {
+    /// Fail if the format can not be written in the encoding, as WebVTT files must be UTF-8
+    pub fn check(&self) -> Result<()> {
+        if self.format == OutputFormat::Vtt && self.encoding != OutputEncoding::Utf8 {
+            let name = clap::ValueEnum::to_possible_value(&self.encoding)
+                .map_or(String::new(), |v| v.get_name().to_owned());
+            bail!(
+                "WebVTT files must be UTF-8, unable to write them as {}",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Returned by `write_sub` when `WriteMode::NewOnly` is used and the output already exists
+#[derive(Debug)]
+pub struct OutputExists {
+    /// The existing output file
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for OutputExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "refusing to overwrite existing file {:?}", self.path)
+    }
+}
+
+impl std::error::Error for OutputExists {}
+
+/// Render `srt` in the format of `opts` and write it to `path`
+pub fn write_sub(path: &Path, srt: &SubRip, opts: &OutputOptions) -> Result<()> {
+    opts.check()?;
+    let content = match opts.format {
+        OutputFormat::Srt => without_boxes(srt).render(),
+        OutputFormat::Vtt => crate::vtt::render(&without_boxes(srt)),
+        OutputFormat::Smi => sami::render(&without_boxes(srt)),
+        OutputFormat::Ass => crate::ass::render(srt),
+    };
+    let content = opts.line_endings.normalize(&content);
+    let content = opts.encoding.encode_file(path, &content);
+
+    write_with(path, opts.write_mode, |file| Ok(file.write_all(&content)?))
+}
+
+/// Create `path` as `mode` describes and call `write` to write its contents. With
+/// `WriteMode::Atomic`, the temporary file is removed again if `write` fails.
+pub(crate) fn write_with(
+    path: &Path,
+    mode: WriteMode,
+    write: impl FnOnce(&mut File) -> Result<()>,
+) -> Result<()> {
+    match mode {
+        WriteMode::Truncate => write(&mut File::create(path)?)?,
+        WriteMode::NewOnly => {
+            let mut file = match File::create_new(path) {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return Err(OutputExists {
+                        path: path.to_owned(),
+                    }
+                    .into())
+                }
+                file => file?,
+            };
+            write(&mut file)?
+        }
+        WriteMode::Atomic => {
+            let name = path
+                .file_name()
+                .context(format!("invalid output path {:?}", path))?;
+            let tmp = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+
+            let mut file = File::create(&tmp)?;
+            if let Err(e) = write(&mut file) {
+                let _ = fs::remove_file(&tmp);
+                return Err(e);
+            }
+            file.sync_all()?;
+            rename_into_place(&tmp, path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rename `tmp` to `path`, copying it instead if they are on different filesystems, e.g. when
+/// the output is a bind mount of its own
+fn rename_into_place(tmp: &Path, path: &Path) -> Result<()> {
+    match fs::rename(tmp, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            warn!(
+                reason = "cross_device_rename", path:% = path.display();
+                "Unable to rename {:?} into place across filesystems, copying it instead, which is not atomic", tmp
+            );
+            fs::copy(tmp, path).context(format!("unable to copy {:?} to {:?}", tmp, path))?;
+            fs::remove_file(tmp)?;
+            Ok(())
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Write `ass` to `path`, as merged by `merge_ssa`. Only the `write_mode` and `line_endings`
+/// options apply, the file is always ASS/SSA.
+pub fn write_ass(path: &Path, ass: &Ass, opts: &OutputOptions) -> Result<()> {
+    let content = opts.line_endings.normalize(&ass.render());
+    let content = opts.encoding.encode_file(path, &content);
+    write_with(path, opts.write_mode, |file| Ok(file.write_all(&content)?))
+}
+
+/// The time between the earliest start and the latest end of any cue in `srt`
+pub fn span(srt: &SubRip) -> Duration {
+    let start = srt.subtitles.iter().map(|s| s.start).min();
+    let end = srt.subtitles.iter().map(|s| s.end).max();
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            let (start, end): (Duration, Duration) = (start.into(), end.into());
+            end.saturating_sub(start)
+        }
+        _ => Duration::ZERO,
+    }
+}
+
+/// How many times longer the span of the longest track is compared to the shortest.
+/// Returns infinity if exactly one of the tracks has an empty span.
+pub fn span_ratio(srt1: &SubRip, srt2: &SubRip) -> f64 {
+    let (a, b) = (span(srt1).as_secs_f64(), span(srt2).as_secs_f64());
+    let (short, long) = if a < b { (a, b) } else { (b, a) };
+    if long == 0.0 {
+        1.0
+    } else {
+        long / short
+    }
+}
+
+/// Convert a VTT cue to SRT, placing it with a position tag translated from its cue settings if
+/// `positions` is set, see `cue_position`
+fn vtt_block_to_srt(vtt_block: VttBlock, sequence: u32, positions: bool) -> Option<SrtSubtitle> {
+    let mut cue = match vtt_block {
+        VttBlock::Que(y) => y,
+        _ => return None,
+    };
+
+    let start = SrtTimestamp {
+        hours: cue.timings.start.hours,
+        minutes: cue.timings.start.minutes,
+        seconds: cue.timings.start.seconds,
+        milliseconds: cue.timings.start.milliseconds,
+    };
+    let end = SrtTimestamp {
+        hours: cue.timings.end.hours,
+        minutes: cue.timings.end.minutes,
+        seconds: cue.timings.end.seconds,
+        milliseconds: cue.timings.end.milliseconds,
+    };
+
+    if positions && let Some(position) = cue.settings.as_ref().and_then(cue_position) {
+        for txt in &mut cue.payload {
+            *txt = format!("{position} {txt}");
+        }
+    }
 
     Some(SrtSubtitle {
         sequence,
@@ -195,12 +3031,12 @@ fn vtt_block_to_srt(vtt_block: VttBlock, sequence: u32) -> Option<SrtSubtitle> {
     })
 }
 
-fn vtt_to_subrip(vtt: WebVtt) -> SubRip {
+fn vtt_to_subrip(vtt: WebVtt, positions: bool) -> SubRip {
     let mut i = 1;
     let mut subtitles = Vec::new();
 
     for vtt_block in vtt {
-        if let Some(sub) = vtt_block_to_srt(vtt_block, i) {
+        if let Some(sub) = vtt_block_to_srt(vtt_block, i, positions) {
             subtitles.push(sub)
         }
         i += 1;
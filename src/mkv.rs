@@ -0,0 +1,88 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::{path::Path, process::Command};
+
+/// Subtitle codecs drawn as images, which have no text to merge
+const IMAGE_CODECS: &[&str] = &["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
+/// Get the codec of the subtitle stream `index` of `path` (e.g. `subrip` or `ass`) with `ffprobe`
+fn subtitle_codec(path: &Path, index: usize) -> Result<String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", &format!("s:{index}")])
+        .args(["-show_entries", "stream=codec_name", "-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .context("unable to run ffprobe, make sure ffmpeg is installed and on the PATH")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed on {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    let codec = String::from_utf8(output.stdout)?.trim().to_owned();
+    if codec.is_empty() {
+        bail!("{:?} has no subtitle stream {}", path, index);
+    }
+    Ok(codec)
+}
+
+/// The `ffmpeg` encoder and muxer to extract a subtitle stream of `codec` with, and the file
+/// extension the result is parsed as. ASS and WebVTT are copied as they are, every other text
+/// codec is converted to SRT.
+pub fn extraction_format(codec: &str) -> Result<(&'static str, &'static str, &'static str)> {
+    let format = match codec {
+        "ass" | "ssa" => ("copy", "ass", "ass"),
+        "webvtt" => ("copy", "webvtt", "vtt"),
+        codec if IMAGE_CODECS.contains(&codec) => {
+            bail!(
+                "{} subtitles are images, only text subtitles can be merged",
+                codec
+            )
+        }
+        _ => ("srt", "srt", "srt"),
+    };
+    Ok(format)
+}
+
+/// Extract the subtitle stream `index` of the MKV file `path`, counting from 0 like `ffmpeg`'s
+/// `-map 0:s:N`. Returns its text and the extension of its format, e.g. `srt` or `ass`.
+pub fn extract_subtitles(path: &Path, index: usize) -> Result<(String, &'static str)> {
+    let codec = subtitle_codec(path, index)?;
+    let (encoder, muxer, ext) = extraction_format(&codec)?;
+
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args([
+            "-map",
+            &format!("0:s:{index}"),
+            "-c:s",
+            encoder,
+            "-f",
+            muxer,
+            "-",
+        ])
+        .output()
+        .context("unable to run ffmpeg, make sure it is installed and on the PATH")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed on {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+
+    info!(
+        "Extracted subtitle stream {} ({}) from {:?}",
+        index, codec, path
+    );
+    let text = String::from_utf8(output.stdout).context(format!(
+        "subtitle stream {} of {:?} is not UTF-8",
+        index, path
+    ))?;
+    Ok((text, ext))
+}
@@ -0,0 +1,93 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::{io::Read, path::Path};
+
+use crate::{InputTooLarge, UnsupportedFormat};
+
+/// The subtitle formats of the `Content-Type`s servers send them with, for URLs without an
+/// extension
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("application/x-subrip", "srt"),
+    ("text/srt", "srt"),
+    ("text/vtt", "vtt"),
+    ("text/x-ass", "ass"),
+    ("text/x-ssa", "ssa"),
+];
+
+/// The format of the subtitles at `url`, from the extension of its path (ignoring the query),
+/// or from the `content_type` of the response if it has none of the formats `load_sub` reads
+pub fn url_format(url: &str, content_type: Option<&str>) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let ext = Path::new(path).extension().and_then(|ext| ext.to_str());
+    if let Some(ext) = ext
+        && let Some(format) = ["srt", "vtt", "ass", "ssa"]
+            .into_iter()
+            .find(|f| f.eq_ignore_ascii_case(ext))
+    {
+        return Some(format);
+    }
+
+    // Ignore parameters such as `; charset=utf-8`
+    let mime = content_type?.split(';').next()?.trim();
+    CONTENT_TYPES
+        .iter()
+        .find(|(t, _)| t.eq_ignore_ascii_case(mime))
+        .map(|(_, format)| *format)
+}
+
+/// Download the subtitles at `url`, returning their contents and format, see `url_format`.
+/// Responses larger than `max_bytes` are not read any further.
+pub fn fetch_subtitles(url: &str, max_bytes: u64) -> Result<(Vec<u8>, &'static str)> {
+    let response = reqwest::blocking::get(url).context(format!("unable to download {}", url))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!(
+            "unable to download {}: the server responded with {}",
+            url,
+            status
+        );
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok());
+    let Some(format) = url_format(url, content_type) else {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let ext = Path::new(path).extension().unwrap_or_default();
+        return Err(UnsupportedFormat {
+            path: url.into(),
+            ext: ext.to_string_lossy().into_owned(),
+        }
+        .into());
+    };
+
+    let too_large = |size| InputTooLarge {
+        path: url.into(),
+        size,
+        max_bytes,
+    };
+    if let Some(size) = response.content_length()
+        && size > max_bytes
+    {
+        return Err(too_large(size).into());
+    }
+
+    // The length may be missing or wrong, so stop reading at the limit either way
+    let mut bytes = Vec::new();
+    response
+        .take(max_bytes + 1)
+        .read_to_end(&mut bytes)
+        .context(format!("unable to download {}", url))?;
+    if bytes.len() as u64 > max_bytes {
+        return Err(too_large(bytes.len() as u64).into());
+    }
+
+    info!(
+        "Downloaded {} bytes of {} subtitles from {}",
+        bytes.len(),
+        format,
+        url
+    );
+    Ok((bytes, format))
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use font8x8::UnicodeFonts;
+use std::{fs::File, io::BufWriter, path::Path, time::Duration};
+use subtp::srt::{SrtTimestamp, SubRip};
+
+use crate::SubPosition;
+
+/// Width of the rendered frame, in pixels
+pub const FRAME_WIDTH: usize = 1280;
+/// Height of the rendered frame, in pixels
+pub const FRAME_HEIGHT: usize = 720;
+
+const BACKGROUND: Rgb = [64, 64, 64];
+const WHITE: Rgb = [255, 255, 255];
+const SHADOW: Rgb = [0, 0, 0];
+/// Each pixel of the 8x8 font is drawn as a square of this many pixels
+const SCALE: usize = 3;
+const GLYPH: usize = 8 * SCALE;
+const LINE_HEIGHT: usize = GLYPH + 6;
+const MARGIN: usize = 32;
+
+type Rgb = [u8; 3];
+
+/// An RGB image of `FRAME_WIDTH` by `FRAME_HEIGHT` pixels
+pub struct Frame {
+    pixels: Vec<u8>,
+}
+
+impl Frame {
+    fn new() -> Self {
+        Frame {
+            pixels: BACKGROUND.repeat(FRAME_WIDTH * FRAME_HEIGHT),
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, size: usize, color: Rgb) {
+        for y in y..(y + size).min(FRAME_HEIGHT) {
+            for x in x..(x + size).min(FRAME_WIDTH) {
+                let i = (y * FRAME_WIDTH + x) * 3;
+                self.pixels[i..i + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Draw `c` with its top left corner at `x`, `y`. Characters the font lacks are drawn as
+    /// a hollow box.
+    fn draw_char(&mut self, x: usize, y: usize, c: char, color: Rgb) {
+        let glyph = font8x8::BASIC_FONTS
+            .get(c)
+            .or_else(|| font8x8::LATIN_FONTS.get(c))
+            .or_else(|| font8x8::GREEK_FONTS.get(c))
+            .or_else(|| font8x8::HIRAGANA_FONTS.get(c))
+            .unwrap_or([0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF]);
+
+        for (row, bits) in glyph.into_iter().enumerate() {
+            for col in 0..8 {
+                if bits & (1 << col) != 0 {
+                    let (px, py) = (x + col * SCALE, y + row * SCALE);
+                    self.fill(px + 2, py + 2, SCALE, SHADOW);
+                    self.fill(px, py, SCALE, color);
+                }
+            }
+        }
+    }
+
+    /// Write the frame as a PNG to `path`
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).context(format!("unable to create {:?}", path))?;
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            FRAME_WIDTH as u32,
+            FRAME_HEIGHT as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()?
+            .write_image_data(&self.pixels)
+            .context(format!("unable to write {:?}", path))
+    }
+}
+
+/// A line of a cue with the color of each character, with all tags removed
+pub type StyledLine = Vec<(char, Rgb)>;
+
+/// Remove the tags of an SRT line, keeping the color of each character from `<font color>` tags
+pub fn style_line(txt: &str) -> StyledLine {
+    let mut colors = vec![WHITE];
+    let mut line = Vec::new();
+    let mut rest = txt;
+
+    while let Some(c) = rest.chars().next() {
+        let close = match c {
+            '<' => rest.find('>'),
+            '{' => rest.find('}'),
+            _ => None,
+        };
+        if let Some(end) = close {
+            let tag = &rest[..=end];
+            if tag.starts_with("<font") {
+                colors.push(tag_color(tag).unwrap_or(*colors.last().unwrap()));
+            } else if tag == "</font>" && colors.len() > 1 {
+                colors.pop();
+            }
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        line.push((c, *colors.last().unwrap()));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    line
+}
+
+/// The color of a `<font color="#rrggbb">` tag
+fn tag_color(tag: &str) -> Option<Rgb> {
+    let hex = tag.split('#').nth(1)?.get(..6)?;
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Draw the cues of `srt` shown at `at`, each at its position and in its colors. Cues without
+/// a position tag are drawn at the bottom center, like players do.
+pub fn render_frame(srt: &SubRip, at: Duration) -> Frame {
+    let mut frame = Frame::new();
+    let at: SrtTimestamp = at.into();
+
+    for &position in SubPosition::value_variants() {
+        let tag = position.to_string();
+        let lines: Vec<StyledLine> = srt
+            .subtitles
+            .iter()
+            .filter(|s| s.start <= at && at < s.end)
+            .filter(|s| match s.text.first() {
+                Some(first) if first.starts_with("{\\an") => first.starts_with(&tag),
+                _ => position == SubPosition::BottomCenter,
+            })
+            .flat_map(|s| &s.text)
+            .map(|txt| style_line(txt.trim_start_matches(&tag).trim_start()))
+            .collect();
+
+        // The variants are in numpad order, bottom left to top right
+        let (row, col) = (position as usize / 3, position as usize % 3);
+        let height = lines.len() * LINE_HEIGHT;
+        let top = match row {
+            0 => FRAME_HEIGHT.saturating_sub(MARGIN + height),
+            1 => FRAME_HEIGHT.saturating_sub(height) / 2,
+            _ => MARGIN,
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let width = line.len() * GLYPH;
+            let left = match col {
+                0 => MARGIN,
+                1 => FRAME_WIDTH.saturating_sub(width) / 2,
+                _ => FRAME_WIDTH.saturating_sub(MARGIN + width),
+            };
+            for (j, &(c, color)) in line.iter().enumerate() {
+                frame.draw_char(left + j * GLYPH, top + i * LINE_HEIGHT, c, color);
+            }
+        }
+    }
+
+    frame
+}
@@ -0,0 +1,98 @@
+use log::warn;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    time::Duration,
+};
+use subtp::srt::{SrtSubtitle, SubRip};
+
+use crate::{split_position, SubPosition};
+
+const HEADER: &str = "<SAMI>
+<HEAD>
+<STYLE TYPE=\"text/css\">
+<!--
+P { margin-left: 8pt; margin-right: 8pt; margin-bottom: 2pt; margin-top: 2pt;
+    text-align: center; font-size: 20pt; font-family: Arial, sans-serif;
+    font-weight: normal; color: white; }
+.SUBCC { Name: Subtitles; lang: und; SAMIType: CC; }
+-->
+</STYLE>
+</HEAD>
+<BODY>
+";
+
+const FOOTER: &str = "</BODY>
+</SAMI>
+";
+
+/// Render `srt` as SAMI. A SAMI file is a list of points in time, each replacing what is on
+/// screen, so every change to the set of cues shown gets a `<SYNC>` showing all of them.
+/// SAMI can not position text, so cues at the top are stacked above the others instead.
+pub fn render(srt: &SubRip) -> String {
+    // The cues ending and starting at each point in time, in milliseconds
+    let mut changes: BTreeMap<u128, (Vec<usize>, Vec<usize>)> = BTreeMap::new();
+    for (i, cue) in srt.subtitles.iter().enumerate() {
+        let start = Into::<Duration>::into(cue.start).as_millis();
+        let end = Into::<Duration>::into(cue.end).as_millis();
+        if start < end {
+            changes.entry(start).or_default().1.push(i);
+            changes.entry(end).or_default().0.push(i);
+        }
+    }
+
+    // The cues on screen, ordered from the top of the screen to the bottom
+    let mut active = BTreeSet::new();
+    let mut positioned = false;
+    let mut shown: Option<String> = None;
+    let mut out = HEADER.to_owned();
+
+    for (ms, (ends, starts)) in changes {
+        for i in ends {
+            active.remove(&(row(&srt.subtitles[i]), i));
+        }
+        for i in starts {
+            let row = row(&srt.subtitles[i]);
+            positioned |= row > 0;
+            active.insert((row, i));
+        }
+
+        let text = if active.is_empty() {
+            "&nbsp;".to_owned()
+        } else {
+            let cues: Vec<String> = active
+                .iter()
+                .map(|&(_, i)| cue_html(&srt.subtitles[i]))
+                .collect();
+            cues.join("<br>")
+        };
+        if shown.as_ref() != Some(&text) {
+            writeln!(out, "<SYNC Start={}><P Class=SUBCC>{}", ms, text).unwrap();
+            shown = Some(text);
+        }
+    }
+
+    if positioned {
+        warn!(
+            reason = "unrepresentable_style";
+            "SAMI output can not position cues, cues at the top are stacked above the others instead"
+        );
+    }
+
+    out.push_str(FOOTER);
+    out
+}
+
+/// Vertical position of a cue, `0` being the top of the screen and `2` the bottom (the default)
+fn row(cue: &SrtSubtitle) -> u8 {
+    let position = cue.text.first().and_then(|txt| split_position(txt).0);
+    // The variants are in numpad order, bottom left to top right
+    2 - position.unwrap_or(SubPosition::BottomCenter) as u8 / 3
+}
+
+/// The lines of a cue without position tags, joined by line breaks. Styling such as
+/// `<font color>` and `<i>` is valid in SAMI, so it is kept.
+fn cue_html(cue: &SrtSubtitle) -> String {
+    let lines: Vec<&str> = cue.text.iter().map(|txt| split_position(txt).1).collect();
+    lines.join("<br>")
+}
@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use log::{info, warn};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use subtp::srt::{SrtSubtitle, SubRip};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    apply_sub_changes, check_overlaps, normalize_srt_timings, retime, strip_box, write_with,
+    InputTooLarge, LoadOptions, NegativeCues, OutputFormat, OutputOptions, TrackOptions,
+};
+
+/// The cues of an SRT file, read one at a time as they are iterated over, see `stream_sub`
+pub struct SrtStream {
+    path: PathBuf,
+    reader: Box<dyn BufRead + Send>,
+    opts: LoadOptions,
+    /// Bytes read so far, after decompressing
+    read: u64,
+    /// The cue after the last one returned, read ahead to check the cues are in order
+    next: Option<SrtSubtitle>,
+    count: usize,
+    warned_encoding: bool,
+}
+
+/// Open an SRT file to read its cues one at a time, rather than all at once like `load_sub`.
+/// Gzipped files are decompressed as they are read. The cues must be in chronological order,
+/// which is checked as they are read, and the file must be UTF-8. Only the `renumber`,
+/// `normalize_unicode`, `fix_overlaps` and `max_bytes` options apply.
+pub fn stream_sub(path: &Path, opts: &LoadOptions) -> Result<SrtStream> {
+    let size = fs::metadata(path)?.len();
+    if size > opts.max_bytes {
+        return Err(InputTooLarge {
+            path: path.to_owned(),
+            size,
+            max_bytes: opts.max_bytes,
+        }
+        .into());
+    }
+
+    let file = File::open(path)?;
+    let (reader, inner): (Box<dyn BufRead + Send>, _) =
+        if path.extension().is_some_and(|ext| ext == "gz") {
+            let reader = BufReader::new(GzDecoder::new(file));
+            (Box::new(reader), path.with_extension(""))
+        } else {
+            (Box::new(BufReader::new(file)), path.to_owned())
+        };
+    if inner.extension().is_none_or(|ext| ext != "srt") {
+        bail!(
+            "only SRT files can be streamed, unable to stream {:?}",
+            path
+        );
+    }
+
+    let mut stream = SrtStream {
+        path: path.to_owned(),
+        reader,
+        opts: opts.clone(),
+        read: 0,
+        next: None,
+        count: 0,
+        warned_encoding: false,
+    };
+    // The first cue is read ahead, so an empty file is known before any cue is merged
+    stream.next = stream.read_cue()?;
+    Ok(stream)
+}
+
+impl SrtStream {
+    /// Whether the file has no cues at all
+    pub fn is_empty(&self) -> bool {
+        self.count == 0 && self.next.is_none()
+    }
+
+    /// The file the cues are read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the lines up to the next blank line and parse them as a cue
+    fn read_cue(&mut self) -> Result<Option<SrtSubtitle>> {
+        let mut block = String::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = self
+                .reader
+                .read_until(b'\n', &mut line)
+                .with_context(|| format!("unable to read {:?}", self.path))?;
+            self.read += read as u64;
+            if self.read > self.opts.max_bytes {
+                // Like `load_sub`, report the full size of a decompressed file
+                let rest = io::copy(&mut self.reader, &mut io::sink()).unwrap_or(0);
+                return Err(InputTooLarge {
+                    path: self.path.clone(),
+                    size: self.read + rest,
+                    max_bytes: self.opts.max_bytes,
+                }
+                .into());
+            }
+            if read == 0 {
+                break;
+            }
+
+            let line = String::from_utf8(std::mem::take(&mut line)).unwrap_or_else(|e| {
+                if !self.warned_encoding {
+                    warn!(
+                        reason = "invalid_encoding", path:% = self.path.display();
+                        "{:?} is not valid UTF-8, which streaming requires, replacing the invalid characters", self.path
+                    );
+                    self.warned_encoding = true;
+                }
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            });
+            if line.trim().is_empty() {
+                if block.is_empty() {
+                    continue;
+                }
+                break;
+            }
+            // Files exported by Windows tools often start with a byte order mark
+            block.push_str(line.strip_prefix('\u{feff}').unwrap_or(&line));
+        }
+        if block.is_empty() {
+            return Ok(None);
+        }
+
+        let mut cue = SubRip::parse(&normalize_srt_timings(&block))
+            .ok()
+            .and_then(|srt| srt.subtitles.into_iter().next())
+            .with_context(|| format!("invalid cue in {:?}:\n{}", self.path, block.trim_end()))?;
+        if self.opts.normalize_unicode {
+            for txt in &mut cue.text {
+                *txt = txt.nfc().collect();
+            }
+        }
+        Ok(Some(cue))
+    }
+}
+
+impl Iterator for SrtStream {
+    type Item = Result<SrtSubtitle>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cue = match self.next.take() {
+            Some(cue) => cue,
+            None if self.count == 0 => match self.read_cue() {
+                Ok(cue) => cue?,
+                Err(e) => return Some(Err(e)),
+            },
+            None => return None,
+        };
+        self.next = match self.read_cue() {
+            Ok(next) => next,
+            Err(e) => return Some(Err(e)),
+        };
+        self.count += 1;
+
+        let mut cue = match self.next.take() {
+            Some(next) if next.start < cue.start => {
+                return Some(Err(anyhow::anyhow!(
+                    "cue {} of {:?} starts before the cue before it, streaming needs the cues to be in order, merge without --streaming",
+                    next.sequence,
+                    self.path
+                )))
+            }
+            Some(next) => {
+                let mut pair = SubRip {
+                    subtitles: vec![cue, next],
+                };
+                check_overlaps(&mut pair, &self.path, self.opts.fix_overlaps);
+                self.next = pair.subtitles.pop();
+                pair.subtitles.pop().unwrap()
+            }
+            None => {
+                info!("Streamed {} subtitles from {:?}", self.count, self.path);
+                cue
+            }
+        };
+        if self.opts.renumber {
+            cue.sequence = self.count as u32;
+        }
+        Some(Ok(cue))
+    }
+}
+
+/// Fail if `opts` changes cues based on the cues around them, which streaming does not keep in
+/// memory
+fn check_streamable(opts: &TrackOptions) -> Result<()> {
+    let whole_track = [
+        (opts.fill_gaps, "--fill-gaps"),
+        (opts.gap_merge.is_some(), "--gap-merge"),
+        (opts.min_duration.is_some(), "--min-duration"),
+        (opts.color_speakers, "--color-speakers"),
+        (opts.anchor.is_some(), "--sub2-anchor"),
+    ];
+    if let Some((_, flag)) = whole_track.iter().find(|(set, _)| *set) {
+        bail!("{} needs the whole track, merge without --streaming", flag);
+    }
+    Ok(())
+}
+
+/// Apply `opts` to a single cue, returning `None` if it is skipped or moved before 00:00:00,
+/// which is counted in `negative`
+fn style_cue(
+    cue: SrtSubtitle,
+    opts: &TrackOptions,
+    negative: &mut NegativeCues,
+) -> Option<SrtSubtitle> {
+    let mut srt = SubRip {
+        subtitles: vec![cue],
+    };
+    negative.add(retime(&mut srt, opts));
+    // Skipping and retiming are done here rather than by `apply_sub_changes`, which would warn
+    // about every cue
+    let untimed = TrackOptions {
+        fps_scale: None,
+        sync_map: None,
+        anchor: None,
+        offset: None,
+        skip_before: None,
+        skip_after: None,
+        ..opts.clone()
+    };
+    apply_sub_changes(&mut srt, &untimed);
+
+    let mut cue = srt.subtitles.pop()?;
+    // Streamed output is SRT, which can not show background boxes
+    for txt in &mut cue.text {
+        strip_box(txt);
+    }
+    let start: Duration = cue.start.into();
+    let kept =
+        opts.skip_before.is_none_or(|t| start >= t) && opts.skip_after.is_none_or(|t| start < t);
+    kept.then_some(cue)
+}
+
+/// Merge `tracks` in chronological order like `merge_tracks`, styling each cue with the options
+/// of its track as it is read and writing it to `path` as soon as it is the earliest left. Only
+/// the next cue of each track is kept in memory. Returns the number of cues written.
+pub fn merge_streams<I>(
+    mut tracks: Vec<(I, TrackOptions)>,
+    path: &Path,
+    opts: &OutputOptions,
+) -> Result<usize>
+where
+    I: Iterator<Item = Result<SrtSubtitle>>,
+{
+    if opts.format != OutputFormat::Srt {
+        bail!("only SRT output can be streamed, merge without --streaming");
+    }
+    for (_, track_opts) in &tracks {
+        check_streamable(track_opts)?;
+    }
+
+    let next_cue = |(cues, track_opts): &mut (I, TrackOptions),
+                    negative: &mut NegativeCues|
+     -> Result<Option<SrtSubtitle>> {
+        for cue in cues {
+            if let Some(cue) = style_cue(cue?, track_opts, negative) {
+                return Ok(Some(cue));
+            }
+        }
+        Ok(None)
+    };
+
+    let mut written = 0;
+    let mut unmappable = 0;
+    let mut negative = NegativeCues::default();
+    write_with(path, opts.write_mode, |file| {
+        let mut out = BufWriter::new(file);
+        out.write_all(opts.encoding.bom())?;
+        let mut heads = tracks
+            .iter_mut()
+            .map(|track| next_cue(track, &mut negative))
+            .collect::<Result<Vec<_>>>()?;
+
+        // The earliest cue of any track, that of the earlier track when several start at once
+        while let Some(i) = heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((head.as_ref()?.start, i)))
+            .min()
+            .map(|(_, i)| i)
+        {
+            let mut cue = heads[i].take().unwrap();
+            written += 1;
+            cue.sequence = written as u32;
+            // Cues are separated by a blank line, like `SubRip::render`
+            let sep = if written > 1 { "\n" } else { "" };
+            let cue = opts.line_endings.normalize(&format!("{sep}{cue}"));
+            let (bytes, n) = opts.encoding.encode(&cue);
+            unmappable += n;
+            out.write_all(&bytes)?;
+
+            heads[i] = next_cue(&mut tracks[i], &mut negative)?;
+        }
+        out.flush()?;
+        Ok(())
+    })?;
+    opts.encoding.warn_unmappable(path, unmappable);
+    negative.warn();
+
+    Ok(written)
+}
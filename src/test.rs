@@ -1,12 +1,41 @@
 #[cfg(test)]
 mod tests {
-    use crate::get_sub_path_regex;
+    use crate::{
+        apply_ass_changes, apply_sub_changes, base_file_stem, builtin_preset, check_not_empty,
+        dump_json, find_matching_subtitle_files, find_subtitle_files, first_preferred_sub,
+        flattened_output, get_sub_path_regex, init_logging, load_ass, load_config, load_sub,
+        load_sub_file, merge, merge_ssa, merge_streams, merge_tracks_with_sources,
+        merge_tracks_with_stats, misnumbered_cues, output_extension, overwrites_input, pair_subs,
+        parse_color, parse_offset, parse_time, position_counts, preferred_sub, preview_cues,
+        renumber, span_ratio, stream_sub, write_crossref, write_sub, write_warnings, Anchor,
+        ConflictPolicy, DumpedTrack, ExtraTrack, InputTooLarge, LineEndings, LineRange,
+        LoadOptions, MergeOptions, OutputEncoding, OutputExists, OutputOptions, Point,
+        SearchOptions, SortBy, SubFile, SubPosition, SyncMap, TrackOptions, UnsupportedFormat,
+        Variant, Warning, WriteMode, PREFER_HI, SPEAKER_PALETTE,
+    };
     use regex::Regex;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+    use subtp::srt::{SrtSubtitle, SubRip};
+
+    /// Build a cue from millisecond timestamps
+    fn cue(sequence: u32, start_ms: u64, end_ms: u64, text: &str) -> SrtSubtitle {
+        SrtSubtitle {
+            sequence,
+            start: Duration::from_millis(start_ms).into(),
+            end: Duration::from_millis(end_ms).into(),
+            text: vec![text.to_owned()],
+            line_position: None,
+        }
+    }
 
     #[test]
     fn test_get_sub_regex() {
         // Test case 1: Basic test for 'en' and 'ja' with both srt and vtt files.
-        let regex_str = get_sub_path_regex(&"en".to_string(), &"ja".to_string(), true);
+        let regex_str = get_sub_path_regex(&["en", "ja"], true, false, &[]);
         let subtitle_pattern = Regex::new(&regex_str).unwrap();
 
         let test_cases = vec![
@@ -17,12 +46,17 @@ mod tests {
             ("movie.ja.vtt", Some("ja"), false, "vtt"),
             ("song.ja.hi.vtt", Some("ja"), true, "vtt"),
             ("song.en.hi.srt", Some("en"), true, "srt"),
+            ("movie.en.part1.srt", Some("en"), false, "srt"),
+            ("movie.ja.hi.part2.vtt", Some("ja"), true, "vtt"),
+            ("show.en.sdh.srt", Some("en"), true, "srt"),
             // Non-matching cases (invalid formats)
             ("movie.de.srt", None, false, ""),
             ("movie.srt", None, false, ""),
             ("movie.ja.txt", None, false, ""),
             ("movie.enhi.vtt", None, false, ""), // Missing dot for 'hi'
             ("movie.en.hisrt", None, false, ""), // Missing dot between hi and srt
+            ("movie.en.sd.srt", None, false, ""),
+            ("movie.en.srt.gz", None, false, ""),
             ("movie..en.srt", None, false, ""),
         ];
 
@@ -48,7 +82,7 @@ mod tests {
     #[test]
     fn test_get_regex_no_vtt() {
         // Test case 2: Test where only srt files should match, not vtt.
-        let regex_str = get_sub_path_regex(&"en".to_string(), &"ja".to_string(), false);
+        let regex_str = get_sub_path_regex(&["en", "ja"], false, false, &[]);
         let subtitle_pattern = Regex::new(&regex_str).unwrap();
 
         let test_cases = vec![
@@ -80,4 +114,2688 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_gzip_input() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let regex = Regex::new(&get_sub_path_regex(&["en", "ja"], false, true, &[])).unwrap();
+        let captures = regex.captures("movie.en.hi.srt.gz").unwrap();
+        assert_eq!(&captures["ext"], "srt");
+        assert!(captures.name("gz").is_some());
+        assert!(regex.captures("movie.en.srt").unwrap().name("gz").is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.srt.gz");
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\nCompressed\n\n".repeat(100);
+        let mut gz = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        gz.write_all(srt.as_bytes()).unwrap();
+        gz.finish().unwrap();
+
+        let loaded = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(loaded.subtitles.len(), 100);
+        assert_eq!(loaded.subtitles[0].text, ["Compressed"]);
+
+        // The limit applies to the decompressed contents too
+        let opts = LoadOptions {
+            max_bytes: 1000,
+            ..Default::default()
+        };
+        let err = load_sub(&path, &opts).unwrap_err();
+        assert_eq!(
+            err.downcast::<InputTooLarge>().unwrap().size,
+            srt.len() as u64
+        );
+    }
+
+    #[test]
+    fn test_many_languages() {
+        let regex =
+            Regex::new(&get_sub_path_regex(&["en", "de", "ja"], false, false, &[])).unwrap();
+        let captures = regex.captures("movie.de.hi.srt").unwrap();
+        assert!(captures.name("lang2").is_some());
+        assert_eq!(&captures["hearing"], "hi");
+        assert!(regex
+            .captures("movie.ja.srt")
+            .unwrap()
+            .name("lang3")
+            .is_some());
+        assert!(!regex.is_match("movie.fr.srt"));
+
+        let root = tempfile::tempdir().unwrap();
+        for file in [
+            "ep1.de.srt",
+            "ep1.fr.srt",
+            "ep1.ja.srt",
+            "ep2.en.srt",
+            "ep2.de.srt",
+        ] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let root_path = root.path().to_path_buf();
+        let found = find_subtitle_files(&root_path, &["en", "de", "ja"], &SearchOptions::default())
+            .unwrap();
+        let subs = &found[&root_path];
+        assert_eq!(subs.len(), 4);
+
+        // The first language in priority order with a file wins
+        let priority = ["en".to_owned(), "de".to_owned()];
+        let pick = |stem| {
+            first_preferred_sub(subs, Path::new(stem), &priority, &[Variant::Normal])
+                .map(|s| s.code.as_str())
+        };
+        assert_eq!(pick("ep1"), Some("de"));
+        assert_eq!(pick("ep2"), Some("en"));
+        assert_eq!(pick("ep3"), None);
+    }
+
+    #[test]
+    fn test_require_video() {
+        let root = tempfile::tempdir().unwrap();
+        for file in [
+            "movie.mkv",
+            "movie.en.srt",
+            "movie.ja.srt",
+            "Clip.MP4",
+            "clip.en.hi.srt",
+            "orphan.en.srt",
+            "orphan.ja.srt",
+        ] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        fs::create_dir(root.path().join("extras")).unwrap();
+        fs::write(root.path().join("extras/trailer.en.srt"), "").unwrap();
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions {
+            require_video: true,
+            ..Default::default()
+        };
+        let found = find_subtitle_files(&root_path, &["en", "ja"], &opts).unwrap();
+        let mut names: Vec<_> = found[&root_path]
+            .iter()
+            .map(|s| s.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort_unstable();
+        // Video extensions are matched regardless of case, but the stems must be the same
+        assert_eq!(names, ["movie.en.srt", "movie.ja.srt"]);
+        assert!(!found.contains_key(&root_path.join("extras")));
+
+        let found = find_subtitle_files(&root_path, &["en", "ja"], &SearchOptions::default());
+        assert_eq!(found.unwrap()[&root_path].len(), 5);
+    }
+
+    #[test]
+    fn test_exclude_dir() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in [
+            "extras",
+            "Show/Season 1",
+            "Show/Season 2",
+            "Show/Season 2/extras",
+        ] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("movie.en.srt"), "").unwrap();
+        }
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions {
+            exclude_dirs: vec!["extras".to_owned(), "Show/* 1".to_owned()],
+            ..Default::default()
+        };
+        let found = find_subtitle_files(&root_path, &["en"], &opts).unwrap();
+        let mut dirs: Vec<_> = found
+            .keys()
+            .map(|d| d.strip_prefix(&root_path).unwrap())
+            .collect();
+        dirs.sort_unstable();
+        // Directories are excluded by name at any depth, or by their path from the root
+        assert_eq!(dirs, [Path::new("Show/Season 2")]);
+    }
+
+    #[test]
+    fn test_single_ok() {
+        let root = tempfile::tempdir().unwrap();
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\nhello\n";
+        for file in ["movie.en.srt", "movie.ja.srt", "show.ja.srt"] {
+            fs::write(root.path().join(file), srt).unwrap();
+        }
+
+        let root_path = root.path().to_path_buf();
+        let found = find_subtitle_files(&root_path, &["en", "ja"], &SearchOptions::default());
+        let subs = &found.unwrap()[&root_path];
+        let (en, ja) = (["en".to_owned()], ["ja".to_owned()]);
+        let prefer = [Variant::Normal];
+        let pair = |single_ok| pair_subs(subs, &en, &ja, &prefer, &prefer, single_ok);
+        let name = |sub: Option<&SubFile>| sub.map(|s| s.path.file_name().unwrap().to_owned());
+
+        let pairs = pair(false);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(name(pairs[0].1).unwrap(), "movie.ja.srt");
+
+        let mut pairs = pair(true);
+        pairs.sort_by_key(|(s1, s2)| name(s1.or(*s2)));
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].0, None);
+        let show = pairs[1].1.unwrap();
+        assert_eq!(name(Some(show)).unwrap(), "show.ja.srt");
+
+        // The single track is styled as it would be when merged
+        let mut srt = load_sub_file(show, &LoadOptions::default()).unwrap();
+        let opts = TrackOptions {
+            color: Some("#fabd2f".to_owned()),
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+        let (merged, _, _) = merge_tracks_with_stats(vec![srt], &MergeOptions::default());
+        assert_eq!(merged.subtitles.len(), 1);
+        assert_eq!(
+            merged.subtitles[0].text,
+            ["{\\an8} <font color=\"#fabd2f\">hello</font>"]
+        );
+    }
+
+    #[test]
+    fn test_flattened_output() {
+        let flat = Path::new("/subs");
+        let mut taken = std::collections::HashSet::new();
+        let mut out = |name: &str| {
+            flattened_output(flat, Path::new(name), "en-ja.srt", &mut taken)
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+
+        assert_eq!(out("movie.en-ja.srt"), "/subs/movie.en-ja.srt");
+        assert_eq!(out("s01e01.en-ja.srt"), "/subs/s01e01.en-ja.srt");
+        // Episodes of different shows named alike are never written over each other
+        assert_eq!(out("s01e01.en-ja.srt"), "/subs/s01e01 (2).en-ja.srt");
+        assert_eq!(out("s01e01.en-ja.srt"), "/subs/s01e01 (3).en-ja.srt");
+        assert_eq!(
+            out("s01e01 (2).en-ja.srt"),
+            "/subs/s01e01 (2) (2).en-ja.srt"
+        );
+    }
+
+    #[test]
+    fn test_detect_lang() {
+        let root = tempfile::tempdir().unwrap();
+        let srt = |text: &str| format!("1\n00:00:01,000 --> 00:00:02,000\n{}\n", text);
+        fs::write(
+            root.path().join("movie.srt"),
+            srt("<i>What are you doing with that?</i> I know the way."),
+        )
+        .unwrap();
+        fs::write(root.path().join("movie.ja.srt"), srt("何をしているの？")).unwrap();
+        fs::write(
+            root.path().join("notes.srt"),
+            srt("Ich weiß nicht, was du hier machst."),
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("clip.fr.srt"),
+            srt("Je ne sais pas ce que vous faites."),
+        )
+        .unwrap();
+        let root_path = root.path().to_path_buf();
+
+        let opts = SearchOptions {
+            detect_lang: true,
+            ..Default::default()
+        };
+        let found = find_subtitle_files(&root_path, &["en", "ja"], &opts).unwrap();
+        let subs = &found[&root_path];
+        assert_eq!(subs.len(), 2);
+        let en = subs.iter().find(|s| s.detected).unwrap();
+        assert_eq!((en.lang.as_str(), en.code.as_str()), ("en", "en"));
+        assert_eq!(en.stem, Path::new("movie"));
+        let pick = first_preferred_sub(
+            subs,
+            Path::new("movie"),
+            &["en".to_owned()],
+            &[Variant::Normal],
+        );
+        assert_eq!(pick, Some(en));
+
+        let found =
+            find_subtitle_files(&root_path, &["en", "ja"], &SearchOptions::default()).unwrap();
+        assert_eq!(found[&root_path].len(), 1);
+    }
+
+    #[test]
+    fn test_detect_lang_ties() {
+        use crate::lang::detect_language;
+
+        // One word each of Spanish and French, which Spanish is listed before
+        for _ in 0..20 {
+            assert_eq!(detect_language("muy oui"), Some(("es", 0.5)));
+            assert_eq!(detect_language("oui muy"), Some(("es", 0.5)));
+        }
+        // As many Cyrillic as Greek letters
+        assert_eq!(detect_language("да να"), Some(("ru", 0.5)));
+        // Shared by French and German, so it tells nothing
+        assert_eq!(detect_language("du"), None);
+    }
+
+    #[test]
+    fn test_detect_scandinavian() {
+        use crate::lang::detect_language;
+
+        let lang = |text| detect_language(text).map(|(lang, _)| lang);
+        assert_eq!(
+            lang("Jeg ved ikke, hvad du taler om. Hvad laver du her? Det blev for meget."),
+            Some("da")
+        );
+        assert_eq!(
+            lang("Jeg vet ikke hva du snakker om. Hva gjør du her? Det ble for mye."),
+            Some("no")
+        );
+        assert_eq!(
+            lang("Jag vet inte vad du pratar om. Vad gör du här? Det blev för mycket."),
+            Some("sv")
+        );
+        // Finnish shares only `ja` and `en` with German and Dutch, which tells too little
+        let (_, confidence) = detect_language(
+            "Minä en tiedä mistä sinä puhut ja mitä sinä teet täällä tänään kotona nyt.",
+        )
+        .unwrap();
+        assert!(confidence < 0.5);
+    }
+
+    #[test]
+    fn test_hi_token() {
+        let tokens = ["cc".to_owned(), "c.c".to_owned()];
+        let regex = Regex::new(&get_sub_path_regex(&["en", "ja"], false, false, &tokens)).unwrap();
+        let hearing = |name| Some(regex.captures(name)?.name("hearing")?.as_str().to_owned());
+        assert_eq!(hearing("song.en.cc.srt").as_deref(), Some("cc"));
+        assert_eq!(hearing("song.en.sdh.srt").as_deref(), Some("sdh"));
+        assert_eq!(hearing("song.en.c.c.srt").as_deref(), Some("c.c"));
+        // Tokens are matched literally
+        assert!(!regex.is_match("song.en.cxc.srt"));
+        let default = Regex::new(&get_sub_path_regex(&["en", "ja"], false, false, &[])).unwrap();
+        assert!(!default.is_match("song.en.cc.srt"));
+        // An empty token is left out rather than matching any segment
+        let empty = get_sub_path_regex(&["en", "ja"], false, false, &["".to_owned()]);
+        assert!(!Regex::new(&empty).unwrap().is_match("song.en.xyz.srt"));
+
+        let root = tempfile::tempdir().unwrap();
+        for file in ["song.en.cc.srt", "song.ja.srt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions {
+            hi_tokens: vec!["cc".to_owned()],
+            ..Default::default()
+        };
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        let en = found[&root_path].iter().find(|s| s.lang == "en").unwrap();
+        assert_eq!(en.variant, Variant::Hi);
+        assert_eq!(en.stem, Path::new("song"));
+
+        let opts = SearchOptions {
+            no_hi: true,
+            ..opts
+        };
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        assert!(found[&root_path].iter().all(|s| s.lang == "ja"));
+    }
+
+    #[test]
+    fn test_lang_globs() {
+        let regex = Regex::new(&get_sub_path_regex(&["en*", "ja"], false, false, &[])).unwrap();
+        let lang = |name| {
+            let captures = regex.captures(name)?;
+            let track = if captures.name("lang1").is_some() {
+                1
+            } else {
+                2
+            };
+            Some((captures["lang"].to_owned(), track))
+        };
+
+        assert_eq!(lang("movie.en-US.srt"), Some(("en-US".to_owned(), 1)));
+        assert_eq!(lang("movie.eng.hi.srt"), Some(("eng".to_owned(), 1)));
+        assert_eq!(lang("movie.ja.srt"), Some(("ja".to_owned(), 2)));
+        assert_eq!(lang("movie.de.srt"), None);
+
+        let literal = Regex::new(&get_sub_path_regex(&["en", "ja"], false, false, &[])).unwrap();
+        assert!(literal.is_match("movie.en.srt"));
+        assert!(!literal.is_match("movie.de.srt"));
+        assert!(!literal.is_match("movie.en-US.srt"));
+        // Regex syntax in a language is matched literally
+        let dotted = Regex::new(&get_sub_path_regex(&["e.", "ja"], false, false, &[])).unwrap();
+        assert!(!dotted.is_match("movie.en.srt"));
+
+        let root = tempfile::tempdir().unwrap();
+        for file in ["ep1.en-US.srt", "ep1.ja.srt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+        let root_path = root.path().to_path_buf();
+        let found =
+            find_matching_subtitle_files(&root_path, "en*", "ja", &SearchOptions::default())
+                .unwrap();
+        let en = found[&root_path].iter().find(|s| s.lang == "en*").unwrap();
+        assert_eq!(en.code, "en-US");
+    }
+
+    #[test]
+    fn test_preferred_variant() {
+        let sub = |name: &str, variant| SubFile {
+            path: PathBuf::from(name),
+            lang: "en".to_owned(),
+            code: "en".to_owned(),
+            variant,
+            parts: Vec::new(),
+            detected: false,
+            stem: base_file_stem(Path::new(name), &[]).unwrap(),
+        };
+        let subs = vec![
+            sub("movie.en.sdh.srt", Variant::Sdh),
+            sub("movie.en.hi.srt", Variant::Hi),
+            sub("movie.en.srt", Variant::Normal),
+            sub("other.en.sdh.srt", Variant::Sdh),
+        ];
+        let pick = |stem: &str, preference: &[Variant]| {
+            preferred_sub(&subs, Path::new(stem), "en", preference)
+                .map(|s| s.path.to_str().unwrap().to_owned())
+        };
+
+        let default = [Variant::Normal, Variant::Hi, Variant::Sdh];
+        assert_eq!(pick("movie", &default).as_deref(), Some("movie.en.srt"));
+        assert_eq!(
+            pick("movie", &[Variant::Sdh, Variant::Hi]).as_deref(),
+            Some("movie.en.sdh.srt")
+        );
+        assert_eq!(pick("other", &default).as_deref(), Some("other.en.sdh.srt"));
+        // Variants left out of the preference are never picked
+        assert_eq!(pick("other", &[Variant::Normal, Variant::Hi]), None);
+        assert_eq!(pick("missing", &default), None);
+    }
+
+    #[test]
+    fn test_span_ratio() {
+        let short = SubRip {
+            subtitles: vec![
+                cue(1, 60_000, 61_000, "a"),
+                cue(2, 1_259_000, 1_260_000, "b"),
+            ],
+        };
+        let long = SubRip {
+            subtitles: vec![
+                cue(1, 60_000, 61_000, "a"),
+                cue(2, 7_259_000, 7_260_000, "b"),
+            ],
+        };
+
+        assert_eq!(span_ratio(&short, &short), 1.0);
+        assert_eq!(span_ratio(&short, &long), 6.0);
+        assert_eq!(span_ratio(&long, &short), 6.0);
+        assert_eq!(span_ratio(&short, &SubRip::default()), f64::INFINITY);
+        assert_eq!(span_ratio(&SubRip::default(), &SubRip::default()), 1.0);
+    }
+
+    #[test]
+    fn test_preset_is_overridden_by_explicit_style() {
+        let explicit = TrackOptions {
+            color: Some("red".to_owned()),
+            ..Default::default()
+        };
+        let style = explicit.or(builtin_preset("boxed-bottom").unwrap());
+
+        assert_eq!(style.color.as_deref(), Some("red"));
+        assert!(matches!(style.position, Some(SubPosition::BottomCenter)));
+        assert_eq!(style.box_color.as_deref(), Some("#000000"));
+        assert!(builtin_preset("no-such-preset").is_none());
+    }
+
+    #[test]
+    fn test_flat_search_does_not_descend() {
+        let root = tempfile::tempdir().unwrap();
+        let season = root.path().join("season2");
+        fs::create_dir(&season).unwrap();
+        for file in ["ep1.en.srt", "ep1.ja.srt"] {
+            fs::write(root.path().join(file), "").unwrap();
+            fs::write(season.join(file), "").unwrap();
+        }
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions::default();
+        let all = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        assert_eq!(all.len(), 2);
+
+        let opts = SearchOptions {
+            flat: true,
+            ..Default::default()
+        };
+        let flat = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        assert_eq!(flat.len(), 1);
+        assert_eq!(flat[&root_path].len(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_search_skips_unreadable_entries() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("ep1.en.srt"), "").unwrap();
+        fs::write(root.path().join("ep1.ja.srt"), "").unwrap();
+        // Following a dangling symlink fails, like reading a directory without permission
+        std::os::unix::fs::symlink(root.path().join("gone"), root.path().join("broken")).unwrap();
+
+        let root_path = root.path().to_path_buf();
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &SearchOptions::default())
+            .unwrap();
+        assert_eq!(found[&root_path].len(), 2);
+    }
+
+    #[test]
+    fn test_no_hi_skips_hearing_impaired() {
+        let root = tempfile::tempdir().unwrap();
+        for file in [
+            "ep1.en.hi.srt",
+            "ep1.ja.srt",
+            "ep2.en.srt",
+            "ep2.ja.sdh.srt",
+        ] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions {
+            no_hi: true,
+            ..Default::default()
+        };
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        let mut names: Vec<_> = found[&root_path]
+            .iter()
+            .map(|s| s.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["ep1.ja.srt", "ep2.en.srt"]);
+
+        // Neither episode has a pair left to merge
+        let preference = [Variant::Normal, Variant::Hi, Variant::Sdh];
+        let subs = &found[&root_path];
+        for ep in ["ep1", "ep2"] {
+            let en = preferred_sub(subs, Path::new(ep), "en", &preference);
+            let ja = preferred_sub(subs, Path::new(ep), "ja", &preference);
+            assert!(en.is_none() || ja.is_none());
+        }
+
+        let only_hi = tempfile::tempdir().unwrap();
+        fs::write(only_hi.path().join("ep1.en.hi.srt"), "").unwrap();
+        fs::write(only_hi.path().join("ep1.ja.hi.srt"), "").unwrap();
+        let only_hi_path = only_hi.path().to_path_buf();
+        let found = find_matching_subtitle_files(&only_hi_path, "en", "ja", &opts).unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_base_file_stem() {
+        let stem = |name: &str| base_file_stem(Path::new(name), &[]).unwrap();
+        assert_eq!(stem("The.Matrix.1999.en.srt"), Path::new("The.Matrix.1999"));
+        assert_eq!(stem("song.ja.hi.srt"), Path::new("song"));
+        assert_eq!(stem("movie.en.sdh.part2.vtt"), Path::new("movie"));
+        assert_eq!(stem("Show.S01E01.en-US.srt.gz"), Path::new("Show.S01E01"));
+        assert_eq!(stem("dir/movie.hi.srt"), Path::new("movie"));
+        assert!(base_file_stem(Path::new("movie.srt"), &[]).is_err());
+        // Custom hearing impaired tokens are left out like `hi`
+        assert_eq!(stem("song.en.cc.srt"), Path::new("song.en"));
+        let tokens = ["cc".to_owned()];
+        let custom = base_file_stem(Path::new("song.en.cc.srt"), &tokens).unwrap();
+        assert_eq!(custom, Path::new("song"));
+    }
+
+    #[test]
+    fn test_prefer_hi_selects_hearing_impaired() {
+        let root = tempfile::tempdir().unwrap();
+        for file in ["movie.en.srt", "movie.en.hi.srt", "movie.ja.srt"] {
+            fs::write(root.path().join(file), "").unwrap();
+        }
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions::default();
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        let subs = &found[&root_path];
+        let pick = |preference| {
+            preferred_sub(subs, Path::new("movie"), "en", preference)
+                .map(|s| s.path.file_name().unwrap().to_str().unwrap().to_string())
+        };
+        let default = [Variant::Normal, Variant::Hi, Variant::Sdh];
+        assert_eq!(pick(&default).as_deref(), Some("movie.en.srt"));
+        assert_eq!(pick(PREFER_HI).as_deref(), Some("movie.en.hi.srt"));
+    }
+
+    #[test]
+    fn test_merge_conflict_policies() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "one"), cue(2, 2000, 3000, "two")],
+        };
+        let srt2 = SubRip {
+            subtitles: vec![cue(1, 2000, 3000, "deux"), cue(2, 4000, 5000, "trois")],
+        };
+        let texts = |policy| {
+            let opts = MergeOptions {
+                on_conflict: policy,
+                ..Default::default()
+            };
+            merge(srt1.clone(), srt2.clone(), &opts)
+                .subtitles
+                .into_iter()
+                .map(|s| s.text.join("|"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts(ConflictPolicy::Keep), ["one", "two", "deux", "trois"]);
+        assert_eq!(texts(ConflictPolicy::Prefer1), ["one", "two", "trois"]);
+        assert_eq!(texts(ConflictPolicy::Prefer2), ["one", "deux", "trois"]);
+        assert_eq!(texts(ConflictPolicy::Concat), ["one", "two|deux", "trois"]);
+    }
+
+    #[test]
+    fn test_merge_interleaves_chronologically() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "a"), cue(2, 4000, 5000, "d")],
+        };
+        let srt2 = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "b"),
+                cue(2, 2000, 3000, "c"),
+                cue(3, 4000, 4500, "e"),
+            ],
+        };
+        let merged = merge(srt1, srt2, &MergeOptions::default());
+
+        let texts: Vec<&str> = merged
+            .subtitles
+            .iter()
+            .map(|s| s.text[0].as_str())
+            .collect();
+        assert_eq!(texts, ["a", "b", "c", "d", "e"]);
+        assert!(merged
+            .subtitles
+            .windows(2)
+            .all(|w| w[0].start <= w[1].start));
+        let sequences: Vec<u32> = merged.subtitles.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_merge_renumbers_both_tracks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path1 = dir.path().join("movie.en.srt");
+        let path2 = dir.path().join("movie.ja.srt");
+        let srt1 = "1\n00:00:01,000 --> 00:00:02,000\na\n\n2\n00:00:03,000 --> 00:00:04,000\nb\n\n3\n00:00:05,000 --> 00:00:06,000\nc\n";
+        let srt2 = "1\n00:00:07,000 --> 00:00:08,000\nd\n\n2\n00:00:09,000 --> 00:00:10,000\ne\n";
+        fs::write(&path1, srt1).unwrap();
+        fs::write(&path2, srt2).unwrap();
+
+        let opts = LoadOptions::default();
+        let srt1 = load_sub(&path1, &opts).unwrap();
+        let srt2 = load_sub(&path2, &opts).unwrap();
+        let merged = merge(srt1, srt2, &MergeOptions::default());
+
+        let sequences: Vec<u32> = merged.subtitles.iter().map(|s| s.sequence).collect();
+        assert_eq!(sequences, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_merge_sources() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "one"), cue(2, 2000, 3000, "two")],
+        };
+        let srt2 = SubRip {
+            subtitles: vec![cue(1, 2000, 3000, "deux"), cue(2, 4000, 5000, "trois")],
+        };
+        let sources = |policy| {
+            let opts = MergeOptions {
+                on_conflict: policy,
+                ..Default::default()
+            };
+            merge_tracks_with_sources(vec![srt1.clone(), srt2.clone()], &opts)
+                .1
+                .into_iter()
+                .map(|s| (s.merged, s.track, s.original))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sources(ConflictPolicy::Keep),
+            [(1, 1, 1), (2, 1, 2), (3, 2, 1), (4, 2, 2)]
+        );
+        assert_eq!(
+            sources(ConflictPolicy::Prefer2),
+            [(1, 1, 1), (2, 2, 1), (3, 2, 2)]
+        );
+        assert_eq!(
+            sources(ConflictPolicy::Concat),
+            [(1, 1, 1), (2, 1, 2), (2, 2, 1), (3, 2, 2)]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crossref.tsv");
+        let (_, sources) = merge_tracks_with_sources(vec![srt1, srt2], &MergeOptions::default());
+        write_crossref(&path, &sources[..2]).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "1\t1\t1\n2\t1\t2\n");
+    }
+
+    #[test]
+    fn test_preview_cues() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 1000, 2000, "one"), cue(2, 5000, 6000, "three")],
+        };
+        let mut srt2 = SubRip {
+            subtitles: vec![cue(1, 3000, 4000, "two")],
+        };
+        let opts = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt2, &opts);
+        let merged = merge(srt1, srt2, &MergeOptions::default());
+
+        assert_eq!(
+            preview_cues(&merged, 2),
+            "1\n00:00:01,000 --> 00:00:02,000\none\n\n\
+             2\n00:00:03,000 --> 00:00:04,000\n{\\an8} two\n\n\
+             ... and 1 more cues\n"
+        );
+        assert_eq!(preview_cues(&merged, 5), merged.render());
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("movie.ja.srt");
+        fs::write(
+            &sub,
+            "1\n00:00:01,000 --> 00:00:02,500\nこんにちは\n\n2\n00:01:02,000 --> 00:01:03,000\nさようなら\n",
+        )
+        .unwrap();
+        let mut srt = load_sub(&sub, &LoadOptions::default()).unwrap();
+        let opts = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let path = dir.path().join("dump.json");
+        dump_json(&path, &[DumpedTrack::new(&sub, &srt)]).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let cues = json[0]["cues"].as_array().unwrap();
+        assert_eq!(json[0]["path"], sub.to_str().unwrap());
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0]["sequence"], 1);
+        assert_eq!(cues[0]["start"], "00:00:01,000");
+        assert_eq!(cues[0]["end"], "00:00:02,500");
+        assert_eq!(cues[1]["start"], "00:01:02,000");
+        // Styled as it would be merged, tags and all
+        assert_eq!(cues[0]["text"][0], "{\\an8} こんにちは");
+    }
+
+    #[test]
+    fn test_misnumbered_cues() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.srt");
+        fs::write(
+            &path,
+            "1\n00:00:01,000 --> 00:00:02,000\nOne\n\n\
+             1\n00:00:03,000 --> 00:00:04,000\nTwo\n\n\
+             2\n00:00:05,000 --> 00:00:06,000\nThree\n",
+        )
+        .unwrap();
+
+        // Collect the warnings of loading it. No other test sets up logging, and this only
+        // fails if it was already set up
+        init_logging(log::Level::Error, true).unwrap();
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(srt.subtitles.len(), 3);
+        assert_eq!(misnumbered_cues(&srt), [(1, 1)]);
+        let warnings = dir.path().join("warnings.json");
+        write_warnings(&warnings).unwrap();
+        let warnings: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(warnings).unwrap()).unwrap();
+        let path = path.display().to_string();
+        assert!(warnings.as_array().unwrap().iter().any(|w| {
+            w["reason"] == "misnumbered_cues" && w["path"].as_str() == Some(path.as_str())
+        }));
+
+        let mut srt = SubRip {
+            subtitles: vec![cue(3, 0, 1000, "a"), cue(2, 1000, 2000, "b")],
+        };
+        assert_eq!(misnumbered_cues(&srt), [(3, 2)]);
+        renumber(&mut srt);
+        assert!(misnumbered_cues(&srt).is_empty());
+    }
+
+    #[test]
+    fn test_vertical_neighbor() {
+        assert_eq!(
+            SubPosition::BottomCenter.vertical_neighbor(),
+            SubPosition::MiddleCenter
+        );
+        assert_eq!(
+            SubPosition::MiddleLeft.vertical_neighbor(),
+            SubPosition::TopLeft
+        );
+        assert_eq!(
+            SubPosition::TopRight.vertical_neighbor(),
+            SubPosition::MiddleRight
+        );
+    }
+
+    #[test]
+    fn test_merge_dedup() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 1000, 3000, "Hello"), cue(2, 5000, 6000, "Again")],
+        };
+        let mut srt2 = SubRip {
+            subtitles: vec![
+                cue(1, 2000, 4000, "Hello"),
+                cue(2, 6000, 7000, "Again"),
+                cue(3, 6500, 7000, "Other"),
+            ],
+        };
+        apply_sub_changes(&mut srt2, &builtin_preset("yellow-top").unwrap());
+        let opts = MergeOptions {
+            dedup: true,
+            ..Default::default()
+        };
+        let (merged, sources) = merge_tracks_with_sources(vec![srt1, srt2], &opts);
+
+        let cues: Vec<_> = merged
+            .subtitles
+            .iter()
+            .map(|s| (s.text[0].as_str(), s.start.into(), s.end.into()))
+            .collect();
+        let ms = Duration::from_millis;
+        assert_eq!(cues[0], ("Hello", ms(1000), ms(4000)));
+        // Cues only touching each other do not overlap
+        assert_eq!(cues[1], ("Again", ms(5000), ms(6000)));
+        assert_eq!(cues.len(), 4);
+        assert_eq!(
+            sources.iter().filter(|s| s.merged == 1).count(),
+            2,
+            "the deduplicated cue comes from both tracks"
+        );
+
+        let same = || SubRip {
+            subtitles: vec![cue(1, 1000, 3000, "Hello")],
+        };
+        assert_eq!(merge(same(), same(), &opts).subtitles.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_stats() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 1000, 3000, "Hello"), cue(2, 5000, 6000, "Bye")],
+        };
+        let srt2 = SubRip {
+            subtitles: vec![
+                cue(1, 2000, 4000, "{\\an8} Hello"),
+                cue(2, 7000, 9500, "Later"),
+                cue(3, 8000, 9000, "Soon"),
+            ],
+        };
+        let opts = MergeOptions {
+            dedup: true,
+            ..Default::default()
+        };
+        let (merged, _, stats) = merge_tracks_with_stats(vec![srt1, srt2], &opts);
+
+        assert_eq!(stats.track_cues, [2, 3]);
+        // The duplicated greeting is counted once
+        assert_eq!(stats.merged_cues, 2 + 3 - 1);
+        assert_eq!(stats.merged_cues, merged.subtitles.len());
+        assert_eq!(stats.earliest, Some(Duration::from_millis(1000)));
+        assert_eq!(stats.latest, Some(Duration::from_millis(9500)));
+        assert_eq!(
+            stats.to_string(),
+            "2 + 3 cues merged into 4 cues spanning 00:00:01,000 --> 00:00:09,500"
+        );
+
+        let (_, _, stats) = merge_tracks_with_stats(
+            vec![SubRip::default(), SubRip::default()],
+            &MergeOptions::default(),
+        );
+        assert_eq!(stats.to_string(), "0 + 0 cues merged into 0 cues");
+    }
+
+    #[test]
+    fn test_sort_by() {
+        // Cues out of order in their files, as in some hand-edited subtitles
+        let srt1 = || SubRip {
+            subtitles: vec![cue(1, 3000, 4000, "a2"), cue(2, 1000, 2000, "a1")],
+        };
+        let srt2 = || SubRip {
+            subtitles: vec![cue(1, 2000, 3000, "b1"), cue(2, 500, 800, "b0")],
+        };
+
+        for (sort_by, expected) in [
+            (SortBy::Time, ["b0", "a1", "b1", "a2"]),
+            (SortBy::Track, ["a1", "a2", "b0", "b1"]),
+            (SortBy::None, ["a2", "a1", "b1", "b0"]),
+        ] {
+            let opts = MergeOptions {
+                sort_by,
+                ..Default::default()
+            };
+            let merged = merge(srt1(), srt2(), &opts);
+            let texts: Vec<_> = merged
+                .subtitles
+                .iter()
+                .map(|s| s.text[0].as_str())
+                .collect();
+            assert_eq!(texts, expected, "{sort_by:?}");
+            // Numbered in the chosen order
+            let sequences: Vec<_> = merged.subtitles.iter().map(|s| s.sequence).collect();
+            assert_eq!(sequences, [1, 2, 3, 4], "{sort_by:?}");
+        }
+    }
+
+    #[test]
+    fn test_combine_overlapping() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 1000, 3000, "hello"), cue(2, 5000, 6000, "bye")],
+        };
+        let mut srt2 = SubRip {
+            subtitles: vec![
+                cue(1, 500, 2000, "こんにちは"),
+                cue(2, 5500, 7000, "さようなら"),
+            ],
+        };
+        let opts = MergeOptions {
+            combine_overlapping: true,
+            ..Default::default()
+        };
+
+        let bottom = TrackOptions {
+            position: Some(SubPosition::BottomCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt2, &bottom);
+        let merged = merge(srt1.clone(), srt2, &opts);
+        let cues: Vec<_> = merged
+            .subtitles
+            .iter()
+            .map(|s| (s.start.to_string(), s.end.to_string(), s.text.clone()))
+            .collect();
+        assert_eq!(
+            cues,
+            [
+                (
+                    "00:00:00,500".to_owned(),
+                    "00:00:03,000".to_owned(),
+                    vec!["hello".to_owned(), "こんにちは".to_owned()]
+                ),
+                (
+                    "00:00:05,000".to_owned(),
+                    "00:00:07,000".to_owned(),
+                    vec!["bye".to_owned(), "さようなら".to_owned()]
+                ),
+            ]
+        );
+
+        // Tracks at different positions are still shown apart
+        let mut srt2 = SubRip {
+            subtitles: vec![cue(1, 500, 2000, "こんにちは")],
+        };
+        let top = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt2, &top);
+        assert_eq!(merge(srt1, srt2, &opts).subtitles.len(), 3);
+    }
+
+    #[test]
+    fn test_merge_three_tracks() {
+        let track = |cues| SubRip { subtitles: cues };
+        let srts = vec![
+            track(vec![cue(1, 0, 1000, "en"), cue(2, 4000, 5000, "en")]),
+            track(vec![cue(1, 2000, 3000, "ja")]),
+            track(vec![cue(1, 1000, 2000, "de"), cue(2, 6000, 7000, "de")]),
+        ];
+        let (merged, sources) = merge_tracks_with_sources(srts, &MergeOptions::default());
+
+        let cues: Vec<_> = merged
+            .subtitles
+            .iter()
+            .map(|s| (s.sequence, s.text[0].as_str()))
+            .collect();
+        assert_eq!(
+            cues,
+            [(1, "en"), (2, "de"), (3, "ja"), (4, "en"), (5, "de")]
+        );
+        let tracks: Vec<_> = sources.iter().map(|s| (s.track, s.original)).collect();
+        assert_eq!(tracks, [(1, 1), (3, 1), (2, 1), (1, 2), (3, 2)]);
+
+        let extra: ExtraTrack = "movie.de.srt,color=#fabd2f,position=middle-center,offset=-1.5"
+            .parse()
+            .unwrap();
+        assert_eq!(extra.path, PathBuf::from("movie.de.srt"));
+        assert_eq!(extra.color.as_deref(), Some("#fabd2f"));
+        assert_eq!(extra.position, Some(SubPosition::MiddleCenter));
+        assert_eq!(extra.offset, Some(-1.5));
+        assert!("movie.de.srt,size=2".parse::<ExtraTrack>().is_err());
+        assert!(",color=red".parse::<ExtraTrack>().is_err());
+    }
+
+    #[test]
+    fn test_offset() {
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 500, 2000, "a"), cue(2, 3000, 4000, "b")],
+        };
+        apply_sub_changes(
+            &mut srt,
+            &TrackOptions {
+                offset: Some(-1.0),
+                ..Default::default()
+            },
+        );
+
+        let times: Vec<(Duration, Duration)> = srt
+            .subtitles
+            .iter()
+            .map(|s| (s.start.into(), s.end.into()))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                (Duration::ZERO, Duration::from_millis(1000)),
+                (Duration::from_millis(2000), Duration::from_millis(3000))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_offset_from() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "before"),
+                cue(2, 60000, 61000, "at"),
+                cue(3, 90000, 92000, "after"),
+            ],
+        };
+        apply_sub_changes(
+            &mut srt,
+            &TrackOptions {
+                offset: Some(2.5),
+                offset_from: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+
+        let starts: Vec<Duration> = srt.subtitles.iter().map(|s| s.start.into()).collect();
+        assert_eq!(
+            starts,
+            [
+                Duration::from_millis(1000),
+                Duration::from_millis(62500),
+                Duration::from_millis(92500)
+            ]
+        );
+        assert_eq!(srt.subtitles[0].end, Duration::from_millis(2000).into());
+        assert_eq!(srt.subtitles[2].end, Duration::from_millis(94500).into());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.ja.srt");
+        fs::write(&path, "").unwrap();
+
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        let e = check_not_empty(srt.subtitles.is_empty(), &path, false).unwrap_err();
+        assert!(e.to_string().contains("movie.ja.srt"), "{e}");
+        assert!(check_not_empty(srt.subtitles.is_empty(), &path, true).is_ok());
+        // Streams read their first cue ahead, so they are known to be empty before merging
+        assert!(stream_sub(&path, &LoadOptions::default())
+            .unwrap()
+            .is_empty());
+
+        let srt = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "a")],
+        };
+        assert!(check_not_empty(srt.subtitles.is_empty(), &path, false).is_ok());
+    }
+
+    #[test]
+    fn test_negative_offset() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 10000, 12000, "early"),
+                cue(2, 29000, 31000, "cut"),
+                cue(3, 40000, 42000, "late"),
+            ],
+        };
+        let opts = TrackOptions {
+            offset: Some(-30.0),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        // Cues entirely before the start are dropped, rather than shown for no time at all
+        let cues: Vec<(u32, Duration, Duration, &str)> = srt
+            .subtitles
+            .iter()
+            .map(|s| (s.sequence, s.start.into(), s.end.into(), s.text[0].as_str()))
+            .collect();
+        assert_eq!(
+            cues,
+            [
+                (1, Duration::ZERO, Duration::from_secs(1), "cut"),
+                (2, Duration::from_secs(10), Duration::from_secs(12), "late")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pos_xy() {
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "left")],
+        };
+        let opts = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            pos_xy: Some("120, -40".parse().unwrap()),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+        assert_eq!(srt.subtitles[0].text, ["{\\pos(120,-40)} left"]);
+
+        assert_eq!("10,20".parse::<Point>().unwrap(), Point { x: 10, y: 20 });
+        assert!("10".parse::<Point>().is_err());
+        assert!("10,2.5".parse::<Point>().is_err());
+        assert!("a,b".parse::<Point>().is_err());
+    }
+
+    #[test]
+    fn test_styling_keeps_existing_formatting() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 1000, "{\\an5}centered"),
+                cue(2, 1000, 2000, "<i><b>emphasis</b></i>"),
+                cue(3, 2000, 3000, "<i>one</i> and <i>two</i>"),
+            ],
+        };
+        let color = |position| TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            position,
+            ..Default::default()
+        };
+
+        let mut unpositioned = srt.clone();
+        apply_sub_changes(&mut unpositioned, &color(None));
+        assert_eq!(
+            unpositioned.subtitles[0].text,
+            ["{\\an5}<font color=\"#fbf1c7\">centered</font>"]
+        );
+
+        apply_sub_changes(&mut srt, &color(Some(SubPosition::TopCenter)));
+        let texts: Vec<&str> = srt.subtitles.iter().map(|s| s.text[0].as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "{\\an8} <font color=\"#fbf1c7\">centered</font>",
+                "{\\an8} <i><b><font color=\"#fbf1c7\">emphasis</font></b></i>",
+                "{\\an8} <font color=\"#fbf1c7\"><i>one</i> and <i>two</i></font>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fps_scale_before_offset() {
+        let retimed = |offset| {
+            let mut srt = SubRip {
+                subtitles: vec![cue(1, 10_000, 12_000, "a")],
+            };
+            let opts = TrackOptions {
+                fps_scale: Some((24.0, 25.0)),
+                offset,
+                ..Default::default()
+            };
+            apply_sub_changes(&mut srt, &opts);
+            let cue = &srt.subtitles[0];
+            (cue.start.into(), cue.end.into())
+        };
+
+        assert_eq!(
+            retimed(None),
+            (Duration::from_millis(9600), Duration::from_millis(11_520))
+        );
+        assert_eq!(
+            retimed(Some(1.0)),
+            (Duration::from_millis(10_600), Duration::from_millis(12_520))
+        );
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#fbf1c7").unwrap(), "#fbf1c7");
+        assert_eq!(parse_color("#FFF").unwrap(), "#FFF");
+        assert_eq!(parse_color("Gold").unwrap(), "gold");
+        for invalid in ["#gggggg", "bluee", "#ffff", "fbf1c7", ""] {
+            let err = parse_color(invalid).unwrap_err().to_string();
+            assert!(err.contains(&format!("{:?}", invalid)), "{}", err);
+        }
+        assert!("movie.de.srt,color=#12".parse::<ExtraTrack>().is_err());
+    }
+
+    #[test]
+    fn test_split_subtitles_are_stitched() {
+        let root = tempfile::tempdir().unwrap();
+        let part1 =
+            "1\n00:00:01,000 --> 00:00:02,000\nfirst\n\n2\n00:00:10,000 --> 00:00:20,000\nsecond\n";
+        let part2 = "1\n00:00:05,000 --> 00:00:06,000\nthird\n";
+        fs::write(root.path().join("movie.en.part2.srt"), part2).unwrap();
+        fs::write(root.path().join("movie.en.part1.srt"), part1).unwrap();
+        fs::write(root.path().join("movie.ja.srt"), part2).unwrap();
+
+        let root_path = root.path().to_path_buf();
+        let opts = SearchOptions::default();
+        let found = find_matching_subtitle_files(&root_path, "en", "ja", &opts).unwrap();
+        let en = found[&root_path].iter().find(|s| s.lang == "en").unwrap();
+        assert_eq!(
+            en.parts,
+            [
+                root.path().join("movie.en.part1.srt"),
+                root.path().join("movie.en.part2.srt")
+            ]
+        );
+
+        let stitched = load_sub_file(en, &LoadOptions::default()).unwrap();
+        let third = &stitched.subtitles[2];
+        assert_eq!(stitched.subtitles.len(), 3);
+        assert_eq!(third.sequence, 3);
+        assert_eq!(third.start, Duration::from_secs(25).into());
+        assert_eq!(third.end, Duration::from_secs(26).into());
+    }
+
+    #[test]
+    fn test_keep_lines() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                SrtSubtitle {
+                    text: vec!["漢字".to_owned(), "かんじ".to_owned(), "kanji".to_owned()],
+                    ..cue(1, 0, 1000, "")
+                },
+                cue(2, 1000, 2000, "short"),
+            ],
+        };
+        let opts = TrackOptions {
+            keep_lines: Some("2-3".parse().unwrap()),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        assert_eq!(srt.subtitles[0].text, ["かんじ", "kanji"]);
+        assert_eq!(srt.subtitles[1].text, ["short"]);
+
+        assert_eq!(
+            "1".parse::<LineRange>().unwrap(),
+            LineRange { first: 1, last: 1 }
+        );
+        assert!("0".parse::<LineRange>().is_err());
+        assert!("3-2".parse::<LineRange>().is_err());
+    }
+
+    #[test]
+    fn test_skip_outside_window() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 10_000, 20_000, "Previously on..."),
+                cue(2, 60_000, 61_000, "a"),
+                cue(3, 62_000, 63_000, "b"),
+                cue(4, 1_300_000, 1_301_000, "credits"),
+            ],
+        };
+        let opts = TrackOptions {
+            skip_before: Some(parse_time("00:01:00").unwrap()),
+            skip_after: Some(parse_time("00:20:00.500").unwrap()),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let cues: Vec<_> = srt
+            .subtitles
+            .iter()
+            .map(|s| (s.sequence, s.text[0].as_str()))
+            .collect();
+        assert_eq!(cues, [(1, "a"), (2, "b")]);
+
+        assert_eq!(parse_time("01:02:03").unwrap(), Duration::from_secs(3723));
+        assert!(parse_time("01:02").is_err());
+        assert_eq!(
+            parse_time("00:00:01.5").unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            parse_time("00:00:01,25").unwrap(),
+            Duration::from_millis(1250)
+        );
+        assert_eq!(
+            parse_time("00:00:01.2345").unwrap(),
+            Duration::from_millis(1234)
+        );
+        assert!(parse_time("00:00:01.").is_err());
+        assert!(parse_time("00:00:01.-5").is_err());
+        assert!(parse_time("00:60:00").is_err());
+        assert!(parse_time("00:00:60").is_err());
+        assert!(parse_time("18446744073709551615:00:00").is_err());
+    }
+
+    #[test]
+    fn test_plain_strips_tags() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 1000, "{\\an8}<i>Hello <b>there</b></i>"),
+                cue(2, 1000, 2000, "no tags"),
+            ],
+        };
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            position: Some(SubPosition::TopCenter),
+            plain: true,
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let texts: Vec<&str> = srt.subtitles.iter().map(|s| s.text[0].as_str()).collect();
+        assert_eq!(texts, ["Hello there", "no tags"]);
+    }
+
+    #[test]
+    fn test_wrap_long_line() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 1000, "the quick brown fox jumps over the lazy dog"),
+                cue(2, 1000, 2000, "short"),
+            ],
+        };
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            position: Some(SubPosition::TopCenter),
+            wrap: Some(16),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        // The tags are not counted, and only lead the first line
+        assert_eq!(
+            srt.subtitles[0].text,
+            [
+                "{\\an8} <font color=\"#fbf1c7\">the quick brown",
+                "fox jumps over",
+                "the lazy dog</font>",
+            ]
+        );
+        assert_eq!(
+            srt.subtitles[1].text,
+            ["{\\an8} <font color=\"#fbf1c7\">short</font>"]
+        );
+    }
+
+    #[test]
+    fn test_wrap_keeps_long_words_whole() {
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "a supercalifragilistic word")],
+        };
+        let opts = TrackOptions {
+            wrap: Some(10),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        assert_eq!(srt.subtitles[0].text, ["a", "supercalifragilistic", "word"]);
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("00:02:13.500").unwrap(), 133.5);
+        assert_eq!(parse_offset("-0:05").unwrap(), -5.0);
+        assert_eq!(parse_offset("2:13.5").unwrap(), 133.5);
+        assert_eq!(parse_offset("-0:05.25").unwrap(), -5.25);
+        assert!(parse_offset("2:75").is_err());
+        assert_eq!(parse_offset("-1.5").unwrap(), -1.5);
+        assert_eq!(parse_offset("1:00:00").unwrap(), 3600.0);
+        assert!(parse_offset("1:2:3:4").is_err());
+        assert!(parse_offset("soon").is_err());
+        assert!(parse_offset("inf").is_err());
+    }
+
+    #[test]
+    fn test_anchor() {
+        let anchor: Anchor = "2=00:00:10.000".parse().unwrap();
+        assert_eq!(anchor.cue, 2);
+        let short: Anchor = "2=0:10.5".parse().unwrap();
+        assert_eq!(short.at, Duration::from_millis(10500));
+        assert!("0=1:00".parse::<Anchor>().is_err());
+        assert!("2=-1".parse::<Anchor>().is_err());
+
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "first"),
+                cue(2, 4000, 5000, "anchored"),
+                cue(3, 6500, 8000, "last"),
+            ],
+        };
+        let opts = TrackOptions {
+            anchor: Some(anchor),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        // Every cue moves by the 6s that take the anchored cue to 10s
+        let starts: Vec<Duration> = srt.subtitles.iter().map(|s| s.start.into()).collect();
+        let ends: Vec<Duration> = srt.subtitles.iter().map(|s| s.end.into()).collect();
+        let secs = |ms: &[u64]| {
+            ms.iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts, secs(&[7000, 10000, 12500]));
+        assert_eq!(ends, secs(&[8000, 11000, 14000]));
+    }
+
+    #[test]
+    fn test_sync_map() {
+        let map: SyncMap = "# from to\n00:00:10 00:00:12\n\n00:00:20 00:00:32\n"
+            .parse()
+            .unwrap();
+        assert!("10 12 14".parse::<SyncMap>().is_err());
+        assert!("10 12\n20 11".parse::<SyncMap>().is_err());
+        assert!("# nothing".parse::<SyncMap>().is_err());
+
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 5000, 6000, "before"),
+                cue(2, 15000, 16000, "between"),
+                cue(3, 25000, 26000, "after"),
+            ],
+        };
+        let opts = TrackOptions {
+            sync_map: Some(map),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        // Between the anchors the 10s from 00:00:10 are stretched to the 20s from 00:00:12,
+        // outside of them cues are shifted like the nearest anchor
+        let timings: Vec<(Duration, Duration)> = srt
+            .subtitles
+            .iter()
+            .map(|s| (s.start.into(), s.end.into()))
+            .collect();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            timings,
+            [
+                (ms(7000), ms(8000)),
+                (ms(22000), ms(24000)),
+                (ms(37000), ms(38000))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 1000, "gap after"),
+                cue(2, 3000, 6000, "overlaps next"),
+                cue(3, 5000, 7000, "last"),
+            ],
+        };
+        let opts = TrackOptions {
+            fill_gaps: true,
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let ends: Vec<Duration> = srt.subtitles.iter().map(|s| s.end.into()).collect();
+        assert_eq!(
+            ends,
+            [
+                Duration::from_millis(3000),
+                Duration::from_millis(6000),
+                Duration::from_millis(7000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hi_annotation_color() {
+        let track = || SubRip {
+            subtitles: vec![cue(1, 0, 1000, "[door] Who is it?")],
+        };
+        let opts = TrackOptions {
+            annotation_color: Some("#928374".to_owned()),
+            ..Default::default()
+        };
+
+        let mut hi = track();
+        apply_sub_changes(&mut hi, &opts.clone().for_variant(Variant::Hi));
+        assert_eq!(
+            hi.subtitles[0].text,
+            ["<font color=\"#928374\">[door]</font> Who is it?"]
+        );
+
+        let mut normal = track();
+        apply_sub_changes(&mut normal, &opts.for_variant(Variant::Normal));
+        assert_eq!(normal.subtitles[0].text, ["[door] Who is it?"]);
+
+        assert_eq!(
+            Variant::from_path(Path::new("movie.en.sdh.srt")),
+            Variant::Sdh
+        );
+        assert_eq!(
+            Variant::from_path(Path::new("movie.hi.srt")),
+            Variant::Normal
+        );
+    }
+
+    #[test]
+    fn test_gap_merge() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 500, "Never"),
+                cue(2, 550, 1000, "gonna"),
+                cue(3, 1050, 1500, "give"),
+                cue(4, 3000, 4000, "you up"),
+            ],
+        };
+        let opts = TrackOptions {
+            gap_merge: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let cues: Vec<(u32, String, Duration)> = srt
+            .subtitles
+            .iter()
+            .map(|s| (s.sequence, s.text.join("\n"), s.end.into()))
+            .collect();
+        assert_eq!(
+            cues,
+            [
+                (
+                    1,
+                    "Never gonna give".to_owned(),
+                    Duration::from_millis(1500)
+                ),
+                (2, "you up".to_owned(), Duration::from_millis(4000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_min_duration() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 100, "flash"),
+                cue(2, 400, 2000, "long enough"),
+                cue(3, 3000, 3200, "last"),
+            ],
+        };
+        let opts = TrackOptions {
+            min_duration: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let ends: Vec<Duration> = srt.subtitles.iter().map(|s| s.end.into()).collect();
+        assert_eq!(
+            ends,
+            [
+                Duration::from_millis(400),
+                Duration::from_millis(2000),
+                Duration::from_millis(4000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_color_speakers() {
+        let mut srt = SubRip {
+            subtitles: vec![
+                cue(1, 0, 1000, "JOHN: Hello."),
+                cue(2, 1000, 2000, "- MARY: Hi!"),
+                cue(3, 2000, 3000, "No label here"),
+                cue(4, 3000, 4000, "JOHN: Bye."),
+            ],
+        };
+        let opts = TrackOptions {
+            color_speakers: true,
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+
+        let (john, mary) = (SPEAKER_PALETTE[0], SPEAKER_PALETTE[1]);
+        let texts: Vec<&str> = srt.subtitles.iter().map(|s| s.text[0].as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                format!("<font color=\"{john}\">JOHN: Hello.</font>").as_str(),
+                format!("<font color=\"{mary}\">- MARY: Hi!</font>").as_str(),
+                "No label here",
+                format!("<font color=\"{john}\">JOHN: Bye.</font>").as_str(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_renumber_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scrambled.en.srt");
+        let srt = "7\n00:00:01,000 --> 00:00:02,000\na\n\n3\n00:00:03,000 --> 00:00:04,000\nb\n\n3\n00:00:05,000 --> 00:00:06,000\nc\n";
+        fs::write(&path, srt).unwrap();
+
+        let sequences = |renumber| {
+            let opts = LoadOptions {
+                renumber,
+                ..Default::default()
+            };
+            load_sub(&path, &opts)
+                .unwrap()
+                .subtitles
+                .iter()
+                .map(|s| s.sequence)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sequences(false), [7, 3, 3]);
+        assert_eq!(sequences(true), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_normalize_unicode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.vi.srt");
+        // "Việt" with the diacritics as combining characters
+        let srt = "1\n00:00:01,000 --> 00:00:02,000\nVie\u{0323}\u{0302}t\n";
+        fs::write(&path, srt).unwrap();
+
+        let text = |normalize_unicode| {
+            let opts = LoadOptions {
+                normalize_unicode,
+                ..Default::default()
+            };
+            load_sub(&path, &opts).unwrap().subtitles[0].text[0].clone()
+        };
+
+        assert_eq!(text(false).chars().count(), 6);
+        assert_eq!(text(true), "Vi\u{1ec7}t");
+    }
+
+    #[test]
+    fn test_fix_overlaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.srt");
+        let srt = "1\n00:00:01,000 --> 00:00:04,000\nFirst\n\n\
+                   2\n00:00:03,000 --> 00:00:05,000\nSecond\n";
+        fs::write(&path, srt).unwrap();
+
+        let ends = |fix_overlaps| {
+            let opts = LoadOptions {
+                fix_overlaps,
+                ..Default::default()
+            };
+            let srt = load_sub(&path, &opts).unwrap();
+            srt.subtitles
+                .iter()
+                .map(|s| s.end.into())
+                .collect::<Vec<Duration>>()
+        };
+
+        assert_eq!(
+            ends(false),
+            [Duration::from_secs(4), Duration::from_secs(5)]
+        );
+        assert_eq!(ends(true), [Duration::from_secs(3), Duration::from_secs(5)]);
+    }
+
+    #[test]
+    fn test_strip_bom() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.srt");
+        fs::write(&path, "\u{feff}1\n00:00:01,000 --> 00:00:02,000\nHello\n").unwrap();
+
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(srt.subtitles[0].sequence, 1);
+        assert_eq!(srt.subtitles[0].text, ["Hello"]);
+    }
+
+    #[test]
+    fn test_decode_windows_1252() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.fr.srt");
+        let text = "Ça va très bien, où est la crème brûlée du café?";
+        let mut srt = b"1\n00:00:01,000 --> 00:00:02,000\n".to_vec();
+        // Windows-1252 encodes these characters as their Unicode code points, in a single byte
+        srt.extend(text.chars().map(|c| c as u8));
+        srt.push(b'\n');
+        fs::write(&path, srt).unwrap();
+
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(srt.subtitles[0].text, [text]);
+    }
+
+    #[cfg(feature = "ffprobe")]
+    #[test]
+    fn test_check_against_media_rescales() {
+        use crate::media::check_against_media;
+
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 10_000, 20_000, "a"), cue(2, 100_000, 125_000, "b")],
+        };
+        let name = Path::new("movie.en.srt");
+
+        check_against_media(&mut srt, name, Duration::from_secs(124), true);
+        assert_eq!(srt.subtitles[1].end, Duration::from_secs(125).into());
+
+        check_against_media(&mut srt, name, Duration::from_secs(100), true);
+        assert_eq!(srt.subtitles[0].start, Duration::from_secs(8).into());
+        assert_eq!(srt.subtitles[1].end, Duration::from_secs(100).into());
+    }
+
+    #[cfg(feature = "ffprobe")]
+    #[test]
+    fn test_audio_language_codes() {
+        use crate::media::{file_language, same_language};
+
+        assert!(same_language("ja", "jpn"));
+        assert!(same_language("ger", "deu"));
+        assert!(same_language("EN", "eng"));
+        assert!(!same_language("en", "jpn"));
+        assert_eq!(file_language(Path::new("dir/movie.ja.hi.srt")), Some("ja"));
+        assert_eq!(file_language(Path::new("movie.srt")), None);
+        assert_eq!(
+            file_language(Path::new("The.Matrix.1999.ja.srt")),
+            Some("ja")
+        );
+        assert_eq!(
+            file_language(Path::new("The.Matrix.1999.en.sdh.part2.srt.gz")),
+            Some("en")
+        );
+    }
+
+    #[cfg(feature = "mkv")]
+    #[test]
+    fn test_mkv_extraction_format() {
+        use crate::mkv::extraction_format;
+
+        assert_eq!(extraction_format("ass").unwrap(), ("copy", "ass", "ass"));
+        assert_eq!(extraction_format("subrip").unwrap(), ("srt", "srt", "srt"));
+        // MP4 text subtitles are converted
+        assert_eq!(extraction_format("mov_text").unwrap().2, "srt");
+        assert!(extraction_format("hdmv_pgs_subtitle").is_err());
+    }
+
+    #[test]
+    fn test_dot_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.srt");
+        let text = "1\n00:00:01.000 --> 00:00:02.500\nMr. Smith?\n\n\
+                    2\n00:00:03,000 --> 00:00:04.250\nSee you at 00:00:05.000.\n";
+        fs::write(&path, text).unwrap();
+        assert!(SubRip::parse(text).is_err());
+
+        let expected = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2500, "Mr. Smith?"),
+                cue(2, 3000, 4250, "See you at 00:00:05.000."),
+            ],
+        };
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(srt, expected);
+        let streamed: Vec<_> = stream_sub(&path, &LoadOptions::default())
+            .unwrap()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert_eq!(streamed, expected.subtitles);
+    }
+
+    #[test]
+    fn test_unsupported_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.txt");
+        fs::write(&path, "1\n00:00:01,000 --> 00:00:02,000\nhello\n").unwrap();
+
+        let e = load_sub(&path, &LoadOptions::default()).unwrap_err();
+        let msg = e.to_string();
+        assert!(msg.contains(&format!("{:?}", path)), "{msg}");
+        assert!(msg.contains("\"txt\""), "{msg}");
+        assert!(msg.contains("srt, vtt, ass, ssa"), "{msg}");
+        assert_eq!(e.downcast::<UnsupportedFormat>().unwrap().ext, "txt");
+
+        let path = dir.path().join("movie");
+        fs::write(&path, "").unwrap();
+        let e = load_sub(&path, &LoadOptions::default()).unwrap_err();
+        assert!(e.to_string().contains("has no extension"), "{e}");
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn test_load_url() {
+        use crate::remote::url_format;
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        assert_eq!(
+            url_format("https://example.com/movie.en.SRT?dl=1", None),
+            Some("srt")
+        );
+        let vtt = Some("text/vtt; charset=utf-8");
+        assert_eq!(
+            url_format("https://paste.example.com/raw/x1y2", vtt),
+            Some("vtt")
+        );
+        assert_eq!(
+            url_format("https://paste.example.com/raw/x1y2", Some("text/plain")),
+            None
+        );
+
+        // Serve a subtitle file, and nothing else, for two requests
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                reader.read_line(&mut request).unwrap();
+                while reader.read_line(&mut String::new()).unwrap() > 2 {}
+
+                let body = "1\n00:00:01,000 --> 00:00:02,000\nhello\n";
+                let response = match request.starts_with("GET /movie.en.srt ") {
+                    true => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    ),
+                    false => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned(),
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let url = format!("http://{addr}/movie.en.srt");
+        let srt = load_sub(Path::new(&url), &LoadOptions::default()).unwrap();
+        assert_eq!(srt.subtitles, [cue(1, 1000, 2000, "hello")]);
+
+        let url = format!("http://{addr}/missing.srt");
+        let e = load_sub(Path::new(&url), &LoadOptions::default()).unwrap_err();
+        assert!(e.to_string().contains("404"), "{e}");
+        server.join().unwrap();
+    }
+
+    #[cfg(not(feature = "url"))]
+    #[test]
+    fn test_url_needs_feature() {
+        let url = Path::new("https://example.com/movie.en.srt");
+        let e = load_sub(url, &LoadOptions::default()).unwrap_err();
+        assert!(e.to_string().contains("`url` feature"), "{e}");
+    }
+
+    #[cfg(not(feature = "mkv"))]
+    #[test]
+    fn test_mkv_needs_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mkv");
+        fs::write(&path, "").unwrap();
+
+        let e = load_sub(&path, &LoadOptions::default()).unwrap_err();
+        assert!(e.to_string().contains("`mkv` feature"), "{e}");
+    }
+
+    #[cfg(feature = "video")]
+    #[test]
+    fn test_contrast_regions() {
+        use crate::contrast::{contrasting_color, region_luma};
+
+        // A frame with a bright top third and a dark rest, like a sky
+        let mut frame = vec![20u8; 64 * 36];
+        frame[..64 * 12].fill(230);
+
+        assert_eq!(region_luma(&frame, SubPosition::TopCenter), 230.0);
+        assert_eq!(region_luma(&frame, SubPosition::BottomLeft), 20.0);
+        assert_eq!(contrasting_color(230.0), "#202020");
+        assert_eq!(contrasting_color(20.0), "#ffffff");
+    }
+
+    #[cfg(feature = "preview")]
+    #[test]
+    fn test_preview_render_styling() {
+        use crate::render::style_line;
+
+        let yellow = [0xff, 0xff, 0x00];
+        let white = [0xff, 0xff, 0xff];
+        let line = style_line("{\\an8}<font color=\"#ffff00\">a<i>b</i></font>c");
+        assert_eq!(line, [('a', yellow), ('b', yellow), ('c', white)]);
+    }
+
+    #[test]
+    fn test_output_extension() {
+        let template = "{lang1}-{lang2}.srt";
+        assert_eq!(output_extension(template, "en", "ja"), "en-ja.srt");
+        assert_eq!(output_extension(template, "ja", "en"), "ja-en.srt");
+        assert_eq!(output_extension("merged.srt", "en", "ja"), "merged.srt");
+    }
+
+    #[test]
+    fn test_output_overwriting_input_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let en = dir.path().join("movie.en.srt");
+        let ja = dir.path().join("movie.ja.srt");
+        fs::write(&en, "").unwrap();
+        fs::write(&ja, "").unwrap();
+        let sub = |path: &PathBuf, lang: &str| SubFile {
+            path: path.clone(),
+            lang: lang.to_owned(),
+            code: lang.to_owned(),
+            variant: Variant::Normal,
+            parts: Vec::new(),
+            detected: false,
+            stem: PathBuf::from("movie"),
+        };
+        let (s1, s2) = (sub(&en, "en"), sub(&ja, "ja"));
+
+        // `--out-ext {lang1}.srt` names the output after the first input
+        let stem = dir.path().join("movie");
+        let out = stem.with_extension(output_extension("{lang1}.srt", "en", "ja"));
+        assert!(overwrites_input(&out, &[&s1, &s2]));
+        let dotted = dir.path().join(".").join("movie.ja.srt");
+        assert!(overwrites_input(&dotted, &[&s1, &s2]));
+        assert!(!overwrites_input(
+            &stem.with_extension("en-ja.srt"),
+            &[&s1, &s2]
+        ));
+
+        fs::write(stem.with_extension("merged.srt"), "").unwrap();
+        assert!(!overwrites_input(
+            &stem.with_extension("merged.srt"),
+            &[&s1, &s2]
+        ));
+    }
+
+    #[test]
+    fn test_max_input_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("huge.en.srt");
+        fs::write(&path, "1\n00:00:01,000 --> 00:00:02,000\nsome text\n").unwrap();
+
+        let opts = LoadOptions {
+            max_bytes: 10,
+            ..Default::default()
+        };
+        let err = load_sub(&path, &opts).unwrap_err();
+        assert!(err.is::<InputTooLarge>());
+        assert!(load_sub(&path, &LoadOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_warning_from_record() {
+        let kvs = [("reason", "span_mismatch"), ("path", "show/ep1.ja.srt")];
+        let warning = Warning::from_record(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("spans differ"))
+                .key_values(&kvs)
+                .build(),
+        );
+        assert_eq!(warning.reason, "span_mismatch");
+        assert_eq!(warning.path.as_deref(), Some("show/ep1.ja.srt"));
+        assert_eq!(warning.message, "spans differ");
+
+        let warning = Warning::from_record(&log::Record::builder().build());
+        assert_eq!(warning.reason, "unspecified");
+        assert_eq!(warning.path, None);
+    }
+
+    #[test]
+    fn test_snap_fps() {
+        let snapped = |fps, ms| {
+            let mut srt = SubRip {
+                subtitles: vec![cue(1, ms, ms, "a")],
+            };
+            let opts = TrackOptions {
+                snap_fps: Some(fps),
+                ..Default::default()
+            };
+            apply_sub_changes(&mut srt, &opts);
+            Into::<Duration>::into(srt.subtitles[0].start).as_millis()
+        };
+
+        assert_eq!(snapped(24.0, 1000), 1000);
+        assert_eq!(snapped(24.0, 1030), 1042);
+        assert_eq!(snapped(23.976, 10_000), 10_010);
+        assert_eq!(snapped(25.0, 19), 0);
+        assert_eq!(snapped(25.0, 21), 40);
+        assert_eq!(snapped(29.97, 3_600_000), 3_600_000);
+        assert_eq!(snapped(29.97, 3_600_020), 3_600_033);
+    }
+
+    #[test]
+    fn test_position_counts() {
+        let mut srt1 = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "a"), cue(2, 1000, 2000, "b")],
+        };
+        let mut srt2 = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "c")],
+        };
+        let bottom = TrackOptions {
+            position: Some(SubPosition::BottomCenter),
+            ..Default::default()
+        };
+        let top = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt1, &bottom);
+        apply_sub_changes(&mut srt2, &top);
+        let mut merged = merge(srt1, srt2, &MergeOptions::default());
+        merged.subtitles.push(cue(4, 3000, 4000, "untagged"));
+
+        let counts = position_counts(&merged);
+        assert_eq!(counts[&Some(SubPosition::BottomCenter)], 2);
+        assert_eq!(counts[&Some(SubPosition::TopCenter)], 1);
+        assert_eq!(counts[&None], 1);
+        assert_eq!(counts.len(), 3);
+
+        assert_eq!(
+            "{\\an8}".parse::<SubPosition>().unwrap(),
+            SubPosition::TopCenter
+        );
+        assert!("{\\an0}".parse::<SubPosition>().is_err());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("movie.srt");
+        let srt = SubRip {
+            subtitles: vec![SrtSubtitle {
+                text: vec!["windows\r".to_owned(), "old mac\rline".to_owned()],
+                ..cue(1, 0, 1000, "")
+            }],
+        };
+        let write = |line_endings| {
+            let opts = OutputOptions {
+                line_endings,
+                ..Default::default()
+            };
+            write_sub(&out, &srt, &opts).unwrap();
+            fs::read_to_string(&out).unwrap()
+        };
+
+        let lf = write(LineEndings::Lf);
+        assert!(!lf.contains('\r'));
+        assert!(lf.contains("windows\nold mac\nline\n"));
+
+        let crlf = write(LineEndings::Crlf);
+        assert_eq!(crlf.matches('\n').count(), crlf.matches("\r\n").count());
+        assert!(!crlf.contains("\r\r"));
+    }
+
+    #[test]
+    fn test_streaming_matches_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let (en, ja) = (
+            dir.path().join("movie.en.srt"),
+            dir.path().join("movie.ja.srt"),
+        );
+        fs::write(&en, "\u{feff}1\r\n00:00:01,000 --> 00:00:03,000\r\nHello\r\n\r\n2\r\n00:00:05,000 --> 00:00:06,000\r\nBye\r\n").unwrap();
+        fs::write(&ja, "1\n00:00:02,000 --> 00:00:04,000\nこんにちは\n\n\n2\n00:00:05,000 --> 00:00:07,000\nさようなら\n\n").unwrap();
+        let ja_opts = TrackOptions {
+            position: Some(SubPosition::TopCenter),
+            offset: Some(0.5),
+            ..Default::default()
+        };
+
+        let mut srt = load_sub(&ja, &LoadOptions::default()).unwrap();
+        apply_sub_changes(&mut srt, &ja_opts);
+        let merged = merge(
+            load_sub(&en, &LoadOptions::default()).unwrap(),
+            srt,
+            &Default::default(),
+        );
+        let expected = dir.path().join("expected.srt");
+        write_sub(&expected, &merged, &OutputOptions::default()).unwrap();
+
+        let out = dir.path().join("movie.srt");
+        let tracks = vec![
+            (
+                stream_sub(&en, &LoadOptions::default()).unwrap(),
+                TrackOptions::default(),
+            ),
+            (stream_sub(&ja, &LoadOptions::default()).unwrap(), ja_opts),
+        ];
+        let written = merge_streams(tracks, &out, &OutputOptions::default()).unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            fs::read_to_string(&expected).unwrap()
+        );
+
+        // Cues out of order can not be merged one at a time
+        fs::write(
+            &ja,
+            "1\n00:00:05,000 --> 00:00:06,000\nB\n\n2\n00:00:01,000 --> 00:00:02,000\nA\n",
+        )
+        .unwrap();
+        let tracks = vec![(
+            stream_sub(&ja, &LoadOptions::default()).unwrap(),
+            TrackOptions::default(),
+        )];
+        assert!(merge_streams(tracks, &out, &OutputOptions::default()).is_err());
+
+        // The shift of an anchor depends on the cue it names, not on each cue
+        let tracks = vec![(
+            stream_sub(&en, &LoadOptions::default()).unwrap(),
+            TrackOptions {
+                anchor: Some(Anchor {
+                    cue: 2,
+                    at: Duration::from_secs(10),
+                }),
+                ..Default::default()
+            },
+        )];
+        let err = merge_streams(tracks, &out, &OutputOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("--sub2-anchor"));
+    }
+
+    #[test]
+    fn test_write_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("movie.srt");
+        let srt = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "new")],
+        };
+        let opts = |write_mode| OutputOptions {
+            write_mode,
+            ..Default::default()
+        };
+
+        fs::write(&out, "old").unwrap();
+        let err = write_sub(&out, &srt, &opts(WriteMode::NewOnly)).unwrap_err();
+        assert!(err.is::<OutputExists>());
+        assert_eq!(fs::read_to_string(&out).unwrap(), "old");
+
+        write_sub(&out, &srt, &opts(WriteMode::Truncate)).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), srt.render());
+
+        fs::write(&out, "old").unwrap();
+        write_sub(&out, &srt, &opts(WriteMode::Atomic)).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), srt.render());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        fs::remove_file(&out).unwrap();
+        write_sub(&out, &srt, &opts(WriteMode::NewOnly)).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), srt.render());
+    }
+
+    const ASS: &str = "[Script Info]
+Title: Test
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize
+Style: Default,Arial,20
+Style: Signs,Arial,20
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Dialogue: 0,0:00:01.50,0:00:03.00,Default,,0,0,0,,Hello, world!\\NSecond line
+Dialogue: 0,0:00:02.00,0:00:04.00,Signs,,0,0,0,,{\\pos(10,10)}SHOP
+Comment: 0,0:00:05.00,0:00:06.00,Default,,0,0,0,,not shown
+Dialogue: 0,1:00:00.00,1:00:01.25,Default,,0,0,0,,Bye
+";
+
+    #[test]
+    fn test_ass_style_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.ass");
+        fs::write(&path, ASS).unwrap();
+
+        let all = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(all.subtitles.len(), 3);
+        assert_eq!(all.subtitles[0].text, ["Hello, world!", "Second line"]);
+        assert_eq!(all.subtitles[0].start, Duration::from_millis(1500).into());
+        assert_eq!(all.subtitles[1].text, ["{\\pos(10,10)}SHOP"]);
+        assert_eq!(
+            all.subtitles[2].end,
+            Duration::from_millis(3_601_250).into()
+        );
+
+        let opts = LoadOptions {
+            style_filter: Some("Default".to_owned()),
+            ..Default::default()
+        };
+        let dialogue = load_sub(&path, &opts).unwrap();
+        let texts: Vec<&str> = dialogue
+            .subtitles
+            .iter()
+            .map(|s| s.text[0].as_str())
+            .collect();
+        assert_eq!(texts, ["Hello, world!", "Bye"]);
+    }
+
+    #[test]
+    fn test_strip_karaoke() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("op.ja.ass");
+        let ass = ASS.replace(
+            "{\\pos(10,10)}SHOP",
+            "{\\k20}Ka{\\kf35}ra{\\K10\\i1}o{\\ko5}ke",
+        );
+        fs::write(&path, ass).unwrap();
+
+        let opts = LoadOptions {
+            strip_karaoke: true,
+            ..Default::default()
+        };
+        let srt = load_sub(&path, &opts).unwrap();
+        assert_eq!(srt.subtitles[1].text, ["Kara{\\i1}oke"]);
+        assert_eq!(srt.subtitles[0].text, ["Hello, world!", "Second line"]);
+    }
+
+    #[test]
+    fn test_preserve_style() {
+        let dir = tempfile::tempdir().unwrap();
+        let (en, ja) = (
+            dir.path().join("movie.en.ass"),
+            dir.path().join("movie.ja.ass"),
+        );
+        fs::write(&en, ASS).unwrap();
+        let other = ASS
+            .replace("Default,Arial,20", "Default,Noto Sans CJK,16")
+            .replace("Signs,Arial,20", "Signs,Arial,20\nStyle: Top,Arial,12")
+            .replace("Hello, world!\\NSecond line", "{\\an8}こんにちは")
+            .replace(
+                "Dialogue: 0,0:00:02.00,0:00:04.00,Signs,,0,0,0,,",
+                "Dialogue: 1,0:00:02.00,0:00:04.00,Top,Hiro,0,0,5,,",
+            );
+        fs::write(&ja, other).unwrap();
+
+        let mut ja = load_ass(&ja, &LoadOptions::default()).unwrap();
+        let opts = TrackOptions {
+            offset: Some(1.0),
+            ..Default::default()
+        };
+        apply_ass_changes(&mut ja, &opts).unwrap();
+        let merged = merge_ssa(load_ass(&en, &LoadOptions::default()).unwrap(), ja).unwrap();
+
+        // Identical styles are shared, clashing ones are renamed
+        let styles: Vec<&str> = merged.styles.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(styles, ["Default", "Signs", "Default_2", "Top"]);
+        assert_eq!(
+            merged.styles[2].fields,
+            ["Default_2", "Noto Sans CJK", "16"]
+        );
+
+        let rendered = merged.render();
+        assert!(rendered.starts_with("[Script Info]\nTitle: Test\n\n[V4+ Styles]\n"));
+        let events: Vec<&str> = rendered
+            .lines()
+            .filter(|l| l.starts_with("Dialogue:"))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "Dialogue: 0,0:00:01.50,0:00:03.00,Default,,0,0,0,,Hello, world!\\NSecond line",
+                "Dialogue: 0,0:00:02.00,0:00:04.00,Signs,,0,0,0,,{\\pos(10,10)}SHOP",
+                "Dialogue: 0,0:00:02.50,0:00:04.00,Default_2,,0,0,0,,{\\an8}こんにちは",
+                "Dialogue: 1,0:00:03.00,0:00:05.00,Top,Hiro,0,0,5,,{\\pos(10,10)}SHOP",
+                "Dialogue: 0,1:00:00.00,1:00:01.25,Default,,0,0,0,,Bye",
+                "Dialogue: 0,1:00:01.00,1:00:02.25,Default_2,,0,0,0,,Bye",
+            ]
+        );
+
+        // Styling as SRT would be lost on the events
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            ..Default::default()
+        };
+        assert!(apply_ass_changes(&mut merged.clone(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_sami_output() {
+        let srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 3000, "bottom"),
+                cue(2, 2000, 3000, "{\\an8} <font color=\"#ffff00\">top</font>"),
+                cue(3, 4000, 5000, "later"),
+            ],
+        };
+        let sami = crate::sami::render(&srt);
+        let syncs: Vec<&str> = sami.lines().filter(|l| l.starts_with("<SYNC")).collect();
+
+        assert_eq!(
+            syncs,
+            [
+                "<SYNC Start=1000><P Class=SUBCC>bottom",
+                "<SYNC Start=2000><P Class=SUBCC><font color=\"#ffff00\">top</font><br>bottom",
+                "<SYNC Start=3000><P Class=SUBCC>&nbsp;",
+                "<SYNC Start=4000><P Class=SUBCC>later",
+                "<SYNC Start=5000><P Class=SUBCC>&nbsp;",
+            ]
+        );
+        assert!(sami.starts_with("<SAMI>") && sami.ends_with("</SAMI>\n"));
+    }
+
+    #[test]
+    fn test_ass_output() {
+        let srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "<i>bottom</i>"),
+                SrtSubtitle {
+                    text: vec![
+                        "{\\an8} <font color=\"#fbf1c7\">top</font>".to_owned(),
+                        "{\\an8} <font color=\"#f00\">red</font> and plain".to_owned(),
+                        "{\\an8} plain".to_owned(),
+                    ],
+                    ..cue(2, 1500, 3_723_450, "")
+                },
+            ],
+        };
+        let ass = crate::ass::render(&srt);
+
+        let styles: Vec<&str> = ass.lines().filter(|l| l.starts_with("Style:")).collect();
+        assert_eq!(styles.len(), 2);
+        assert!(styles[0].starts_with("Style: Default,Arial,16,&HFFFFFF,"));
+        assert!(styles[0].ends_with(",2,10,10,10,1"));
+        assert!(styles[1].starts_with("Style: an8_fbf1c7,Arial,16,&HC7F1FB,"));
+        assert!(styles[1].ends_with(",8,10,10,10,1"));
+
+        let events: Vec<&str> = ass.lines().filter(|l| l.starts_with("Dialogue:")).collect();
+        assert_eq!(
+            events,
+            [
+                "Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}bottom{\\i0}",
+                "Dialogue: 0,0:00:01.50,1:02:03.45,an8_fbf1c7,,0,0,0,,\
+                 top\\N{\\c&H0000FF&}red{\\c} and plain\\N{\\c&HFFFFFF&}plain{\\c}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ass_named_colors() {
+        let srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "<font color=\"gold\">style</font>"),
+                cue(2, 3000, 4000, "a <font color=\"teal\">word</font>"),
+                cue(3, 5000, 6000, "<font color=\"nocolor\">unknown</font>"),
+            ],
+        };
+        let ass = crate::ass::render(&srt);
+        assert!(ass.contains("Style: an2_gold,Arial,16,&H00D7FF,"));
+        assert!(ass.contains(",an2_gold,,0,0,0,,style\n"));
+        assert!(ass.contains(",Default,,0,0,0,,a {\\c&H808000&}word{\\c}\n"));
+        // An unknown color was never overridden, so there is nothing to reset
+        assert!(ass.contains(",Default,,0,0,0,,unknown\n"));
+    }
+
+    #[test]
+    fn test_box_color() {
+        let srt1 = SubRip {
+            subtitles: vec![cue(1, 1000, 2000, "plain")],
+        };
+        let mut srt2 = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "boxed"),
+                cue(2, 3000, 4000, "two\nlines"),
+            ],
+        };
+        // Named colors are boxed in their color too
+        let opts = TrackOptions {
+            box_color: Some("navy".to_owned()),
+            ..builtin_preset("boxed-bottom").unwrap()
+        };
+        apply_sub_changes(&mut srt2, &opts);
+        assert_eq!(
+            srt2.subtitles[0].text[0],
+            "{\\an2} {\\bord0\\shad0\\3c&H800000&\\4c&H800000&}<font color=\"#ffffff\">boxed</font>"
+        );
+        let merged = merge(srt1, srt2, &MergeOptions::default());
+
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, format| {
+            let out = dir.path().join(name);
+            let opts = OutputOptions {
+                format,
+                ..Default::default()
+            };
+            write_sub(&out, &merged, &opts).unwrap();
+            fs::read_to_string(out).unwrap()
+        };
+
+        let ass = write("out.ass", crate::OutputFormat::Ass);
+        let boxed = ass
+            .lines()
+            .find(|l| l.starts_with("Style: an2_ffffff_box800000,"))
+            .unwrap();
+        let fields: Vec<&str> = boxed.split(',').collect();
+        // OutlineColour, BackColour and BorderStyle
+        assert_eq!(
+            (fields[5], fields[6], fields[15]),
+            ("&H800000", "&H800000", "3")
+        );
+        let plain = ass
+            .lines()
+            .find(|l| l.starts_with("Style: Default,"))
+            .unwrap();
+        assert_eq!(plain.split(',').nth(15), Some("1"));
+        let events: Vec<&str> = ass.lines().filter(|l| l.starts_with("Dialogue:")).collect();
+        assert!(events[1].ends_with(
+            ",an2_ffffff_box800000,,0,0,0,,{\\bord0\\shad0\\3c&H800000&\\4c&H800000&}boxed"
+        ));
+
+        // Formats unable to show boxes get no box tags
+        let srt = write("out.srt", crate::OutputFormat::Srt);
+        assert!(!srt.contains("bord0"));
+        assert!(srt.contains("{\\an2} <font color=\"#ffffff\">boxed</font>"));
+        assert!(!write("out.vtt", crate::OutputFormat::Vtt).contains("bord0"));
+    }
+
+    #[test]
+    fn test_font_size() {
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 1000, 2000, "<i>small</i>")],
+        };
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            position: Some(SubPosition::TopCenter),
+            size: Some(12),
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+        assert_eq!(
+            srt.subtitles[0].text,
+            ["{\\an8} <i><font color=\"#fbf1c7\"><font size=\"12\">small</font></font></i>"]
+        );
+
+        let ass = crate::ass::render(&srt);
+        assert!(ass.contains(",an8_fbf1c7,,0,0,0,,{\\i1}{\\fs12}small{\\fs}{\\i0}"));
+        let vtt = crate::vtt::render(&srt);
+        assert!(vtt.contains("<i><c.color_fbf1c7><c.size_12>small</c></c></i>"));
+        assert!(vtt.contains("::cue(.size_12) { font-size: 12px; }"));
+    }
+
+    #[test]
+    fn test_bold_italic() {
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 1000, 2000, "emphasis")],
+        };
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            size: Some(12),
+            bold: true,
+            italic: true,
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+        assert_eq!(
+            srt.subtitles[0].text,
+            ["<font color=\"#fbf1c7\"><font size=\"12\"><b><i>emphasis</i></b></font></font>"]
+        );
+
+        let ass = crate::ass::render(&srt);
+        assert!(ass.contains(",an2_fbf1c7,,0,0,0,,{\\fs12}{\\b1}{\\i1}emphasis{\\i0}{\\b0}{\\fs}"));
+
+        // Only italic, inside the color
+        let mut srt = SubRip {
+            subtitles: vec![cue(1, 1000, 2000, "{\\an8} aside")],
+        };
+        let opts = TrackOptions {
+            color: Some("#fbf1c7".to_owned()),
+            italic: true,
+            ..Default::default()
+        };
+        apply_sub_changes(&mut srt, &opts);
+        assert_eq!(
+            srt.subtitles[0].text,
+            ["{\\an8} <font color=\"#fbf1c7\"><i>aside</i></font>"]
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("submerger.toml");
+        fs::write(
+            &path,
+            r##"
+out-format = "ass"
+
+[sub1]
+color = "white"
+
+[sub2]
+color = "#fabd2f"
+position = "top-center"
+offset = "-0:01.500"
+size = 18
+"##,
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.out_format, Some(crate::OutputFormat::Ass));
+        assert_eq!(config.sub1.color.as_deref(), Some("white"));
+        assert_eq!(config.sub1.position, None);
+        assert_eq!(config.sub2.offset, Some(-1.5));
+
+        // Options given on the command line take precedence
+        let cli = TrackOptions {
+            color: Some("#ffffff".to_owned()),
+            ..Default::default()
+        };
+        let opts = cli.or(config.sub2.options());
+        assert_eq!(opts.color.as_deref(), Some("#ffffff"));
+        assert_eq!(opts.position, Some(SubPosition::TopCenter));
+        assert_eq!(opts.size, Some(18));
+
+        // Typos and invalid values are errors rather than silently ignored
+        for invalid in [
+            "[sub2]\ncolour = \"red\"",
+            "[sub2]\nposition = \"top\"",
+            "[sub2]\ncolor = \"#ff\"",
+            "[sub3]",
+        ] {
+            fs::write(&path, invalid).unwrap();
+            assert!(load_config(&path).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_config_presets() {
+        let config: crate::Config = toml::from_str(
+            r##"
+[preset.subdued]
+color = "#928374"
+position = "top-left"
+box-color = "#282828"
+
+[preset.dim-top]
+color = "#606060"
+"##,
+        )
+        .unwrap();
+        let subdued = config.preset("subdued").unwrap();
+        assert_eq!(subdued.color.as_deref(), Some("#928374"));
+        assert_eq!(subdued.position, Some(SubPosition::TopLeft));
+        assert_eq!(subdued.box_color.as_deref(), Some("#282828"));
+        // Presets of the file replace built-in ones of the same name
+        let dim = config.preset("dim-top").unwrap();
+        assert_eq!(
+            (dim.color.as_deref(), dim.position),
+            (Some("#606060"), None)
+        );
+        let yellow = config.preset("yellow-top").unwrap();
+        assert_eq!(yellow.color.as_deref(), Some("#ffff00"));
+        assert!(config.preset("missing").is_none());
+        assert_eq!(
+            config.preset_names(),
+            [
+                "dim-bottom",
+                "yellow-top",
+                "boxed-bottom",
+                "dim-top",
+                "subdued"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_lang() {
+        let config: crate::Config = toml::from_str(
+            r##"
+[sub2]
+color = "#fabd2f"
+position = "top-center"
+
+[lang.ja]
+color = "#83a598"
+size = 20
+"##,
+        )
+        .unwrap();
+
+        // The options of the language take precedence over those of the track
+        let ja = config.sub2_options(Some("ja"));
+        assert_eq!(ja.color.as_deref(), Some("#83a598"));
+        assert_eq!(ja.position, Some(SubPosition::TopCenter));
+        assert_eq!(ja.size, Some(20));
+        let ja_jp = config.sub2_options(Some("ja-JP"));
+        assert_eq!(ja_jp.color, ja.color);
+
+        // Unknown or missing languages only get those of the track
+        for lang in [Some("en"), None] {
+            let opts = config.sub2_options(lang);
+            assert_eq!(opts.color.as_deref(), Some("#fabd2f"));
+            assert_eq!(opts.size, None);
+        }
+        assert_eq!(config.sub1_options(Some("ja")).size, Some(20));
+    }
+
+    #[test]
+    fn test_out_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.srt");
+        let srt = SubRip {
+            subtitles: vec![cue(1, 0, 1000, "Café"), cue(2, 1000, 2000, "こんにちは")],
+        };
+
+        let opts = OutputOptions {
+            encoding: OutputEncoding::Utf16le,
+            ..Default::default()
+        };
+        write_sub(&path, &srt, &opts).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes[..2], [0xff, 0xfe]);
+        let (text, had_errors) = encoding_rs::UTF_16LE.decode_without_bom_handling(&bytes[2..]);
+        assert!(!had_errors);
+        assert_eq!(text, srt.render());
+
+        let (bytes, unmappable) = OutputEncoding::Windows1252.encode("Café こんにちは");
+        assert_eq!(bytes, b"Caf\xe9 ?????");
+        assert_eq!(unmappable, 5);
+        assert!(OutputEncoding::Windows1252.bom().is_empty());
+
+        // WebVTT files must be UTF-8
+        let vtt = dir.path().join("merged.vtt");
+        let opts = OutputOptions {
+            format: crate::OutputFormat::Vtt,
+            encoding: OutputEncoding::Utf16le,
+            ..Default::default()
+        };
+        let err = write_sub(&vtt, &srt, &opts).unwrap_err();
+        assert!(err.to_string().contains("utf-16le"), "{err}");
+        assert!(!vtt.exists());
+    }
+
+    #[test]
+    fn test_vtt_positions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.en.vtt");
+        fs::write(
+            &path,
+            "WEBVTT\n\n\
+             00:00:01.000 --> 00:00:02.000 line:0 align:left\nsign\n\n\
+             00:00:02.000 --> 00:00:03.000 line:50% position:90%\nthought\n\n\
+             00:00:03.000 --> 00:00:04.000 line:-1\nplain\n\n\
+             00:00:04.000 --> 00:00:05.000 line:85% align:end\ntwo\nlines\n",
+        )
+        .unwrap();
+
+        let opts = LoadOptions {
+            vtt_positions: true,
+            ..Default::default()
+        };
+        let texts = |srt: SubRip| -> Vec<Vec<String>> {
+            srt.subtitles.into_iter().map(|s| s.text).collect()
+        };
+        assert_eq!(
+            texts(load_sub(&path, &opts).unwrap()),
+            [
+                vec!["{\\an7} sign"],
+                vec!["{\\an6} thought"],
+                vec!["plain"],
+                vec!["{\\an3} two", "{\\an3} lines"],
+            ]
+        );
+        // Without the option, the settings are dropped
+        let srt = load_sub(&path, &LoadOptions::default()).unwrap();
+        assert_eq!(texts(srt)[0], ["sign"]);
+
+        // Positions written to VTT are read back as they were
+        let positioned: Vec<_> = <SubPosition as clap::ValueEnum>::value_variants()
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                cue(
+                    i as u32 + 1,
+                    i as u64 * 1000,
+                    i as u64 * 1000 + 500,
+                    &format!("{p} x"),
+                )
+            })
+            .collect();
+        let srt = SubRip {
+            subtitles: positioned,
+        };
+        fs::write(&path, crate::vtt::render(&srt)).unwrap();
+        let read = load_sub(&path, &opts).unwrap();
+        let expected: Vec<_> = srt
+            .subtitles
+            .iter()
+            .map(|s| s.text[0].replace("{\\an2} ", ""))
+            .collect();
+        let read: Vec<_> = read.subtitles.iter().map(|s| s.text[0].clone()).collect();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn test_vtt_output() {
+        let srt = SubRip {
+            subtitles: vec![
+                cue(1, 1000, 2000, "bottom"),
+                cue(
+                    2,
+                    1500,
+                    2500,
+                    "{\\an9} <font color=\"#fbf1c7\">top {\\pos(1,1)}right</font>",
+                ),
+            ],
+        };
+        let vtt = crate::vtt::render(&srt);
+
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n\
+             STYLE\n::cue(.color_fbf1c7) { color: #fbf1c7; }\n\n\
+             00:00:01.000 --> 00:00:02.000\nbottom\n\n\
+             00:00:01.500 --> 00:00:02.500 line:0 align:right\n<c.color_fbf1c7>top right</c>\n"
+        );
+    }
 }
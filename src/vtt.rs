@@ -0,0 +1,185 @@
+use clap::ValueEnum;
+use log::warn;
+use regex::Regex;
+use std::{collections::BTreeSet, sync::LazyLock, time::Duration};
+use subtp::{
+    srt::{SrtSubtitle, SubRip},
+    vtt::{
+        Alignment, CueSettings, Line, LineAlignment, Percentage, VttBlock, VttCue, VttStyle,
+        VttTimings, WebVtt,
+    },
+};
+
+use crate::{split_position, SubPosition};
+
+static FONT_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<font(?P<attrs>[^>]*)>"#).unwrap());
+
+static COLOR_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"color="?(?P<color>[^"\s>]+)"?"#).unwrap());
+
+static SIZE_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"size="?(?P<size>\d+)"?"#).unwrap());
+
+/// Override blocks of ASS inputs, such as `{\pos(10,10)}`, which VTT has no equivalent of
+static OVERRIDE_BLOCK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\\[^}]*\}").unwrap());
+
+/// Render `srt` as WebVTT. Position tags become cue settings and `<font>` tags become classes
+/// styled by a `STYLE` block, other override blocks are removed with a warning.
+pub fn render(srt: &SubRip) -> String {
+    let mut colors = BTreeSet::new();
+    let mut sizes = BTreeSet::new();
+    let mut stripped = 0;
+
+    let cues: Vec<VttBlock> = srt
+        .subtitles
+        .iter()
+        .map(|cue| {
+            let position = cue.text.first().and_then(|txt| split_position(txt).0);
+            let payload = cue
+                .text
+                .iter()
+                .map(|txt| {
+                    let txt = split_position(txt).1;
+                    if OVERRIDE_BLOCK.is_match(txt) {
+                        stripped += 1;
+                    }
+                    let txt = OVERRIDE_BLOCK.replace_all(txt, "");
+                    let txt = FONT_TAG.replace_all(&txt, |c: &regex::Captures| {
+                        let mut tag = "<c".to_owned();
+                        if let Some(color) = COLOR_ATTR.captures(&c["attrs"]) {
+                            colors.insert(color["color"].to_owned());
+                            tag.push('.');
+                            tag.push_str(&class(&color["color"]));
+                        }
+                        if let Some(size) = SIZE_ATTR.captures(&c["attrs"]) {
+                            sizes.insert(size["size"].to_owned());
+                            tag.push_str(".size_");
+                            tag.push_str(&size["size"]);
+                        }
+                        tag + ">"
+                    });
+                    txt.replace("</font>", "</c>")
+                })
+                .collect();
+
+            VttCue {
+                timings: timings(cue),
+                settings: position.and_then(settings),
+                payload,
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+
+    if stripped > 0 {
+        warn!(
+            reason = "unrepresentable_style";
+            "VTT output can not represent override blocks such as {{\\pos(..)}}, removed them from {} lines", stripped
+        );
+    }
+
+    let mut blocks = Vec::new();
+    if !colors.is_empty() || !sizes.is_empty() {
+        let style: Vec<String> = colors
+            .iter()
+            .map(|color| format!("::cue(.{}) {{ color: {}; }}", class(color), color))
+            .chain(
+                sizes
+                    .iter()
+                    .map(|size| format!("::cue(.size_{}) {{ font-size: {}px; }}", size, size)),
+            )
+            .collect();
+        blocks.push(
+            VttStyle {
+                style: style.join("\n"),
+            }
+            .into(),
+        );
+    }
+    blocks.extend(cues);
+
+    WebVtt {
+        blocks,
+        ..Default::default()
+    }
+    .render()
+}
+
+/// The name of the class giving text `color`, e.g. `color_fbf1c7` for `#fbf1c7`
+fn class(color: &str) -> String {
+    let name: String = color
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    format!("color_{}", name)
+}
+
+fn timings(cue: &SrtSubtitle) -> VttTimings {
+    VttTimings {
+        start: Into::<Duration>::into(cue.start).into(),
+        end: Into::<Duration>::into(cue.end).into(),
+    }
+}
+
+/// Cue settings placing a cue at `position`, `None` for the default bottom center
+fn settings(position: SubPosition) -> Option<CueSettings> {
+    // The variants are in numpad order, bottom left to top right
+    let line = match position as usize / 3 {
+        0 => None,
+        1 => Some(Line::Percentage(
+            Percentage { value: 50.0 },
+            Some(LineAlignment::Center),
+        )),
+        _ => Some(Line::LineNumber(0, None)),
+    };
+    let align = match position as usize % 3 {
+        0 => Some(Alignment::Left),
+        1 => None,
+        _ => Some(Alignment::Right),
+    };
+
+    (line.is_some() || align.is_some()).then(|| CueSettings {
+        line,
+        align,
+        ..Default::default()
+    })
+}
+
+/// The one of the nine `SubPosition`s closest to where a cue with `settings` is shown, the
+/// reverse of `settings`, or `None` for the default bottom center. Left to right text is assumed.
+///
+/// | Setting                               | Placement                                        |
+/// |---------------------------------------|--------------------------------------------------|
+/// | no `line`, or `line:-N`               | bottom                                           |
+/// | `line:N`, counting from 0 at the top  | top                                              |
+/// | `line:P%`                             | top below 33.3%, bottom above 66.7%, else middle |
+/// | `position:P%`                         | left below 33.3%, right above 66.7%, else center |
+/// | `align:left` or `align:start`         | left, if there is no `position`                  |
+/// | `align:right` or `align:end`          | right, if there is no `position`                 |
+/// | `align:center`, or neither            | center                                           |
+pub fn cue_position(settings: &CueSettings) -> Option<SubPosition> {
+    // Thirds of the screen, from the top or the left
+    let third = |percent: f32| match percent {
+        p if p < 100.0 / 3.0 => 0,
+        p if p > 200.0 / 3.0 => 2,
+        _ => 1,
+    };
+    let row = match &settings.line {
+        None => 0,
+        Some(Line::LineNumber(n, _)) if *n < 0 => 0,
+        Some(Line::LineNumber(_, _)) => 2,
+        Some(Line::Percentage(p, _)) => 2 - third(p.value),
+    };
+    let column = match (&settings.position, &settings.align) {
+        (Some(position), _) => third(position.value.value),
+        (None, Some(Alignment::Left | Alignment::Start)) => 0,
+        (None, Some(Alignment::Right | Alignment::End)) => 2,
+        (None, Some(Alignment::Center) | None) => 1,
+    };
+
+    // The variants are in numpad order, bottom left to top right
+    let position = SubPosition::value_variants()[row * 3 + column];
+    (position != SubPosition::BottomCenter).then_some(position)
+}
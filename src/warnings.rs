@@ -0,0 +1,75 @@
+use anyhow::Result;
+use log::{kv::Key, Level, Log, Metadata, Record};
+use serde::Serialize;
+use simple_logger::SimpleLogger;
+use std::{fs::File, path::Path, sync::Mutex};
+
+/// Warnings logged so far, only collected if enabled in `init_logging`
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+/// A logged warning. Warnings can attach a machine-stable `reason` code and the `path` of the
+/// file they concern as key-values, e.g. `warn!(reason = "span_mismatch", path:% = p.display(); "...")`.
+#[derive(Serialize)]
+pub struct Warning {
+    /// Machine-stable code of what went wrong, `unspecified` if the warning has none
+    pub reason: String,
+    /// The file the warning concerns, if any
+    pub path: Option<String>,
+    /// The logged message
+    pub message: String,
+}
+
+impl Warning {
+    /// Read the key-values of a logged warning
+    pub fn from_record(record: &Record) -> Self {
+        let kvs = record.key_values();
+        Warning {
+            reason: kvs
+                .get(Key::from("reason"))
+                .map_or("unspecified".to_owned(), |v| v.to_string()),
+            path: kvs.get(Key::from("path")).map(|v| v.to_string()),
+            message: record.args().to_string(),
+        }
+    }
+}
+
+/// Logs to stderr like `simple_logger`, while collecting warnings into `WARNINGS`
+struct RecordingLogger {
+    inner: SimpleLogger,
+}
+
+impl Log for RecordingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Warn {
+            WARNINGS.lock().unwrap().push(Warning::from_record(record));
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Set up logging at `level`, additionally collecting all warnings if `record_warnings` is set
+pub fn init_logging(level: Level, record_warnings: bool) -> Result<()> {
+    if !record_warnings {
+        return Ok(simple_logger::init_with_level(level)?);
+    }
+
+    let inner = SimpleLogger::new().with_level(level.to_level_filter());
+    log::set_max_level(level.max(Level::Warn).to_level_filter());
+    log::set_boxed_logger(Box::new(RecordingLogger { inner }))?;
+    Ok(())
+}
+
+/// Write all collected warnings to `path` as a JSON array
+pub fn write_warnings(path: &Path) -> Result<()> {
+    let warnings = WARNINGS.lock().unwrap();
+    serde_json::to_writer_pretty(File::create(path)?, &*warnings)?;
+    Ok(())
+}
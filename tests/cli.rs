@@ -0,0 +1,164 @@
+use std::{fs, path::Path, process::Command};
+
+const EN: &str = "1\n00:00:01,000 --> 00:00:02,000\nHello\n";
+const JA: &str = "1\n00:00:01,000 --> 00:00:02,000\nこんにちは\n";
+
+fn recursive(dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_submerger"))
+        .args(["recursive", "en", "ja"])
+        .arg(dir)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_skip_existing() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("movie.en.srt"), EN).unwrap();
+    fs::write(dir.path().join("movie.ja.srt"), JA).unwrap();
+    let out = dir.path().join("movie.srt");
+    fs::write(&out, "edited by hand").unwrap();
+
+    recursive(dir.path(), &["--skip-existing"]);
+    assert_eq!(fs::read(&out).unwrap(), b"edited by hand");
+
+    // The pair is otherwise merged over the existing output
+    recursive(dir.path(), &[]);
+    let merged = fs::read_to_string(&out).unwrap();
+    assert!(merged.contains("Hello") && merged.contains("こんにちは"));
+}
+
+#[test]
+fn test_single_ok_is_styled() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("movie.en.srt"), EN).unwrap();
+
+    recursive(dir.path(), &["--single-ok", "--sub1-bold"]);
+    // The track without a pair is written alone, with its styling
+    let single = fs::read_to_string(dir.path().join("movie.srt")).unwrap();
+    assert!(single.contains("<b>Hello</b>"));
+}
+
+#[test]
+fn test_detected_language_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let en = "1\n00:00:01,000 --> 00:00:02,000\nWhat are you doing with that? I know the way.\n";
+    fs::write(dir.path().join("movie.srt"), en).unwrap();
+    fs::write(dir.path().join("movie.ja.srt"), JA).unwrap();
+
+    recursive(dir.path(), &["--detect-lang"]);
+    // Named `movie.srt` the output would be the detected input
+    assert_eq!(
+        fs::read_to_string(dir.path().join("movie.srt")).unwrap(),
+        en
+    );
+    let merged = fs::read_to_string(dir.path().join("movie.en-ja.srt")).unwrap();
+    assert!(merged.contains("I know the way") && merged.contains("こんにちは"));
+}
+
+#[test]
+fn test_streaming_warns_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let early: String = (1..=5)
+        .map(|i| format!("{i}\n00:00:0{i},000 --> 00:00:0{i},500\nearly {i}\n\n"))
+        .collect();
+    let (en, ja) = (
+        dir.path().join("movie.en.srt"),
+        dir.path().join("movie.ja.srt"),
+    );
+    fs::write(&en, early).unwrap();
+    fs::write(&ja, JA).unwrap();
+    let warnings = dir.path().join("warnings.json");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_submerger"))
+        .args([
+            "simple",
+            "--streaming",
+            "--sub1-offset",
+            "-30",
+            "--warnings-json",
+        ])
+        .arg(&warnings)
+        .args([&en, &ja, &dir.path().join("movie.srt")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let warnings = fs::read_to_string(warnings).unwrap();
+    assert_eq!(warnings.matches("negative_timestamps").count(), 1);
+}
+
+#[test]
+fn test_preview_before_positionals() {
+    let dir = tempfile::tempdir().unwrap();
+    let (en, ja) = (
+        dir.path().join("movie.en.srt"),
+        dir.path().join("movie.ja.srt"),
+    );
+    fs::write(&en, EN).unwrap();
+    fs::write(&ja, JA).unwrap();
+    let out = dir.path().join("movie.srt");
+
+    for preview in ["--preview", "--preview=1"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_submerger"))
+            .args(["simple", preview])
+            .args([&en, &ja, &out])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{preview}");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Hello"));
+        assert!(!out.exists());
+    }
+}
+
+#[test]
+fn test_base_is_kept_verbatim() {
+    let dir = tempfile::tempdir().unwrap();
+    let base =
+        "7\n00:00:01,000 --> 00:00:02,000\n{\\an8}<font color=\"#abcdef\">Hand  styled</font>\n\n\
+                9\n00:00:05,000 --> 00:00:06,000\n<i>Second</i>\n";
+    let (en, ja) = (
+        dir.path().join("movie.en.srt"),
+        dir.path().join("movie.ja.srt"),
+    );
+    fs::write(&en, base).unwrap();
+    fs::write(&ja, "1\n00:00:03,000 --> 00:00:04,000\nこんにちは\n").unwrap();
+    let out = dir.path().join("movie.srt");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_submerger"))
+        .args(["simple", "--base", "--sub1-bold", "--sub1-offset", "10"])
+        .args([&en, &ja, &out])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let merged = fs::read_to_string(&out).unwrap();
+    let cues: Vec<&str> = merged.trim_end().split("\n\n").collect();
+    assert_eq!(
+        cues[0],
+        "1\n00:00:01,000 --> 00:00:02,000\n{\\an8}<font color=\"#abcdef\">Hand  styled</font>"
+    );
+    assert!(cues[1].starts_with("2\n00:00:03,000 --> 00:00:04,000\n"));
+    assert_eq!(cues[2], "3\n00:00:05,000 --> 00:00:06,000\n<i>Second</i>");
+}
+
+#[test]
+fn test_empty_input_when_streaming() {
+    let dir = tempfile::tempdir().unwrap();
+    let (en, ja) = (
+        dir.path().join("movie.en.srt"),
+        dir.path().join("movie.ja.srt"),
+    );
+    fs::write(&en, EN).unwrap();
+    fs::write(&ja, "").unwrap();
+    let out = dir.path().join("movie.srt");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_submerger"))
+        .args(["simple", "--streaming"])
+        .args([&en, &ja, &out])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no subtitles"));
+    assert!(!out.exists());
+}